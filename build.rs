@@ -3,6 +3,7 @@ extern crate semver;
 
 use semver::{Version, VersionReq};
 use semver::ParseError::IncorrectParse;
+use std::env;
 use std::process::Command;
 
 /// Get the output from running `llvm-config` with the given argument.
@@ -77,13 +78,24 @@ fn main() {
     	.success()
  	);
    
-  // Check for LLVM 3.6 or greater.
-  let minimum_llvm_version = VersionReq::parse(">=3.6").unwrap();
+  // Compile the runtime library embedded by `RuntimeLibrary::builtin`.
+  let runtime_bc = format!("{}/runtime.bc", env::var("OUT_DIR").unwrap());
+  assert!(
+    Command::new("clang++")
+      .args(&["runtime/runtime.cc", "-c", "-emit-llvm", "-O2", "-o", &runtime_bc])
+      .status()
+      .unwrap()
+      .success()
+  );
+
+  // Check for LLVM 19 or greater, for debug records, opaque pointers and the pass builder
+  // of the new pass manager. llvm-sys checks for the version it was generated for.
+  let minimum_llvm_version = VersionReq::parse(">=19.1").unwrap();
   let version = llvm_version();
   if minimum_llvm_version.matches(&version) {
     println!("Found LLVM version {}", version);
   } else {
-    panic!("LLVM version 3.6 or higher is required. (Found {})", version);
+    panic!("LLVM version 19.1 or higher is required. (Found {})", version);
   };
 
   // Parse library linking flags from llvm-config.
@@ -0,0 +1,46 @@
+// Runtime helpers for generated code.
+//
+// build.rs compiles this file into bitcode which is embedded in the crate and linked
+// into generated modules by `RuntimeLibrary::link_into`. Only the helpers a module
+// calls are linked, with internal linkage so the optimizer can inline them.
+//
+// Helpers must be `extern "C"` so generated code can declare them by their plain names.
+
+#include <stddef.h> // size_t
+#include <stdint.h> // Sized Types
+#include <string.h> // memcmp
+
+extern "C" {
+
+// 64-bit FNV-1a hash of `len` bytes at `data`.
+uint64_t rt_hash_bytes(const uint8_t* data, size_t len) {
+  uint64_t hash = 0xcbf29ce484222325ULL;
+  for (size_t i = 0; i < len; i++) {
+    hash ^= data[i];
+    hash *= 0x100000001b3ULL;
+  }
+  return hash;
+}
+
+// Combine two hashes, e.g. of the columns of a composite key.
+uint64_t rt_hash_combine(uint64_t seed, uint64_t hash) {
+  return seed ^ (hash + 0x9e3779b97f4a7c15ULL + (seed << 6) + (seed >> 2));
+}
+
+// Compare two strings given as pointer and length, returning a negative number, zero
+// or a positive number like `memcmp`.
+int32_t rt_str_cmp(const char* a, size_t a_len, const char* b, size_t b_len) {
+  size_t len = a_len < b_len ? a_len : b_len;
+  int cmp = memcmp(a, b, len);
+  if (cmp != 0) {
+    return cmp;
+  }
+  return a_len < b_len ? -1 : (a_len > b_len ? 1 : 0);
+}
+
+// Returns true if two strings given as pointer and length are equal.
+bool rt_str_eq(const char* a, size_t a_len, const char* b, size_t b_len) {
+  return a_len == b_len && memcmp(a, b, a_len) == 0;
+}
+
+}
@@ -1,11 +1,16 @@
 //! Analysis Module
 
+use std::collections::HashMap;
+use std::fmt;
 use std::mem;
-use libc::{c_char, c_int};
+use libc::{c_char, c_int, c_void};
 use llvm_sys::analysis::{self, LLVMVerifierFailureAction};
+use llvm_sys::core;
 use llvm_sys::prelude::{LLVMModuleRef, LLVMValueRef};
 
-use value::Function;
+use block::BasicBlock;
+use util::chars;
+use value::{Function, Instruction, ValueIter, ValueRef};
 
 // Extended APIs, offering more APIs than LLVM C API does.
 extern "C" {
@@ -13,6 +18,120 @@ extern "C" {
                              Action: LLVMVerifierFailureAction,
                              OutMessage: *mut *mut c_char)
                              -> c_int;
+  pub fn LLVMLintFunction(f: LLVMValueRef) -> *mut c_char;
+}
+
+/// A problem found in IR by `Verifier` or `Lint`.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+  /// What is wrong, e.g. `Undefined behavior: Division by zero`.
+  pub message: String,
+  /// The name of the function at fault, if known.
+  pub function: Option<String>,
+  /// The name of the basic block at fault, or its position like `#2` if it has no name.
+  pub block: Option<String>,
+  /// The instruction at fault, if it could be found.
+  pub instruction: Option<Instruction>,
+  /// The values printed along with the message, usually the instructions involved.
+  pub values: Vec<String>,
+}
+
+impl Diagnostic {
+  fn new(message: &str) -> Diagnostic {
+    Diagnostic {
+      message: message.to_string(),
+      function: None,
+      block: None,
+      instruction: None,
+      values: Vec::new(),
+    }
+  }
+}
+
+impl fmt::Display for Diagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    try!(f.write_str(&self.message));
+    match (&self.function, &self.block) {
+      (&Some(ref func), &Some(ref block)) => {
+        try!(write!(f, "\n  in block {} of function {}", block, func))
+      }
+      (&Some(ref func), &None) => try!(write!(f, "\n  in function {}", func)),
+      _ => {}
+    }
+    for value in &self.values {
+      try!(write!(f, "\n    {}", value));
+    }
+    Ok(())
+  }
+}
+
+// Where each instruction of some functions is, by its printed form.
+struct InstructionMap {
+  locations: HashMap<String, (String, String, Instruction)>,
+}
+
+impl InstructionMap {
+  fn new<'a, I: IntoIterator<Item = &'a Function>>(funcs: I) -> InstructionMap {
+    let mut locations = HashMap::new();
+    for func in funcs {
+      let func_name = func.name().unwrap_or("").to_string();
+      for (i, bb) in func.blocks().into_iter().enumerate() {
+        let block = bb.name().map_or_else(|| format!("#{}", i), |name| name.to_string());
+        for inst in bb.instructions() {
+          // Identical instructions are told apart by the first one only.
+          locations.entry(inst.to_string().trim().to_string())
+            .or_insert_with(|| (func_name.clone(), block.clone(), inst));
+        }
+      }
+    }
+    InstructionMap { locations: locations }
+  }
+
+  // Attribute `diag` to the first of its values which is an instruction.
+  fn locate(&self, diag: &mut Diagnostic) {
+    let found = diag.values.iter().filter_map(|v| self.locations.get(v)).next();
+    if let Some(&(ref func, ref block, inst)) = found {
+      diag.function = Some(func.clone());
+      diag.block = Some(block.clone());
+      diag.instruction = Some(inst);
+    }
+  }
+}
+
+// Split the output of the verifier or the lint pass into diagnostics. Each message starts
+// with a capital letter, and is followed by the values it is about, one per line.
+fn parse_diagnostics(text: &str, map: &InstructionMap) -> Vec<Diagnostic> {
+  let mut diags: Vec<Diagnostic> = Vec::new();
+
+  for line in text.lines().filter(|l| !l.trim().is_empty()) {
+    let starts_message = line.chars().next().map_or(false, |c| c.is_uppercase());
+    if starts_message || diags.is_empty() {
+      diags.push(Diagnostic::new(line.trim()));
+    } else if let Some(diag) = diags.last_mut() {
+      diag.values.push(line.trim().to_string());
+    }
+  }
+
+  for diag in &mut diags {
+    map.locate(diag);
+  }
+  diags
+}
+
+// Parse the diagnostics of a single function.
+fn func_diagnostics(func: &Function, text: &str) -> Vec<Diagnostic> {
+  let mut diags = parse_diagnostics(text, &InstructionMap::new(Some(func)));
+  let name = func.name().unwrap_or("");
+  for diag in &mut diags {
+    diag.function = Some(name.to_string());
+  }
+  diags
+}
+
+fn defined_functions(module: LLVMModuleRef) -> Vec<Function> {
+  ValueIter::new(unsafe { core::LLVMGetFirstFunction(module) }, core::LLVMGetNextFunction)
+    .filter(|f: &Function| unsafe { core::LLVMIsDeclaration(f.0) == 0 })
+    .collect()
 }
 
 /// IR Verifier
@@ -43,4 +162,453 @@ impl Verifier {
       llvm_ret!(res, (), error)
     }
   }
+
+  /// Verifies that a module is valid, returning what is wrong with it, or nothing if it
+  /// is valid.
+  ///
+  /// The problems found in functions come with the function, block and instruction at
+  /// fault.
+  pub fn diagnose_module(module: LLVMModuleRef) -> Vec<Diagnostic> {
+    let text = match Verifier::verify_module(module) {
+      Ok(()) => return Vec::new(),
+      Err(text) => text,
+    };
+
+    let funcs = defined_functions(module);
+    let diags = funcs.iter().flat_map(Verifier::diagnose_func).collect::<Vec<_>>();
+    if !diags.is_empty() {
+      return diags;
+    }
+    // What is wrong lies outside of functions, e.g. in a global.
+    parse_diagnostics(&text, &InstructionMap::new(&funcs))
+  }
+
+  /// Verifies that a single function is valid, returning what is wrong with it, or
+  /// nothing if it is valid.
+  pub fn diagnose_func(func: &Function) -> Vec<Diagnostic> {
+    match Verifier::verify_func(func) {
+      Ok(()) => Vec::new(),
+      Err(text) => func_diagnostics(func, &text),
+    }
+  }
+}
+
+/// Checks IR for code which is valid, but most likely wrong, such as undefined behavior
+/// like misaligned loads, or division by a constant zero.
+///
+/// The IR must be valid, so it should be verified first.
+pub struct Lint;
+
+impl Lint {
+  /// Checks all the functions defined in a module.
+  pub fn check_module(module: LLVMModuleRef) -> Vec<Diagnostic> {
+    defined_functions(module).iter().flat_map(Lint::check_func).collect()
+  }
+
+  /// Checks a single function, which must have a body.
+  pub fn check_func(func: &Function) -> Vec<Diagnostic> {
+    if unsafe { core::LLVMIsDeclaration(func.0) } != 0 {
+      return Vec::new();
+    }
+
+    let text = unsafe {
+      let out = LLVMLintFunction(func.0);
+      let text = chars::to_str(out).to_string();
+      ::libc::free(out as *mut c_void);
+      text
+    };
+    func_diagnostics(func, &text)
+  }
+}
+
+/// The control flow graph of a function, i.e. which basic blocks may branch to which.
+pub struct Cfg {
+  blocks: Vec<BasicBlock>,
+  index: HashMap<BasicBlock, usize>,
+  succs: Vec<Vec<usize>>,
+  preds: Vec<Vec<usize>>,
+}
+
+impl Cfg {
+  pub fn new(func: &Function) -> Cfg {
+    let blocks = func.blocks();
+    let index = blocks.iter().enumerate().map(|(i, &bb)| (bb, i)).collect::<HashMap<_, _>>();
+
+    let succs = blocks.iter()
+      .map(|bb| bb.successors().iter().filter_map(|succ| index.get(succ).cloned()).collect())
+      .collect::<Vec<Vec<usize>>>();
+    let mut preds = vec![Vec::new(); blocks.len()];
+    for (i, bb_succs) in succs.iter().enumerate() {
+      for &succ in bb_succs {
+        preds[succ].push(i);
+      }
+    }
+
+    Cfg {
+      blocks: blocks,
+      index: index,
+      succs: succs,
+      preds: preds,
+    }
+  }
+
+  /// Returns the basic blocks of the function, the entry block first.
+  pub fn blocks(&self) -> &[BasicBlock] {
+    &self.blocks
+  }
+
+  /// Returns the entry block, or `None` if the function has no body.
+  pub fn entry(&self) -> Option<BasicBlock> {
+    self.blocks.first().cloned()
+  }
+
+  /// Returns the blocks `bb` may branch to.
+  pub fn successors(&self, bb: BasicBlock) -> Vec<BasicBlock> {
+    self.map(&self.succs, bb)
+  }
+
+  /// Returns the blocks which may branch to `bb`.
+  pub fn predecessors(&self, bb: BasicBlock) -> Vec<BasicBlock> {
+    self.map(&self.preds, bb)
+  }
+
+  /// Returns the successors of each block.
+  pub fn successor_map(&self) -> HashMap<BasicBlock, Vec<BasicBlock>> {
+    self.blocks.iter().map(|&bb| (bb, self.successors(bb))).collect()
+  }
+
+  /// Returns the predecessors of each block.
+  pub fn predecessor_map(&self) -> HashMap<BasicBlock, Vec<BasicBlock>> {
+    self.blocks.iter().map(|&bb| (bb, self.predecessors(bb))).collect()
+  }
+
+  /// Returns the blocks reachable from the entry block in reverse post-order, where a
+  /// block comes before its successors unless it is reached through a back edge.
+  pub fn reverse_post_order(&self) -> Vec<BasicBlock> {
+    if self.blocks.is_empty() {
+      return Vec::new();
+    }
+    reverse_post_order(0, &self.succs).into_iter().map(|i| self.blocks[i]).collect()
+  }
+
+  fn map(&self, edges: &[Vec<usize>], bb: BasicBlock) -> Vec<BasicBlock> {
+    self.index.get(&bb).map_or_else(Vec::new, |&i| edges[i].iter().map(|&j| self.blocks[j]).collect())
+  }
+}
+
+// Returns the nodes reachable from `root` in reverse post-order.
+fn reverse_post_order(root: usize, succs: &[Vec<usize>]) -> Vec<usize> {
+  let mut visited = vec![false; succs.len()];
+  let mut order = Vec::with_capacity(succs.len());
+  // Each node on the stack is paired with the index of its next successor to visit.
+  let mut stack = vec![(root, 0)];
+  visited[root] = true;
+
+  while let Some(&mut (node, ref mut next)) = stack.last_mut() {
+    if let Some(&succ) = succs[node].get(*next) {
+      *next += 1;
+      if !visited[succ] {
+        visited[succ] = true;
+        stack.push((succ, 0));
+      }
+    } else {
+      order.push(node);
+      stack.pop();
+    }
+  }
+
+  order.reverse();
+  order
+}
+
+// Returns the immediate dominator of each node reachable from `root`, with the algorithm
+// of Cooper, Harvey and Kennedy. The root is its own immediate dominator.
+fn immediate_dominators(root: usize, succs: &[Vec<usize>], preds: &[Vec<usize>]) -> Vec<Option<usize>> {
+  let rpo = reverse_post_order(root, succs);
+  let mut order = vec![usize::max_value(); succs.len()];
+  for (i, &node) in rpo.iter().enumerate() {
+    order[node] = i;
+  }
+
+  let mut idom = vec![None; succs.len()];
+  idom[root] = Some(root);
+
+  let intersect = |idom: &[Option<usize>], mut a: usize, mut b: usize| {
+    while a != b {
+      while order[a] > order[b] {
+        a = idom[a].unwrap();
+      }
+      while order[b] > order[a] {
+        b = idom[b].unwrap();
+      }
+    }
+    a
+  };
+
+  let mut changed = true;
+  while changed {
+    changed = false;
+    for &node in &rpo[1..] {
+      let mut new_idom = None;
+      for &pred in preds[node].iter().filter(|&&p| idom[p].is_some()) {
+        new_idom = Some(match new_idom {
+          Some(other) => intersect(&idom, pred, other),
+          None => pred,
+        });
+      }
+      if new_idom.is_some() && idom[node] != new_idom {
+        idom[node] = new_idom;
+        changed = true;
+      }
+    }
+  }
+  idom
+}
+
+/// The dominator tree or post-dominator tree of a function.
+///
+/// A block dominates another if every path from the entry block to the other block goes
+/// through it, and post-dominates it if every path from it to a return goes through it.
+/// Every block dominates itself. Blocks unreachable from the entry block, or which never
+/// return for a post-dominator tree, are dominated by no other block.
+pub struct DominatorTree {
+  blocks: Vec<BasicBlock>,
+  index: HashMap<BasicBlock, usize>,
+  idom: Vec<Option<usize>>,
+  // The interval of each node in a depth-first walk of the tree. A node dominates another
+  // iff its interval contains the other one.
+  interval: Vec<Option<(usize, usize)>>,
+  post: bool,
+}
+
+impl DominatorTree {
+  /// Compute the dominator tree of the function of `cfg`.
+  pub fn new(cfg: &Cfg) -> DominatorTree {
+    let mut idom = Vec::new();
+    if !cfg.blocks.is_empty() {
+      idom = immediate_dominators(0, &cfg.succs, &cfg.preds);
+      idom[0] = None;
+    }
+    DominatorTree::from_idoms(cfg, idom, false)
+  }
+
+  /// Compute the post-dominator tree of the function of `cfg`.
+  pub fn new_post(cfg: &Cfg) -> DominatorTree {
+    // The edges are reversed, and a virtual root is added which branches to the blocks
+    // without successors, i.e. those returning or unreachable.
+    let n = cfg.blocks.len();
+    let mut succs = cfg.preds.clone();
+    let mut preds = cfg.succs.clone();
+    let exits = (0..n).filter(|&i| cfg.succs[i].is_empty()).collect::<Vec<_>>();
+    for &exit in &exits {
+      preds[exit].push(n);
+    }
+    succs.push(exits);
+    preds.push(Vec::new());
+
+    let mut idom = immediate_dominators(n, &succs, &preds);
+    idom.pop();
+    for d in &mut idom {
+      if *d == Some(n) {
+        *d = None;
+      }
+    }
+    DominatorTree::from_idoms(cfg, idom, true)
+  }
+
+  fn from_idoms(cfg: &Cfg, idom: Vec<Option<usize>>, post: bool) -> DominatorTree {
+    let n = idom.len();
+    let mut children = vec![Vec::new(); n];
+    let mut roots = Vec::new();
+    for (node, d) in idom.iter().enumerate() {
+      match *d {
+        Some(parent) => children[parent].push(node),
+        None => roots.push(node),
+      }
+    }
+
+    let mut interval = vec![None; n];
+    let mut counter = 0;
+    for root in roots {
+      // Each node on the stack is paired with the index of its next child to visit, and
+      // when it was entered.
+      let mut stack = vec![(root, 0, counter)];
+      counter += 1;
+      while let Some(&mut (node, ref mut next, enter)) = stack.last_mut() {
+        if let Some(&child) = children[node].get(*next) {
+          *next += 1;
+          stack.push((child, 0, counter));
+          counter += 1;
+        } else {
+          interval[node] = Some((enter, counter));
+          stack.pop();
+        }
+      }
+    }
+
+    DominatorTree {
+      blocks: cfg.blocks.clone(),
+      index: cfg.index.clone(),
+      idom: idom,
+      interval: interval,
+      post: post,
+    }
+  }
+
+  /// Returns true if this is a post-dominator tree.
+  pub fn is_post_dominator(&self) -> bool {
+    self.post
+  }
+
+  /// Returns the closest block strictly dominating `bb`, i.e. its parent in the tree, or
+  /// `None` if it has none.
+  pub fn idom(&self, bb: BasicBlock) -> Option<BasicBlock> {
+    self.index.get(&bb).and_then(|&i| self.idom[i]).map(|d| self.blocks[d])
+  }
+
+  /// Returns the blocks whose immediate dominator is `bb`.
+  pub fn children(&self, bb: BasicBlock) -> Vec<BasicBlock> {
+    let i = self.index.get(&bb).cloned();
+    (0..self.blocks.len())
+      .filter(|&j| i.is_some() && self.idom[j] == i)
+      .map(|j| self.blocks[j])
+      .collect()
+  }
+
+  /// Returns true if `a` dominates `b`, or post-dominates it for a post-dominator tree.
+  pub fn dominates(&self, a: BasicBlock, b: BasicBlock) -> bool {
+    if a == b {
+      return true;
+    }
+    let interval = |bb| self.index.get(&bb).and_then(|&i| self.interval[i]);
+    match (interval(a), interval(b)) {
+      (Some((a_in, a_out)), Some((b_in, b_out))) => a_in <= b_in && b_out <= a_out,
+      _ => false,
+    }
+  }
+
+  /// Returns true if `a` dominates `b` and isn't `b`.
+  pub fn strictly_dominates(&self, a: BasicBlock, b: BasicBlock) -> bool {
+    a != b && self.dominates(a, b)
+  }
+}
+
+/// A natural loop, i.e. the blocks of a cycle entered only through its header.
+#[derive(Clone, Debug)]
+pub struct Loop {
+  header: BasicBlock,
+  latches: Vec<BasicBlock>,
+  blocks: Vec<BasicBlock>,
+  exits: Vec<BasicBlock>,
+  depth: usize,
+}
+
+impl Loop {
+  /// Returns the block every iteration starts with, which dominates the whole loop.
+  pub fn header(&self) -> BasicBlock {
+    self.header
+  }
+
+  /// Returns the blocks in the loop branching back to the header.
+  pub fn latches(&self) -> &[BasicBlock] {
+    &self.latches
+  }
+
+  /// Returns the blocks in the loop in reverse post-order, the header first.
+  pub fn blocks(&self) -> &[BasicBlock] {
+    &self.blocks
+  }
+
+  /// Returns the blocks outside the loop which blocks in the loop may branch to.
+  pub fn exits(&self) -> &[BasicBlock] {
+    &self.exits
+  }
+
+  /// Returns how many loops this loop is nested in, plus 1.
+  pub fn depth(&self) -> usize {
+    self.depth
+  }
+
+  pub fn contains(&self, bb: BasicBlock) -> bool {
+    self.blocks.contains(&bb)
+  }
+}
+
+/// The natural loops of a function.
+pub struct LoopInfo {
+  loops: Vec<Loop>,
+}
+
+impl LoopInfo {
+  /// Find the loops of the function of `cfg`, whose dominator tree is `dom`.
+  pub fn new(cfg: &Cfg, dom: &DominatorTree) -> LoopInfo {
+    assert!(!dom.is_post_dominator(), "loops are found with a dominator tree");
+    let rpo = cfg.reverse_post_order();
+
+    let mut loops = Vec::new();
+    for &header in &rpo {
+      // A back edge branches to a block dominating its source.
+      let latches = cfg.predecessors(header)
+        .into_iter()
+        .filter(|&pred| dom.dominates(header, pred))
+        .collect::<Vec<_>>();
+      if latches.is_empty() {
+        continue;
+      }
+
+      // The loop is made of the blocks reaching a latch without going through the header.
+      let mut in_loop = vec![header];
+      let mut work = latches.clone();
+      while let Some(bb) = work.pop() {
+        if !in_loop.contains(&bb) {
+          in_loop.push(bb);
+          work.extend(cfg.predecessors(bb).into_iter().filter(|&p| dom.dominates(header, p)));
+        }
+      }
+
+      let blocks = rpo.iter().cloned().filter(|bb| in_loop.contains(bb)).collect::<Vec<_>>();
+      let mut exits = Vec::new();
+      for &bb in &blocks {
+        for succ in cfg.successors(bb) {
+          if !in_loop.contains(&succ) && !exits.contains(&succ) {
+            exits.push(succ);
+          }
+        }
+      }
+
+      loops.push(Loop {
+        header: header,
+        latches: latches,
+        blocks: blocks,
+        exits: exits,
+        depth: 1,
+      });
+    }
+
+    for i in 0..loops.len() {
+      let depth = loops.iter().filter(|l| l.contains(loops[i].header)).count();
+      loops[i].depth = depth;
+    }
+    LoopInfo { loops: loops }
+  }
+
+  /// Returns the loops, outer loops before the loops they contain.
+  pub fn loops(&self) -> &[Loop] {
+    &self.loops
+  }
+
+  /// Returns the innermost loop containing `bb`, or `None` if it is in no loop.
+  pub fn loop_for(&self, bb: BasicBlock) -> Option<&Loop> {
+    self.loops.iter().filter(|l| l.contains(bb)).max_by_key(|l| l.depth)
+  }
+
+  /// Returns the number of loops containing `bb`.
+  pub fn depth(&self, bb: BasicBlock) -> usize {
+    self.loop_for(bb).map_or(0, |l| l.depth)
+  }
+
+  /// Returns true if `bb` is the header of a loop.
+  pub fn is_header(&self, bb: BasicBlock) -> bool {
+    self.loops.iter().any(|l| l.header == bb)
+  }
 }
@@ -0,0 +1,248 @@
+//! Attributes and calling conventions
+//!
+//! Unlike the legacy bitmask `value::Attribute`, the attributes here are
+//! created in a context and may carry a value, such as `dereferenceable(N)`,
+//! `align N` or string attributes like `"target-cpu"="haswell"`. They can be
+//! attached to a function, its return value, its parameters or a call site.
+
+use libc::{c_char, c_uint};
+use llvm_sys::{LLVMAttributeFunctionIndex, LLVMAttributeIndex, LLVMAttributeReturnIndex, core};
+use llvm_sys::prelude::{LLVMAttributeRef, LLVMContextRef};
+
+use super::LLVMRef;
+
+/// The position an attribute is attached to.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum AttrIndex {
+  /// The function itself, e.g. `nounwind` or `"target-cpu"`.
+  Function,
+  /// The return value, e.g. `noalias` on an allocator.
+  Return,
+  /// The parameter at the given (zero-based) position.
+  Param(usize),
+}
+
+impl From<AttrIndex> for LLVMAttributeIndex {
+  fn from(idx: AttrIndex) -> LLVMAttributeIndex {
+    match idx {
+      AttrIndex::Function => LLVMAttributeFunctionIndex,
+      AttrIndex::Return => LLVMAttributeReturnIndex,
+      AttrIndex::Param(i) => i as c_uint + 1,
+    }
+  }
+}
+
+/// An enum or string attribute.
+///
+/// Refer to http://llvm.org/docs/LangRef.html#parameter-attributes and
+/// http://llvm.org/docs/LangRef.html#function-attributes
+#[derive(Copy, Clone)]
+pub struct Attr(pub LLVMAttributeRef);
+impl_from_ref!(LLVMAttributeRef, Attr);
+
+impl Attr {
+  /// Returns the kind id of the enum attribute with the given name, or `None`
+  /// if LLVM doesn't know such an attribute.
+  pub fn kind_for_name(name: &str) -> Option<c_uint> {
+    let kind = unsafe {
+      core::LLVMGetEnumAttributeKindForName(name.as_ptr() as *const c_char, name.len())
+    };
+
+    if kind == 0 {
+      None
+    } else {
+      Some(kind)
+    }
+  }
+
+  /// Create an enum attribute, like `nonnull` or `dereferenceable`.
+  ///
+  /// `val` is only meaningful for integer attributes and is ignored otherwise.
+  /// This panics if `name` is not a known attribute.
+  pub fn new_enum(ctx: LLVMContextRef, name: &str, val: u64) -> Attr {
+    let kind = match Attr::kind_for_name(name) {
+      Some(kind) => kind,
+      None => panic!("unknown attribute: {}", name),
+    };
+
+    Attr(unsafe { core::LLVMCreateEnumAttribute(ctx, kind, val) })
+  }
+
+  /// Create a string attribute, like `"target-cpu"="haswell"`.
+  pub fn new_string(ctx: LLVMContextRef, key: &str, val: &str) -> Attr {
+    Attr(unsafe {
+      core::LLVMCreateStringAttribute(ctx,
+                                      key.as_ptr() as *const c_char,
+                                      key.len() as c_uint,
+                                      val.as_ptr() as *const c_char,
+                                      val.len() as c_uint)
+    })
+  }
+
+  /// Create a `noalias` attribute.
+  pub fn noalias(ctx: LLVMContextRef) -> Attr {
+    Attr::new_enum(ctx, "noalias", 0)
+  }
+
+  /// Create a `nonnull` attribute.
+  pub fn nonnull(ctx: LLVMContextRef) -> Attr {
+    Attr::new_enum(ctx, "nonnull", 0)
+  }
+
+  /// Create a `dereferenceable(bytes)` attribute.
+  pub fn dereferenceable(ctx: LLVMContextRef, bytes: u64) -> Attr {
+    Attr::new_enum(ctx, "dereferenceable", bytes)
+  }
+
+  /// Create an `align bytes` attribute.
+  pub fn align(ctx: LLVMContextRef, bytes: u64) -> Attr {
+    Attr::new_enum(ctx, "align", bytes)
+  }
+
+  /// Returns true if this is a string attribute.
+  pub fn is_string(&self) -> bool {
+    unsafe { core::LLVMIsStringAttribute(self.0) != 0 }
+  }
+
+  /// Returns true if this is an enum attribute.
+  pub fn is_enum(&self) -> bool {
+    unsafe { core::LLVMIsEnumAttribute(self.0) != 0 }
+  }
+
+  /// Returns the integer value of an enum attribute, or 0 if it has none.
+  pub fn enum_value(&self) -> u64 {
+    unsafe { core::LLVMGetEnumAttributeValue(self.0) }
+  }
+
+  /// Returns the key of a string attribute.
+  pub fn string_key<'a>(&self) -> &'a str {
+    unsafe {
+      let mut len = 0;
+      let ptr = core::LLVMGetStringAttributeKind(self.0, &mut len);
+      to_sized_str(ptr, len)
+    }
+  }
+
+  /// Returns the value of a string attribute.
+  pub fn string_value<'a>(&self) -> &'a str {
+    unsafe {
+      let mut len = 0;
+      let ptr = core::LLVMGetStringAttributeValue(self.0, &mut len);
+      to_sized_str(ptr, len)
+    }
+  }
+}
+
+unsafe fn to_sized_str<'a>(ptr: *const c_char, len: c_uint) -> &'a str {
+  let bytes = ::std::slice::from_raw_parts(ptr as *const u8, len as usize);
+  ::std::str::from_utf8_unchecked(bytes)
+}
+
+/// Calling conventions of functions and call sites.
+///
+/// Refer to http://llvm.org/docs/LangRef.html#calling-conventions
+#[repr(C)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum CallConv {
+  /// The C calling convention.
+  C = 0,
+  /// Make calls as fast as possible, e.g. by passing things in registers.
+  Fast = 8,
+  /// Make code in the caller as efficient as possible under the assumption
+  /// that the call is not commonly executed.
+  Cold = 9,
+  /// Used by the Glasgow Haskell Compiler.
+  GHC = 10,
+  /// Used by the High-Performance Erlang Compiler.
+  HiPE = 11,
+  /// Used for dynamic register based calls, e.g. stackmap and patchpoint.
+  AnyReg = 13,
+  /// Preserve most registers for the caller.
+  PreserveMost = 14,
+  /// Preserve all registers for the caller.
+  PreserveAll = 15,
+  /// X86 `stdcall`.
+  X86Stdcall = 64,
+  /// X86 `fastcall`.
+  X86Fastcall = 65,
+}
+
+impl CallConv {
+  /// Returns the calling convention with the given LLVM id, or `None` if this
+  /// crate doesn't expose it.
+  pub fn from_raw(cc: c_uint) -> Option<CallConv> {
+    match cc {
+      0 => Some(CallConv::C),
+      8 => Some(CallConv::Fast),
+      9 => Some(CallConv::Cold),
+      10 => Some(CallConv::GHC),
+      11 => Some(CallConv::HiPE),
+      13 => Some(CallConv::AnyReg),
+      14 => Some(CallConv::PreserveMost),
+      15 => Some(CallConv::PreserveAll),
+      64 => Some(CallConv::X86Stdcall),
+      65 => Some(CallConv::X86Fastcall),
+      _ => None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::{Builder, FunctionTy, JitCompiler};
+  use types::LLVMTy;
+  use value::{CallSite, Value, ValueRef};
+
+  #[test]
+  fn test_func_attrs() {
+    let jit = JitCompiler::new("test_attrs").ok().unwrap();
+    let ctx = jit.context();
+
+    let ptr_ty = u8::llvm_ty(ctx).pointer_ty();
+    let func = jit.add_func("attrs", &FunctionTy::new(&ptr_ty, &[&ptr_ty, &u64::llvm_ty(ctx)]));
+
+    func.add_attr(AttrIndex::Return, &Attr::noalias(ctx));
+    func.arg(0).add_attr(&Attr::dereferenceable(ctx, 16));
+    func.add_attr(AttrIndex::Function, &Attr::new_string(ctx, "target-cpu", "generic"));
+
+    assert!(func.get_enum_attr(AttrIndex::Return, "noalias").is_some());
+    assert!(func.get_enum_attr(AttrIndex::Param(1), "noalias").is_none());
+    assert_eq!(16,
+               func.get_enum_attr(AttrIndex::Param(0), "dereferenceable").unwrap().enum_value());
+
+    let cpu = func.get_string_attr(AttrIndex::Function, "target-cpu").unwrap();
+    assert!(cpu.is_string());
+    assert_eq!("target-cpu", cpu.string_key());
+    assert_eq!("generic", cpu.string_value());
+
+    func.remove_enum_attr(AttrIndex::Return, "noalias");
+    assert!(func.get_enum_attr(AttrIndex::Return, "noalias").is_none());
+  }
+
+  #[test]
+  fn test_call_conv() {
+    let jit = JitCompiler::new("test_call_conv").ok().unwrap();
+    let ctx = jit.context();
+    let bld = Builder::new(ctx);
+
+    let callee = jit.create_func_prototype("callee", &u64::llvm_ty(ctx), &[&u64::llvm_ty(ctx)],
+                                           Some(&bld));
+    callee.set_call_conv(CallConv::Fast);
+    bld.create_ret(&callee.arg(0).into());
+    assert_eq!(Some(CallConv::Fast), callee.get_call_conv());
+
+    let caller = jit.create_func_prototype("caller", &u64::llvm_ty(ctx), &[&u64::llvm_ty(ctx)],
+                                           Some(&bld));
+    let call = CallSite::from(bld.create_call(&callee, &[&caller.arg(0).into()]));
+    call.set_call_conv(CallConv::Fast);
+    call.add_attr(AttrIndex::Function, &Attr::new_enum(ctx, "nounwind", 0));
+    bld.create_ret(&Value::from(&call));
+
+    assert_eq!(Some(CallConv::Fast), call.get_call_conv());
+    assert!(call.get_enum_attr(AttrIndex::Function, "nounwind").is_some());
+    assert!(call.ty() == u64::llvm_ty(ctx));
+
+    jit.verify().unwrap();
+  }
+}
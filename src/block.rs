@@ -3,12 +3,20 @@
 use llvm_sys::core;
 use llvm_sys::prelude::LLVMBasicBlockRef;
 
-use value::Value;
+use util::chars;
+use value::{Instruction, Value, ValueIter};
 
 /// A container of instructions that execute sequentially.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
 pub struct BasicBlock(pub LLVMBasicBlockRef);
 
 impl BasicBlock {
+  /// Returns the name of this basic block, or `None` if it has no name.
+  pub fn name(&self) -> Option<&str> {
+    let name = chars::to_str(unsafe { core::LLVMGetBasicBlockName(self.0) });
+    if name.is_empty() { None } else { Some(name) }
+  }
+
   /// Return the enclosing method, or `None` if it is not attached to a method.
   pub fn parent(&self) -> Option<Value> {
     unsafe {
@@ -22,6 +30,31 @@ impl BasicBlock {
     }
   }
 
+  /// Iterate through the instructions in this basic block.
+  pub fn instructions(&self) -> ValueIter<Instruction> {
+    ValueIter::new(unsafe { core::LLVMGetFirstInstruction(self.0) },
+                   core::LLVMGetNextInstruction)
+  }
+
+  /// Returns the terminator instruction of this basic block, or `None` if it has none yet.
+  pub fn terminator(&self) -> Option<Instruction> {
+    unsafe { ::util::ret_nullable_ptr(core::LLVMGetBasicBlockTerminator(self.0)) }
+  }
+
+  /// Returns the basic blocks the terminator of this block may branch to, each once.
+  pub fn successors(&self) -> Vec<BasicBlock> {
+    let mut succs = Vec::new();
+    if let Some(term) = self.terminator() {
+      for i in 0..unsafe { core::LLVMGetNumSuccessors(term.0) } {
+        let succ = BasicBlock(unsafe { core::LLVMGetSuccessor(term.0, i) });
+        if !succs.contains(&succ) {
+          succs.push(succ);
+        }
+      }
+    }
+    succs
+  }
+
   /// Move this basic block after the `other` basic block in its function.
   pub fn move_after(&self, other: &BasicBlock) {
     unsafe { core::LLVMMoveBasicBlockAfter(self.0, other.0) }
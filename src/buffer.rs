@@ -13,6 +13,18 @@ impl MemoryBuffer {
     self.0
   }
 
+  /// Wrap a buffer created by LLVM, taking its ownership.
+  pub fn from_ptr(ptr: LLVMMemoryBufferRef) -> MemoryBuffer {
+    MemoryBuffer(ptr)
+  }
+
+  /// Give up the ownership of this buffer, e.g. to LLVM, returning it.
+  pub fn into_ptr(self) -> LLVMMemoryBufferRef {
+    let ptr = self.0;
+    mem::forget(self);
+    ptr
+  }
+
   pub fn from_file(path: &str) -> Result<MemoryBuffer, String> {
     let c_path = chars::from_str(path);
 
@@ -24,6 +36,25 @@ impl MemoryBuffer {
       llvm_ret!(ret, MemoryBuffer(out), err)
     }
   }
+
+  /// Returns the contents of this buffer.
+  pub fn as_bytes(&self) -> &[u8] {
+    unsafe {
+      let start = core::LLVMGetBufferStart(self.0) as *const u8;
+      ::std::slice::from_raw_parts(start, core::LLVMGetBufferSize(self.0))
+    }
+  }
+
+  /// Create a buffer holding a copy of `bytes`, e.g. bitcode embedded with `include_bytes!`.
+  pub fn from_bytes(name: &str, bytes: &[u8]) -> MemoryBuffer {
+    let c_name = chars::from_str(name);
+
+    MemoryBuffer(unsafe {
+      core::LLVMCreateMemoryBufferWithMemoryRangeCopy(bytes.as_ptr() as *const c_char,
+                                                      bytes.len(),
+                                                      c_name)
+    })
+  }
 }
 
 impl_dispose!(MemoryBuffer, core::LLVMDisposeMemoryBuffer);
@@ -1,15 +1,18 @@
 #![allow(dead_code)]
 
 use std::mem;
+use std::ptr;
 
 use llvm_sys::{LLVMIntPredicate, LLVMOpcode, LLVMRealPredicate, core};
 use llvm_sys::prelude::{LLVMBuilderRef, LLVMContextRef, LLVMValueRef};
 use libc::{c_char, c_uint};
 
 use super::LLVMRef;
-use types::Ty;
+use types::{FunctionTy, Ty};
+use util::HasContext;
 use block::BasicBlock;
-use value::{Function, PhiNode, Predicate, Value, ValueRef};
+use debuginfo::DILocation;
+use value::{AsmDialect, Function, InlineAsm, PhiNode, Predicate, Value, ValueRef};
 
 static NULL_NAME: [c_char; 1] = [0];
 
@@ -127,6 +130,13 @@ impl Builder {
     BasicBlock(unsafe { core::LLVMGetInsertBlock(self.0) })
   }
 
+  /// Sets the source location attached to instructions built from now on, or stops
+  /// attaching one if `loc` is `None`.
+  pub fn set_current_debug_location(&self, loc: Option<&DILocation>) {
+    let loc = loc.map_or(ptr::null_mut(), |l| l.0);
+    unsafe { core::LLVMSetCurrentDebugLocation2(self.0, loc) }
+  }
+
   /// Position the builder at `instr` within `block`.
   pub fn position_at(&self, block: &BasicBlock, instr: &Value) {
     unsafe { core::LLVMPositionBuilder(self.0, block.0, instr.0) }
@@ -216,7 +226,7 @@ impl Builder {
   ///
   /// This will return the return value of the function.
   fn create_call_internal<V: LLVMRef<LLVMValueRef>>(&self,
-                                                    func: &Function,
+                                                    callee: LLVMValueRef,
                                                     args: &[&V],
                                                     tail_call: bool)
                                                     -> Value {
@@ -224,7 +234,7 @@ impl Builder {
 
     Value(unsafe {
       let call = core::LLVMBuildCall(self.0,
-                                     func.0,
+                                     callee,
                                      ref_array.as_ptr() as *mut LLVMValueRef,
                                      args.len() as c_uint,
                                      NULL_NAME.as_ptr());
@@ -242,14 +252,36 @@ impl Builder {
   ///
   /// This will return the return value of the function.
   pub fn create_call(&self, func: &Function, args: &[&Value]) -> Value {
-    self.create_call_internal(func, args, false)
+    self.create_call_internal(func.0, args, false)
   }
 
   /// Build an instruction that calls the function `func` with the arguments `args`.
   ///
   /// This will return the return value of the function.
   pub fn create_tail_call<V: LLVMRef<LLVMValueRef>>(&self, func: &Function, args: &[&V]) -> Value {
-    self.create_call_internal(func, args, true)
+    self.create_call_internal(func.0, args, true)
+  }
+
+  /// Returns the inline assembly `asm` of the function type `fn_ty`, to be called with
+  /// `create_asm_call`, or an error string if `constraints` don't match the type. See
+  /// `InlineAsm::new`.
+  pub fn create_inline_asm(&self,
+                           fn_ty: &FunctionTy,
+                           asm: &str,
+                           constraints: &str,
+                           side_effects: bool,
+                           align_stack: bool,
+                           dialect: AsmDialect)
+                           -> Result<InlineAsm, String> {
+    InlineAsm::new(fn_ty, asm, constraints, side_effects, align_stack, dialect)
+  }
+
+  /// Build an instruction that runs the inline assembly `asm` with the operands `args`.
+  ///
+  /// This will return the output of the assembly, or a struct of its outputs if it has
+  /// several.
+  pub fn create_asm_call(&self, asm: &InlineAsm, args: &[&Value]) -> Value {
+    self.create_call_internal(asm.0, args, false)
   }
 
   /// Build an instruction that yields to `true_val` if `cond` is equal to `1`, and `false_val`
@@ -270,7 +302,7 @@ impl Builder {
       CastOp::UIToFP => LLVMOpcode::LLVMUIToFP,
       CastOp::SIToFP => LLVMOpcode::LLVMSIToFP,
       CastOp::FPToUI => LLVMOpcode::LLVMFPToUI,
-      CastOp::FPToSI => LLVMOpcode::LLVMFPToUI,
+      CastOp::FPToSI => LLVMOpcode::LLVMFPToSI,
       CastOp::PtrToInt => LLVMOpcode::LLVMPtrToInt,
       CastOp::IntToPtr => LLVMOpcode::LLVMIntToPtr,
       CastOp::BitCast => LLVMOpcode::LLVMBitCast,
@@ -279,6 +311,25 @@ impl Builder {
     Value(unsafe { core::LLVMBuildCast(self.0, llvm_op, value.0, dest_ty.0, NULL_NAME.as_ptr()) })
   }
 
+  /// Build an instruction that widens the `i1` boolean `value` into the `i8` ABI boolean,
+  /// e.g. to return it to Rust or store it into memory shared with Rust.
+  pub fn create_bool_to_abi(&self, value: &Value) -> Value {
+    debug_assert!(value.ty().is_bool(), "The value must be an i1");
+    let abi_ty = Ty::abi_bool_ty(value.ty().context());
+    self.create_cast(CastOp::ZExt, value, &abi_ty)
+  }
+
+  /// Build an instruction that narrows the `i8` ABI boolean `value` into an `i1` boolean,
+  /// e.g. for an argument passed from Rust.
+  pub fn create_abi_to_bool(&self, value: &Value) -> Value {
+    let ty = value.ty();
+    debug_assert!(ty == Ty::abi_bool_ty(ty.context()), "The value must be an i8");
+    let zero = Value(unsafe { core::LLVMConstNull(ty.0) });
+    Value(unsafe {
+      core::LLVMBuildICmp(self.0, LLVMIntPredicate::LLVMIntNE, value.0, zero.0, NULL_NAME.as_ptr())
+    })
+  }
+
   /// Build an instruction that casts a value into a certain type.
   pub fn create_bit_cast(&self, value: &Value, dest: &Ty) -> Value {
     Value(unsafe { core::LLVMBuildBitCast(self.0, value.0, dest.0, NULL_NAME.as_ptr()) })
@@ -301,14 +352,18 @@ impl Builder {
   unary_instr!{create_load, LLVMBuildLoad}
   unary_instr!{create_neg, LLVMBuildNeg}
   unary_instr!{create_not, LLVMBuildNot}
+  unary_instr!{create_fneg, LLVMBuildFNeg}
 
   bin_instr!{create_add, LLVMBuildAdd, LLVMBuildFAdd}
   bin_instr!{create_sub, LLVMBuildSub, LLVMBuildFSub}
   bin_instr!{create_mul, LLVMBuildMul, LLVMBuildFMul}
   bin_instr!{create_div, LLVMBuildSDiv, LLVMBuildFDiv}
   bin_instr!{create_rem, LLVMBuildSRem, LLVMBuildFRem}
+  bin_instr!{create_udiv, LLVMBuildUDiv}
+  bin_instr!{create_urem, LLVMBuildURem}
   bin_instr!{create_shl, LLVMBuildShl}
   bin_instr!{create_ashr, LLVMBuildAShr}
+  bin_instr!{create_lshr, LLVMBuildLShr}
   bin_instr!{create_and, LLVMBuildAnd}
   bin_instr!{create_or, LLVMBuildOr}
   bin_instr!{create_xor, LLVMBuildXor}
@@ -387,8 +442,8 @@ impl Builder {
 #[cfg(test)]
 mod tests {
   use super::super::{FunctionTy, JitCompiler};
-  use types::LLVMTy;
-  use value::{Predicate, ToValue};
+  use types::{LLVMTy, Ty};
+  use value::{AsmDialect, Predicate, ToValue};
 
   #[test]
   pub fn test_cond_br() {
@@ -435,4 +490,43 @@ mod tests {
       }
     }
   }
+
+  #[test]
+  #[cfg(target_arch = "x86_64")]
+  pub fn test_inline_asm() {
+    let jit = JitCompiler::new("test_asm").ok().unwrap();
+    let ctx = jit.context();
+    let bld = jit.new_builder();
+    let u64_ty = u64::llvm_ty(ctx);
+    let func = jit.create_func_prototype("plus_one", &u64_ty, &[&u64_ty], Some(&bld));
+
+    let pause = bld.create_inline_asm(&FunctionTy::new(&Ty::void_ty(ctx), &[]),
+                                      "pause",
+                                      "~{memory}",
+                                      true,
+                                      false,
+                                      AsmDialect::Att)
+                   .unwrap();
+    bld.create_asm_call(&pause, &[]);
+    let lea = bld.create_inline_asm(&FunctionTy::new(&u64_ty, &[&u64_ty]),
+                                    "lea $0, [$1 + 1]",
+                                    "=r,r",
+                                    false,
+                                    false,
+                                    AsmDialect::Intel)
+                 .unwrap();
+    bld.create_ret(&bld.create_asm_call(&lea, &[&func.arg(0).into()]));
+    jit.verify().unwrap();
+
+    let plus_one: fn(u64) -> u64 = unsafe { ::std::mem::transmute(jit.get_func_ptr(&func).unwrap()) };
+    assert_eq!(42, plus_one(41));
+  }
+
+  #[test]
+  pub fn test_invalid_asm_constraints() {
+    let jit = JitCompiler::new("test_asm").ok().unwrap();
+    let u64_ty = u64::llvm_ty(jit.context());
+    let fn_ty = FunctionTy::new(&u64_ty, &[&u64_ty]);
+    assert!(jit.builder().create_inline_asm(&fn_ty, "nop", "=r", false, false, AsmDialect::Att).is_err());
+  }
 }
@@ -0,0 +1,190 @@
+//! Constants and constant expressions
+//!
+//! Constants are folded by LLVM when they are built, so constant expressions
+//! over other constants usually yield plain constants again. They can be
+//! used as global initializers.
+
+use std::fmt;
+
+use libc::c_uint;
+use llvm_sys::core;
+use llvm_sys::prelude::LLVMValueRef;
+
+use super::LLVMRef;
+use types::Ty;
+use value::{Value, ValueRef};
+
+/// A constant value or constant expression.
+#[derive(Copy, Clone)]
+pub struct Constant(pub LLVMValueRef);
+impl_from_ref!(LLVMValueRef, Constant);
+impl_from_into!(Constant, Value);
+impl_display!(Constant, LLVMPrintValueToString);
+impl ValueRef for Constant {}
+
+impl Constant {
+  /// Returns the value given as a constant, or `None` if it is not a constant.
+  pub fn from_value(val: &Value) -> Option<Constant> {
+    if val.is_constant() {
+      Some(Constant(val.0))
+    } else {
+      None
+    }
+  }
+
+  /// Create the zero value of the given type, e.g. `0`, `0.0`, `null` or `zeroinitializer`.
+  pub fn null(ty: &Ty) -> Constant {
+    Constant(unsafe { core::LLVMConstNull(ty.0) })
+  }
+
+  /// Create the zero value of an aggregate type, which is `zeroinitializer`.
+  ///
+  /// This is the same as `null`, but reads better for structs and arrays.
+  pub fn zero_initializer(ty: &Ty) -> Constant {
+    Constant::null(ty)
+  }
+
+  /// Create a null pointer of the given pointer type.
+  pub fn null_ptr(ty: &Ty) -> Constant {
+    Constant(unsafe { core::LLVMConstPointerNull(ty.0) })
+  }
+
+  /// Create a value of the given integer or vector type with all bits set.
+  pub fn all_ones(ty: &Ty) -> Constant {
+    Constant(unsafe { core::LLVMConstAllOnes(ty.0) })
+  }
+
+  /// Create a constant array of `elem_ty` from the constants given.
+  pub fn array(elem_ty: &Ty, vals: &[&Value]) -> Constant {
+    let ref_array = to_llvmref_array!(vals, LLVMValueRef);
+
+    Constant(unsafe {
+      core::LLVMConstArray(elem_ty.0,
+                           ref_array.as_ptr() as *mut LLVMValueRef,
+                           vals.len() as c_uint)
+    })
+  }
+
+  /// Create a constant in-bounds `getelementptr` of `ptr`, which points to `elem_ty`.
+  pub fn gep(elem_ty: &Ty, ptr: &Constant, indices: &[&Value]) -> Constant {
+    let ref_array = to_llvmref_array!(indices, LLVMValueRef);
+
+    Constant(unsafe {
+      core::LLVMConstInBoundsGEP2(elem_ty.0,
+                                  ptr.0,
+                                  ref_array.as_ptr() as *mut LLVMValueRef,
+                                  indices.len() as c_uint)
+    })
+  }
+
+  /// Create a constant `bitcast` of this constant to `ty`.
+  pub fn bit_cast(&self, ty: &Ty) -> Constant {
+    Constant(unsafe { core::LLVMConstBitCast(self.0, ty.0) })
+  }
+
+  /// Create a constant `ptrtoint` of this pointer to the integer type `ty`.
+  pub fn ptr_to_int(&self, ty: &Ty) -> Constant {
+    Constant(unsafe { core::LLVMConstPtrToInt(self.0, ty.0) })
+  }
+
+  /// Create a constant `inttoptr` of this integer to the pointer type `ty`.
+  pub fn int_to_ptr(&self, ty: &Ty) -> Constant {
+    Constant(unsafe { core::LLVMConstIntToPtr(self.0, ty.0) })
+  }
+
+  /// Returns true if this is a constant integer.
+  pub fn is_int(&self) -> bool {
+    unsafe { !core::LLVMIsAConstantInt(self.0).is_null() }
+  }
+
+  /// Returns true if this is a constant floating-point number.
+  pub fn is_float(&self) -> bool {
+    unsafe { !core::LLVMIsAConstantFP(self.0).is_null() }
+  }
+
+  /// Returns the value of this constant integer, zero-extended to 64 bits, or `None` if
+  /// this is not a constant integer.
+  pub fn int_zext_value(&self) -> Option<u64> {
+    if self.is_int() {
+      Some(unsafe { core::LLVMConstIntGetZExtValue(self.0) as u64 })
+    } else {
+      None
+    }
+  }
+
+  /// Returns the value of this constant integer, sign-extended to 64 bits, or `None` if
+  /// this is not a constant integer.
+  pub fn int_sext_value(&self) -> Option<i64> {
+    if self.is_int() {
+      Some(unsafe { core::LLVMConstIntGetSExtValue(self.0) as i64 })
+    } else {
+      None
+    }
+  }
+
+  /// Returns the value of this constant floating-point number as a `f64`, or `None` if
+  /// this is not a constant floating-point number.
+  pub fn float_value(&self) -> Option<f64> {
+    if self.is_float() {
+      let mut loses_info = 0;
+      Some(unsafe { core::LLVMConstRealGetDouble(self.0, &mut loses_info) })
+    } else {
+      None
+    }
+  }
+
+  /// Returns the element at `index` of a constant struct, array or vector, or `None` if
+  /// there is none.
+  pub fn element(&self, index: usize) -> Option<Constant> {
+    unsafe { ::util::ret_nullable_ptr(core::LLVMGetAggregateElement(self.0, index as c_uint)) }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::JitCompiler;
+  use types::LLVMTy;
+  use value::{ToValue, ValueRef};
+
+  #[test]
+  fn test_constants() {
+    let jit = JitCompiler::new("test_constants").ok().unwrap();
+    let ctx = jit.context();
+
+    assert_eq!("i32 0", format!("{}", Constant::null(&i32::llvm_ty(ctx))));
+    assert_eq!("i8 -1", format!("{}", Constant::all_ones(&u8::llvm_ty(ctx))));
+    assert!(Constant::null(&i64::llvm_ty(ctx)).is_null());
+    assert!(Constant::null_ptr(&i64::llvm_ty(ctx).pointer_ty()).is_null());
+
+    let c = Constant::from_value(&(-3i32).to_value(ctx)).unwrap();
+    assert_eq!(Some(-3), c.int_sext_value());
+    assert_eq!(Some(0xfffffffd), c.int_zext_value());
+    assert_eq!(None, c.float_value());
+
+    let f = Constant::from_value(&1.5f64.to_value(ctx)).unwrap();
+    assert_eq!(Some(1.5), f.float_value());
+    assert_eq!(None, f.int_zext_value());
+  }
+
+  #[test]
+  fn test_const_array_global() {
+    let jit = JitCompiler::new("test_constants").ok().unwrap();
+    let ctx = jit.context();
+
+    let vals = (0..4u64).map(|i| (i * i).to_value(ctx)).collect::<Vec<Value>>();
+    let val_refs = vals.iter().collect::<Vec<&Value>>();
+    let table = Constant::array(&u64::llvm_ty(ctx), &val_refs);
+    assert_eq!("[4 x i64] [i64 0, i64 1, i64 4, i64 9]", format!("{}", table));
+    assert_eq!(Some(9), table.element(3).and_then(|c| c.int_zext_value()));
+
+    let global = jit.add_global_constant("squares", &table.into());
+    let init = Constant::from_value(&global.get_initializer()).unwrap();
+    assert_eq!(Some(4), init.element(2).and_then(|c| c.int_zext_value()));
+
+    let global_ptr = Constant(global.0);
+    let second = Constant::gep(&table.ty(), &global_ptr, &[&0i32.to_value(ctx), &1i32.to_value(ctx)]);
+    assert!(second.is_constant());
+    assert!(!second.ptr_to_int(&u64::llvm_ty(ctx)).is_null());
+  }
+}
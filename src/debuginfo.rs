@@ -0,0 +1,442 @@
+//! Debug Info Module
+//!
+//! Generates DWARF debug information for generated code, so JIT-compiled
+//! functions show up with names, source locations and variables in debuggers
+//! and profilers.
+//!
+//! Refer to http://llvm.org/docs/SourceLevelDebugging.html
+
+use std::ptr;
+
+use libc::{c_char, c_uint};
+use llvm_sys::{LLVMModuleFlagBehavior, core};
+use llvm_sys::debuginfo::{self as di, LLVMDIFlagZero, LLVMDWARFEmissionKind,
+                          LLVMDWARFSourceLanguage};
+use llvm_sys::prelude::{LLVMContextRef, LLVMDIBuilderRef, LLVMMetadataRef};
+
+use super::LLVMRef;
+use block::BasicBlock;
+use module::Module;
+use value::{Function, Value};
+
+/// A debug info scope, which can enclose functions, types and variables.
+pub trait DIScope: LLVMRef<LLVMMetadataRef> {}
+
+macro_rules! di_node (
+  ($(#[$attr:meta])* $name:ident) => (
+    $(#[$attr])*
+    #[derive(Copy, Clone)]
+    pub struct $name(pub LLVMMetadataRef);
+    impl_from_ref!(LLVMMetadataRef, $name);
+  );
+);
+
+di_node!(
+  /// A source file.
+  DIFile);
+di_node!(
+  /// A compile unit, which is the root of all debug info in a module.
+  DICompileUnit);
+di_node!(
+  /// The debug info of a function.
+  DISubprogram);
+di_node!(
+  /// A lexical block within a subprogram, like the body of a loop.
+  DILexicalBlock);
+di_node!(
+  /// A basic, derived or composite type.
+  DIType);
+di_node!(
+  /// A local variable or parameter.
+  DIVariable);
+di_node!(
+  /// A source location: a line and column within a scope.
+  DILocation);
+
+impl DIScope for DIFile {}
+impl DIScope for DICompileUnit {}
+impl DIScope for DISubprogram {}
+impl DIScope for DILexicalBlock {}
+
+/// DWARF encodings of basic types.
+#[repr(C)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum DIEncoding {
+  Address = 0x01,
+  Boolean = 0x02,
+  Float = 0x04,
+  Signed = 0x05,
+  SignedChar = 0x06,
+  Unsigned = 0x07,
+  UnsignedChar = 0x08,
+}
+
+/// A struct member given to `DebugInfoBuilder::create_struct_type`.
+pub struct DIMember<'a> {
+  pub name: &'a str,
+  pub ty: DIType,
+  pub size_in_bits: u64,
+  pub align_in_bits: u32,
+  pub offset_in_bits: u64,
+  pub line: usize,
+}
+
+/// Builds debug info for a module.
+///
+/// It should be finalized with `finalize()` before the module is compiled. Otherwise,
+/// it is finalized when it is dropped.
+pub struct DebugInfoBuilder {
+  builder: LLVMDIBuilderRef,
+  ctx: LLVMContextRef,
+  finalized: bool,
+}
+
+impl DebugInfoBuilder {
+  /// Create a new builder for the module given, and add the module flags required to emit
+  /// debug info.
+  pub fn new(module: &Module) -> DebugInfoBuilder {
+    unsafe {
+      let ctx = core::LLVMGetModuleContext(module.0);
+      let i32_ty = core::LLVMInt32TypeInContext(ctx);
+      let flags = [("Debug Info Version", di::LLVMDebugMetadataVersion()), ("Dwarf Version", 4)];
+
+      for &(key, val) in flags.iter() {
+        let val = core::LLVMValueAsMetadata(core::LLVMConstInt(i32_ty, val as u64, 0));
+        core::LLVMAddModuleFlag(module.0,
+                                LLVMModuleFlagBehavior::LLVMModuleFlagBehaviorWarning,
+                                key.as_ptr() as *const c_char,
+                                key.len(),
+                                val);
+      }
+
+      DebugInfoBuilder {
+        builder: di::LLVMCreateDIBuilder(module.0),
+        ctx: ctx,
+        finalized: false,
+      }
+    }
+  }
+
+  /// Construct any deferred debug info descriptors. No more debug info can be added after this.
+  pub fn finalize(&mut self) {
+    if !self.finalized {
+      unsafe { di::LLVMDIBuilderFinalize(self.builder) }
+      self.finalized = true;
+    }
+  }
+
+  /// Create a source file descriptor.
+  pub fn create_file(&self, filename: &str, directory: &str) -> DIFile {
+    DIFile(unsafe {
+      di::LLVMDIBuilderCreateFile(self.builder,
+                                  filename.as_ptr() as *const c_char,
+                                  filename.len(),
+                                  directory.as_ptr() as *const c_char,
+                                  directory.len())
+    })
+  }
+
+  /// Create the compile unit of the module. There should be only one per module.
+  pub fn create_compile_unit(&self, file: &DIFile, producer: &str, optimized: bool)
+                             -> DICompileUnit {
+    let empty = "";
+
+    DICompileUnit(unsafe {
+      di::LLVMDIBuilderCreateCompileUnit(self.builder,
+                                         LLVMDWARFSourceLanguage::LLVMDWARFSourceLanguageC,
+                                         file.0,
+                                         producer.as_ptr() as *const c_char,
+                                         producer.len(),
+                                         optimized as i32,
+                                         empty.as_ptr() as *const c_char,
+                                         0,
+                                         0,
+                                         empty.as_ptr() as *const c_char,
+                                         0,
+                                         LLVMDWARFEmissionKind::LLVMDWARFEmissionKindFull,
+                                         0,
+                                         0,
+                                         0,
+                                         empty.as_ptr() as *const c_char,
+                                         0,
+                                         empty.as_ptr() as *const c_char,
+                                         0)
+    })
+  }
+
+  /// Create a basic type like `int64` or `double`.
+  pub fn create_basic_type(&self, name: &str, size_in_bits: u64, encoding: DIEncoding) -> DIType {
+    DIType(unsafe {
+      di::LLVMDIBuilderCreateBasicType(self.builder,
+                                       name.as_ptr() as *const c_char,
+                                       name.len(),
+                                       size_in_bits,
+                                       encoding as c_uint,
+                                       LLVMDIFlagZero)
+    })
+  }
+
+  /// Create a pointer type to `pointee`.
+  pub fn create_pointer_type(&self, pointee: &DIType, size_in_bits: u64) -> DIType {
+    DIType(unsafe {
+      di::LLVMDIBuilderCreatePointerType(self.builder,
+                                         pointee.0,
+                                         size_in_bits,
+                                         0,
+                                         0,
+                                         ptr::null(),
+                                         0)
+    })
+  }
+
+  /// Create a struct type with the members given.
+  pub fn create_struct_type<S: DIScope>(&self,
+                                        scope: &S,
+                                        name: &str,
+                                        file: &DIFile,
+                                        line: usize,
+                                        size_in_bits: u64,
+                                        align_in_bits: u32,
+                                        members: &[DIMember])
+                                        -> DIType {
+    let mut elements = members.iter()
+      .map(|m| unsafe {
+        di::LLVMDIBuilderCreateMemberType(self.builder,
+                                          scope.as_ref(),
+                                          m.name.as_ptr() as *const c_char,
+                                          m.name.len(),
+                                          file.0,
+                                          m.line as c_uint,
+                                          m.size_in_bits,
+                                          m.align_in_bits,
+                                          m.offset_in_bits,
+                                          LLVMDIFlagZero,
+                                          m.ty.0)
+      })
+      .collect::<Vec<LLVMMetadataRef>>();
+
+    DIType(unsafe {
+      di::LLVMDIBuilderCreateStructType(self.builder,
+                                        scope.as_ref(),
+                                        name.as_ptr() as *const c_char,
+                                        name.len(),
+                                        file.0,
+                                        line as c_uint,
+                                        size_in_bits,
+                                        align_in_bits,
+                                        LLVMDIFlagZero,
+                                        ptr::null_mut(),
+                                        elements.as_mut_ptr(),
+                                        elements.len() as c_uint,
+                                        0,
+                                        ptr::null_mut(),
+                                        ptr::null(),
+                                        0)
+    })
+  }
+
+  /// Create an array type of `count` elements of `elem`.
+  pub fn create_array_type(&self, elem: &DIType, count: i64, size_in_bits: u64,
+                           align_in_bits: u32) -> DIType {
+    unsafe {
+      let mut subscripts = [di::LLVMDIBuilderGetOrCreateSubrange(self.builder, 0, count)];
+      DIType(di::LLVMDIBuilderCreateArrayType(self.builder,
+                                              size_in_bits,
+                                              align_in_bits,
+                                              elem.0,
+                                              subscripts.as_mut_ptr(),
+                                              1))
+    }
+  }
+
+  /// Create a function type. `ret` is `None` for a void function.
+  pub fn create_subroutine_type(&self, file: &DIFile, ret: Option<&DIType>, params: &[&DIType])
+                                -> DIType {
+    let mut tys = Vec::with_capacity(params.len() + 1);
+    tys.push(ret.map_or(ptr::null_mut(), |t| t.0));
+    tys.extend(params.iter().map(|t| t.0));
+
+    DIType(unsafe {
+      di::LLVMDIBuilderCreateSubroutineType(self.builder,
+                                            file.0,
+                                            tys.as_mut_ptr(),
+                                            tys.len() as c_uint,
+                                            LLVMDIFlagZero)
+    })
+  }
+
+  /// Create the debug info of `func`, attach it to `func` and return it.
+  pub fn create_function<S: DIScope>(&self,
+                                     scope: &S,
+                                     func: &Function,
+                                     name: &str,
+                                     file: &DIFile,
+                                     line: usize,
+                                     ty: &DIType,
+                                     optimized: bool)
+                                     -> DISubprogram {
+    let linkage_name = ::value::ValueRef::name(func).unwrap_or(name);
+    let sp = DISubprogram(unsafe {
+      di::LLVMDIBuilderCreateFunction(self.builder,
+                                      scope.as_ref(),
+                                      name.as_ptr() as *const c_char,
+                                      name.len(),
+                                      linkage_name.as_ptr() as *const c_char,
+                                      linkage_name.len(),
+                                      file.0,
+                                      line as c_uint,
+                                      ty.0,
+                                      0,
+                                      1,
+                                      line as c_uint,
+                                      di::LLVMDIFlagPrototyped,
+                                      optimized as i32)
+    });
+
+    func.set_subprogram(&sp);
+    sp
+  }
+
+  /// Create a lexical block within `scope`.
+  pub fn create_lexical_block<S: DIScope>(&self, scope: &S, file: &DIFile, line: usize,
+                                          col: usize) -> DILexicalBlock {
+    DILexicalBlock(unsafe {
+      di::LLVMDIBuilderCreateLexicalBlock(self.builder,
+                                          scope.as_ref(),
+                                          file.0,
+                                          line as c_uint,
+                                          col as c_uint)
+    })
+  }
+
+  /// Create a local variable within `scope`.
+  pub fn create_auto_variable<S: DIScope>(&self,
+                                          scope: &S,
+                                          name: &str,
+                                          file: &DIFile,
+                                          line: usize,
+                                          ty: &DIType)
+                                          -> DIVariable {
+    DIVariable(unsafe {
+      di::LLVMDIBuilderCreateAutoVariable(self.builder,
+                                          scope.as_ref(),
+                                          name.as_ptr() as *const c_char,
+                                          name.len(),
+                                          file.0,
+                                          line as c_uint,
+                                          ty.0,
+                                          1,
+                                          LLVMDIFlagZero,
+                                          0)
+    })
+  }
+
+  /// Create the variable of the `arg_no`-th (zero-based) parameter of a function.
+  pub fn create_parameter_variable<S: DIScope>(&self,
+                                               scope: &S,
+                                               name: &str,
+                                               arg_no: usize,
+                                               file: &DIFile,
+                                               line: usize,
+                                               ty: &DIType)
+                                               -> DIVariable {
+    DIVariable(unsafe {
+      di::LLVMDIBuilderCreateParameterVariable(self.builder,
+                                               scope.as_ref(),
+                                               name.as_ptr() as *const c_char,
+                                               name.len(),
+                                               arg_no as c_uint + 1,
+                                               file.0,
+                                               line as c_uint,
+                                               ty.0,
+                                               1,
+                                               LLVMDIFlagZero)
+    })
+  }
+
+  /// Create a source location within `scope`.
+  pub fn create_location<S: DIScope>(&self, line: usize, col: usize, scope: &S) -> DILocation {
+    DILocation(unsafe {
+      di::LLVMDIBuilderCreateDebugLocation(self.ctx,
+                                           line as c_uint,
+                                           col as c_uint,
+                                           scope.as_ref(),
+                                           ptr::null_mut())
+    })
+  }
+
+  /// Declare that the variable `var` lives in `storage`, which is usually an `alloca`,
+  /// at the end of `block`.
+  pub fn insert_declare_at_end(&self,
+                               storage: &Value,
+                               var: &DIVariable,
+                               loc: &DILocation,
+                               block: &BasicBlock) {
+    unsafe {
+      let expr = di::LLVMDIBuilderCreateExpression(self.builder, ptr::null_mut(), 0);
+      di::LLVMDIBuilderInsertDeclareRecordAtEnd(self.builder,
+                                                storage.0,
+                                                var.0,
+                                                expr,
+                                                loc.0,
+                                                block.0);
+    }
+  }
+}
+
+impl Drop for DebugInfoBuilder {
+  fn drop(&mut self) {
+    self.finalize();
+    unsafe { di::LLVMDisposeDIBuilder(self.builder) }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::{Builder, JitCompiler};
+  use types::LLVMTy;
+  use value::ToValue;
+
+  #[test]
+  fn test_debug_info() {
+    let jit = JitCompiler::new("test_debug_info").ok().unwrap();
+    let ctx = jit.context();
+    let bld = Builder::new(ctx);
+
+    let mut dib = DebugInfoBuilder::new(jit.module());
+    let file = dib.create_file("plan.sql", "/tmp");
+    let cu = dib.create_compile_unit(&file, "llvm-rs", false);
+
+    let i64_di = dib.create_basic_type("int64", 64, DIEncoding::Signed);
+    let fn_di = dib.create_subroutine_type(&file, Some(&i64_di), &[&i64_di]);
+
+    let func = jit.create_func_prototype("plus_one", &i64::llvm_ty(ctx), &[&i64::llvm_ty(ctx)],
+                                         Some(&bld));
+    let sp = dib.create_function(&cu, &func, "plus_one", &file, 1, &fn_di, false);
+    let block = dib.create_lexical_block(&sp, &file, 2, 1);
+
+    let entry = func.get_entry().unwrap();
+    let param = dib.create_parameter_variable(&sp, "x", 0, &file, 1, &i64_di);
+    let local = dib.create_auto_variable(&block, "y", &file, 2, &i64_di);
+
+    bld.set_current_debug_location(Some(&dib.create_location(1, 1, &sp)));
+    let x_ptr = bld.create_alloca(&i64::llvm_ty(ctx));
+    bld.create_store(&func.arg(0).into(), &x_ptr);
+    dib.insert_declare_at_end(&x_ptr, &param, &dib.create_location(1, 1, &sp), &entry);
+
+    bld.set_current_debug_location(Some(&dib.create_location(2, 5, &block)));
+    let y_ptr = bld.create_alloca(&i64::llvm_ty(ctx));
+    let y = bld.create_add(&bld.create_load(&x_ptr), &1i64.to_value(ctx));
+    bld.create_store(&y, &y_ptr);
+    dib.insert_declare_at_end(&y_ptr, &local, &dib.create_location(2, 5, &block), &entry);
+    bld.create_ret(&bld.create_load(&y_ptr));
+    bld.set_current_debug_location(None);
+
+    dib.finalize();
+    jit.verify().unwrap();
+
+    let plus_one: fn(i64) -> i64 = unsafe { ::std::mem::transmute(jit.get_func_ptr(&func).unwrap()) };
+    assert_eq!(2, plus_one(1));
+  }
+}
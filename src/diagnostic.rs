@@ -0,0 +1,206 @@
+//! Diagnostics and Fatal Errors
+//!
+//! LLVM reports diagnostics such as warnings, inline assembly errors and optimization
+//! remarks to a handler of the context, which prints them to stderr and exits on errors
+//! by default. `DiagnosticHandler` hands them to a Rust closure instead.
+//!
+//! Errors LLVM can't recover from go through `report_fatal_error`, which prints them and
+//! exits the process. `install_fatal_error_handler` gives their reason to a logger
+//! instead, before the process exits all the same. Turning them into errors is an opt-in
+//! of `catch_fatal_error`, which unwinds through LLVM's frames. A context in which a fatal
+//! error occurred may be left in any state, so it shouldn't be used afterwards.
+
+use std::cell::Cell;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Mutex, Once};
+
+use libc::{c_char, c_void};
+use llvm_sys::core;
+use llvm_sys::prelude::{LLVMContextRef, LLVMDiagnosticInfoRef};
+use llvm_sys::LLVMDiagnosticSeverity;
+
+use util::chars;
+
+// Extended APIs, offering more APIs than LLVM C API does.
+extern "C" {
+  pub fn LLVMInstallUnwindingFatalErrorHandler(callback: extern "C-unwind" fn(*const c_char));
+}
+
+/// The severity of a diagnostic.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Severity {
+  Error,
+  Warning,
+  Remark,
+  Note,
+}
+
+impl From<LLVMDiagnosticSeverity> for Severity {
+  fn from(severity: LLVMDiagnosticSeverity) -> Severity {
+    match severity {
+      LLVMDiagnosticSeverity::LLVMDSError => Severity::Error,
+      LLVMDiagnosticSeverity::LLVMDSWarning => Severity::Warning,
+      LLVMDiagnosticSeverity::LLVMDSRemark => Severity::Remark,
+      LLVMDiagnosticSeverity::LLVMDSNote => Severity::Note,
+    }
+  }
+}
+
+type Handler = Box<FnMut(Severity, &str)>;
+
+extern "C" fn on_diagnostic(info: LLVMDiagnosticInfoRef, opaque: *mut c_void) {
+  let handler = unsafe { &mut *(opaque as *mut Handler) };
+  unsafe {
+    let description = core::LLVMGetDiagInfoDescription(info);
+    handler(core::LLVMGetDiagInfoSeverity(info).into(), chars::to_str(description));
+    core::LLVMDisposeMessage(description);
+  }
+}
+
+/// A handler of the diagnostics of a context, which is removed when this is dropped.
+///
+/// Errors reported to it don't exit the process, unlike with LLVM's default handler.
+pub struct DiagnosticHandler {
+  ctx: LLVMContextRef,
+  // Referred to by the context, so it must live as long.
+  _handler: Box<Handler>,
+}
+
+impl DiagnosticHandler {
+  /// Hand the severity and message of each diagnostic of `ctx` to `handler`, in place of
+  /// the handler set so far.
+  pub fn new<F>(ctx: LLVMContextRef, handler: F) -> DiagnosticHandler
+    where F: FnMut(Severity, &str) + 'static
+  {
+    let mut handler: Box<Handler> = Box::new(Box::new(handler));
+    let opaque = &mut *handler as *mut Handler as *mut c_void;
+    unsafe { core::LLVMContextSetDiagnosticHandler(ctx, Some(on_diagnostic), opaque) }
+
+    DiagnosticHandler {
+      ctx: ctx,
+      _handler: handler,
+    }
+  }
+}
+
+impl Drop for DiagnosticHandler {
+  fn drop(&mut self) {
+    unsafe { core::LLVMContextSetDiagnosticHandler(self.ctx, None, ::std::ptr::null_mut()) }
+  }
+}
+
+/// An error LLVM couldn't recover from, with the reason given by LLVM.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FatalError(pub String);
+
+impl fmt::Display for FatalError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "LLVM fatal error: {}", self.0)
+  }
+}
+
+type Logger = Box<Fn(&str) + Send + Sync>;
+
+static FATAL_ERROR_LOGGER: Mutex<Option<Logger>> = Mutex::new(None);
+static FATAL_ERROR_HANDLER: Once = Once::new();
+
+thread_local!(static CATCHING_FATAL_ERROR: Cell<bool> = Cell::new(false));
+
+// Returning lets LLVM exit the process, so this only unwinds within `catch_fatal_error`.
+extern "C-unwind" fn on_fatal_error(reason: *const c_char) {
+  let reason = chars::to_str(reason).to_string();
+  let logged = match FATAL_ERROR_LOGGER.lock() {
+    Ok(logger) => logger.as_ref().map(|log| log(&reason)).is_some(),
+    Err(_) => false,
+  };
+  if !logged {
+    eprintln!("LLVM ERROR: {}", reason);
+  }
+
+  if CATCHING_FATAL_ERROR.with(|catching| catching.get()) {
+    panic::panic_any(FatalError(reason));
+  }
+}
+
+fn ensure_fatal_error_handler() {
+  FATAL_ERROR_HANDLER.call_once(|| unsafe {
+    LLVMInstallUnwindingFatalErrorHandler(on_fatal_error)
+  });
+}
+
+/// Give the reason of each fatal error of LLVM to `log` in place of printing it, before
+/// LLVM exits the process. This applies to all threads.
+pub fn install_fatal_error_handler<F>(log: F)
+  where F: Fn(&str) + Send + Sync + 'static
+{
+  if let Ok(mut logger) = FATAL_ERROR_LOGGER.lock() {
+    *logger = Some(Box::new(log));
+  }
+  ensure_fatal_error_handler();
+}
+
+/// Make fatal errors be printed to stderr again, as LLVM does by default.
+pub fn reset_fatal_error_handler() {
+  if let Ok(mut logger) = FATAL_ERROR_LOGGER.lock() {
+    *logger = None;
+  }
+}
+
+/// Run `f`, returning the fatal error which occurred in it on this thread if any, in place
+/// of exiting the process. The error is still logged as usual. Other panics are passed on.
+///
+/// # Safety
+///
+/// The fatal error is raised as a panic which unwinds through LLVM's C++ frames up to
+/// here. LLVM must be built with unwind tables, as it is by default on x86-64, and the
+/// frames unwound must not hold locks or leave shared state half-updated, which holds for
+/// a context, module or builder used only by `f`. These must not be used afterwards.
+pub unsafe fn catch_fatal_error<F, R>(f: F) -> Result<R, FatalError>
+  where F: FnOnce() -> R
+{
+  ensure_fatal_error_handler();
+  let catching = CATCHING_FATAL_ERROR.with(|catching| catching.replace(true));
+  let res = panic::catch_unwind(AssertUnwindSafe(f));
+  CATCHING_FATAL_ERROR.with(|c| c.set(catching));
+
+  match res {
+    Ok(res) => Ok(res),
+    Err(payload) => {
+      match payload.downcast::<FatalError>() {
+        Ok(err) => Err(*err),
+        Err(payload) => panic::resume_unwind(payload),
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use llvm_sys::analysis::LLVMVerifierFailureAction;
+  use analysis::LLVMVerifyFunction2;
+  use super::super::JitCompiler;
+  use types::Ty;
+  use value::ToValue;
+
+  #[test]
+  fn test_catch_fatal_error() {
+    let jit = JitCompiler::new("test_fatal").ok().unwrap();
+    let ctx = jit.context();
+    let bld = jit.new_builder();
+    let func = jit.create_func_prototype("broken", &Ty::void_ty(ctx), &[], Some(&bld));
+    bld.create_ret(&1i32.to_value(ctx));
+
+    let res = unsafe {
+      catch_fatal_error(|| {
+        let mut error = ::std::ptr::null_mut();
+        LLVMVerifyFunction2(func.0, LLVMVerifierFailureAction::LLVMAbortProcessAction, &mut error)
+      })
+    };
+
+    assert!(res.err().unwrap().0.starts_with("Broken function found"));
+    assert_eq!(Ok(1), unsafe { catch_fatal_error(|| 1) });
+    assert!(!CATCHING_FATAL_ERROR.with(|catching| catching.get()));
+  }
+}
@@ -0,0 +1,225 @@
+//! Graphviz Export
+//!
+//! Renders the control flow graph of a function, or the call graph of a module, in the
+//! DOT language of Graphviz, so it can be rendered later with e.g. `dot -Tsvg`, without
+//! `opt -view-cfg` or a display.
+
+use std::collections::HashMap;
+
+use llvm_sys::core;
+use llvm_sys::prelude::LLVMValueRef;
+
+use analysis::{Cfg, DominatorTree};
+use block::BasicBlock;
+use module::Module;
+use value::{Function, ValueRef};
+
+// Escape `s` for a double-quoted label, with each line left-aligned.
+fn escape(s: &str) -> String {
+  let mut escaped = String::with_capacity(s.len());
+  for c in s.chars() {
+    match c {
+      '"' | '\\' => {
+        escaped.push('\\');
+        escaped.push(c);
+      }
+      '\n' => escaped.push_str("\\l"),
+      _ => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+fn block_name(bb: BasicBlock, i: usize) -> String {
+  bb.name().map_or_else(|| format!("#{}", i), |name| name.to_string())
+}
+
+// Returns the label of the edge to the successor `i` of the terminator `term`.
+fn edge_label(term: LLVMValueRef, i: u32) -> Option<&'static str> {
+  unsafe {
+    if !core::LLVMIsABranchInst(term).is_null() && core::LLVMIsConditional(term) != 0 {
+      Some(if i == 0 { "T" } else { "F" })
+    } else if !core::LLVMIsASwitchInst(term).is_null() && i == 0 {
+      Some("default")
+    } else {
+      None
+    }
+  }
+}
+
+/// Returns the control flow graph of `func` in the DOT language, each block listing its
+/// instructions. Edges to loop headers are dashed.
+pub fn function_cfg(func: &Function) -> String {
+  let name = func.name().unwrap_or("");
+  let cfg = Cfg::new(func);
+  let dom = DominatorTree::new(&cfg);
+  let index = cfg.blocks().iter().enumerate().map(|(i, &bb)| (bb, i)).collect::<HashMap<_, _>>();
+
+  let mut dot = format!("digraph \"CFG for '{0}' function\" {{\n  label=\"CFG for '{0}' function\";\n",
+                        escape(name));
+  dot.push_str("  node [shape=box, fontname=\"monospace\"];\n\n");
+
+  for (i, &bb) in cfg.blocks().iter().enumerate() {
+    let mut label = format!("{}:\n", block_name(bb, i));
+    for inst in bb.instructions() {
+      label.push_str(&inst.to_string());
+      label.push('\n');
+    }
+    dot.push_str(&format!("  bb{} [label=\"{}\"];\n", i, escape(&label)));
+  }
+
+  for (i, &bb) in cfg.blocks().iter().enumerate() {
+    let term = match bb.terminator() {
+      Some(term) => term.0,
+      None => continue,
+    };
+    for s in 0..unsafe { core::LLVMGetNumSuccessors(term) } {
+      let succ = BasicBlock(unsafe { core::LLVMGetSuccessor(term, s) });
+      let mut attrs = Vec::new();
+      if let Some(label) = edge_label(term, s) {
+        attrs.push(format!("label=\"{}\"", label));
+      }
+      if dom.dominates(succ, bb) {
+        attrs.push("style=dashed".to_string());
+      }
+
+      dot.push_str(&format!("  bb{} -> bb{}", i, index[&succ]));
+      if !attrs.is_empty() {
+        dot.push_str(&format!(" [{}]", attrs.join(", ")));
+      }
+      dot.push_str(";\n");
+    }
+  }
+
+  dot.push_str("}\n");
+  dot
+}
+
+// Returns the function called by the call or invoke `inst`, or `None` if it isn't one or
+// runs inline assembly, or a null pointer if the call is indirect.
+unsafe fn called_function(inst: LLVMValueRef) -> Option<LLVMValueRef> {
+  if core::LLVMIsACallInst(inst).is_null() && core::LLVMIsAInvokeInst(inst).is_null() {
+    return None;
+  }
+
+  let mut callee = core::LLVMGetCalledValue(inst);
+  if !core::LLVMIsAInlineAsm(callee).is_null() {
+    return None;
+  }
+  // Look through a cast of the function to another signature.
+  if !core::LLVMIsAConstantExpr(callee).is_null() {
+    callee = core::LLVMGetOperand(callee, 0);
+  }
+  Some(core::LLVMIsAFunction(callee))
+}
+
+/// Returns the call graph of `m` in the DOT language. Functions only declared in `m` are
+/// dashed, indirect calls go to a node of their own, and inline assembly isn't drawn.
+pub fn call_graph(m: &Module) -> String {
+  let funcs = m.functions().collect::<Vec<Function>>();
+  let index = funcs.iter().enumerate().map(|(i, f)| (f.0, i)).collect::<HashMap<_, _>>();
+  // The number of calls from each caller to each callee, `None` for indirect calls.
+  let mut calls: Vec<((usize, Option<usize>), usize)> = Vec::new();
+
+  for (i, func) in funcs.iter().enumerate() {
+    for bb in func.blocks() {
+      for inst in bb.instructions() {
+        let callee = match unsafe { called_function(inst.0) } {
+          Some(callee) => index.get(&callee).cloned(),
+          None => continue,
+        };
+        match calls.iter().position(|c| c.0 == (i, callee)) {
+          Some(pos) => calls[pos].1 += 1,
+          None => calls.push(((i, callee), 1)),
+        }
+      }
+    }
+  }
+
+  let mut dot = format!("digraph \"Call graph for '{0}'\" {{\n  label=\"Call graph for '{0}'\";\n",
+                        escape(m.name()));
+  dot.push_str("  node [shape=box];\n\n");
+
+  for (i, func) in funcs.iter().enumerate() {
+    let declaration = unsafe { core::LLVMIsDeclaration(func.0) != 0 };
+    dot.push_str(&format!("  f{} [label=\"{}\"{}];\n",
+                          i,
+                          escape(func.name().unwrap_or("")),
+                          if declaration { ", style=dashed" } else { "" }));
+  }
+  if calls.iter().any(|c| (c.0).1.is_none()) {
+    dot.push_str("  indirect [label=\"<indirect>\", shape=ellipse];\n");
+  }
+  dot.push('\n');
+
+  for ((caller, callee), count) in calls {
+    let callee = callee.map_or_else(|| "indirect".to_string(), |c| format!("f{}", c));
+    dot.push_str(&format!("  f{} -> {}", caller, callee));
+    if count > 1 {
+      dot.push_str(&format!(" [label=\"{}\"]", count));
+    }
+    dot.push_str(";\n");
+  }
+
+  dot.push_str("}\n");
+  dot
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::JitCompiler;
+  use types::{FunctionTy, LLVMTy, Ty};
+  use value::{AsmDialect, Predicate, ToValue};
+
+  #[test]
+  fn test_function_cfg() {
+    let jit = JitCompiler::new("test_dot").ok().unwrap();
+    let ctx = jit.context();
+    let bld = jit.new_builder();
+    let i32_ty = i32::llvm_ty(ctx);
+    let func = jit.create_func_prototype("sign", &i32_ty, &[&i32_ty], Some(&bld));
+    let (neg, pos) = (func.append("neg"), func.append("pos"));
+    let cond = bld.create_cmp(&func.arg(0).into(), &0i32.to_value(ctx), Predicate::Lt);
+    bld.create_cond_br(&cond, &neg, &pos);
+    bld.position_at_end(&neg);
+    bld.create_ret(&(-1i32).to_value(ctx));
+    bld.position_at_end(&pos);
+    bld.create_ret(&1i32.to_value(ctx));
+
+    let dot = func.to_dot();
+    assert!(dot.starts_with("digraph \"CFG for 'sign' function\" {"));
+    assert!(dot.contains("bb1 [label=\"neg:\\l  ret i32 -1\\l\"];"));
+    assert!(dot.contains("bb0 -> bb1 [label=\"T\"];"));
+    assert!(dot.contains("bb0 -> bb2 [label=\"F\"];"));
+  }
+
+  #[test]
+  fn test_call_graph() {
+    let jit = JitCompiler::new("test_dot").ok().unwrap();
+    let ctx = jit.context();
+    let bld = jit.new_builder();
+    let void_ty = Ty::void_ty(ctx);
+    let external = jit.create_func_prototype("external", &void_ty, &[], None);
+    let helper = jit.create_func_prototype("helper", &void_ty, &[], Some(&bld));
+    bld.create_call(&external, &[]);
+    let pause = bld.create_inline_asm(&FunctionTy::new(&void_ty, &[]),
+                                      "pause",
+                                      "",
+                                      true,
+                                      false,
+                                      AsmDialect::Att)
+                   .unwrap();
+    bld.create_asm_call(&pause, &[]);
+    bld.create_ret_void();
+    jit.create_func_prototype("main", &void_ty, &[], Some(&bld));
+    bld.create_call(&helper, &[]);
+    bld.create_call(&helper, &[]);
+    bld.create_ret_void();
+
+    let dot = jit.module().call_graph_dot();
+    assert!(dot.contains("f0 [label=\"external\", style=dashed];"));
+    assert!(dot.contains("f1 -> f0;"));
+    assert!(dot.contains("f2 -> f1 [label=\"2\"];"));
+    assert!(!dot.contains("indirect"));
+  }
+}
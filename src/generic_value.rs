@@ -0,0 +1,54 @@
+//! Generic values for running functions through the execution engine
+
+use libc::{c_ulonglong, c_void};
+use llvm_sys::execution_engine as ee;
+use llvm_sys::execution_engine::LLVMGenericValueRef;
+
+use types::Ty;
+
+/// A value passed to or returned from `JitCompiler::run_function`.
+///
+/// Unlike `Value`, it holds actual data rather than IR. It doesn't remember its type,
+/// so reading it back needs the type for floating-point numbers and signedness for
+/// integers.
+pub struct GenericValue(pub LLVMGenericValueRef);
+impl_dispose!(GenericValue, ee::LLVMDisposeGenericValue);
+
+impl GenericValue {
+  /// Create an integer of the integer type `ty`.
+  pub fn from_int(ty: &Ty, val: u64, signed: bool) -> GenericValue {
+    GenericValue(unsafe {
+      ee::LLVMCreateGenericValueOfInt(ty.0, val as c_ulonglong, signed as i32)
+    })
+  }
+
+  /// Create a floating-point number of the floating-point type `ty`.
+  pub fn from_float(ty: &Ty, val: f64) -> GenericValue {
+    GenericValue(unsafe { ee::LLVMCreateGenericValueOfFloat(ty.0, val) })
+  }
+
+  /// Create a pointer.
+  pub fn from_ptr<T>(ptr: *const T) -> GenericValue {
+    GenericValue(unsafe { ee::LLVMCreateGenericValueOfPointer(ptr as *mut c_void) })
+  }
+
+  /// Returns the bit width of this integer.
+  pub fn int_width(&self) -> usize {
+    unsafe { ee::LLVMGenericValueIntWidth(self.0) as usize }
+  }
+
+  /// Returns the value of this integer, sign-extended if `signed` is true.
+  pub fn to_int(&self, signed: bool) -> u64 {
+    unsafe { ee::LLVMGenericValueToInt(self.0, signed as i32) as u64 }
+  }
+
+  /// Returns the value of this floating-point number of the type `ty`.
+  pub fn to_float(&self, ty: &Ty) -> f64 {
+    unsafe { ee::LLVMGenericValueToFloat(ty.0, self.0) }
+  }
+
+  /// Returns the value of this pointer.
+  pub fn to_ptr<T>(&self) -> *const T {
+    unsafe { ee::LLVMGenericValueToPointer(self.0) as *const T }
+  }
+}
@@ -0,0 +1,71 @@
+//! Host CPU Detection
+//!
+//! Tells the target triple, CPU and features of the machine the process runs on, so the
+//! JIT can generate code for it, e.g. with AVX2 or AVX-512 where they are available,
+//! rather than for a generic CPU of its architecture.
+
+use libc::c_char;
+use llvm_sys::core::LLVMDisposeMessage;
+use llvm_sys::target_machine::{LLVMGetDefaultTargetTriple, LLVMGetHostCPUFeatures,
+                               LLVMGetHostCPUName};
+
+use util::chars;
+
+// Returns the message `msg` as a string, and dispose it.
+unsafe fn take_message(msg: *mut c_char) -> String {
+  let s = chars::to_str(msg).to_string();
+  LLVMDisposeMessage(msg);
+  s
+}
+
+/// Returns the target triple of the host, e.g. `x86_64-unknown-linux-gnu`.
+pub fn triple() -> String {
+  unsafe { take_message(LLVMGetDefaultTargetTriple()) }
+}
+
+/// Returns the name LLVM gives to the host CPU, e.g. `skylake-avx512`, or `generic` if it
+/// is unknown.
+pub fn cpu_name() -> String {
+  unsafe { take_message(LLVMGetHostCPUName()) }
+}
+
+/// Returns the features of the host CPU, as LLVM spells them, e.g. `+avx2,-avx512f`.
+/// Features not listed are left to the defaults of the CPU.
+pub fn cpu_feature_string() -> String {
+  unsafe { take_message(LLVMGetHostCPUFeatures()) }
+}
+
+/// Returns the names of the features the host CPU supports, e.g. `avx2`, sorted.
+pub fn cpu_features() -> Vec<String> {
+  let mut features = cpu_feature_string()
+    .split(',')
+    .filter(|f| f.starts_with('+'))
+    .map(|f| f[1..].to_string())
+    .collect::<Vec<String>>();
+  features.sort();
+  features
+}
+
+/// Returns true if the host CPU supports the feature `name`, e.g. `avx2`.
+pub fn has_feature(name: &str) -> bool {
+  cpu_feature_string().split(',').any(|f| f.starts_with('+') && &f[1..] == name)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_host() {
+    assert!(!triple().is_empty());
+    assert!(!cpu_name().is_empty());
+
+    let features = cpu_features();
+    assert!(features.iter().all(|f| has_feature(f)));
+    assert!(!has_feature("no-such-feature"));
+    if cfg!(target_arch = "x86_64") {
+      assert!(triple().starts_with("x86_64"));
+      assert!(has_feature("sse2"));
+    }
+  }
+}
@@ -5,41 +5,134 @@ extern crate llvm_sys;
 
 #[macro_use]pub mod macros;
 pub mod analysis;
+pub mod attribute;
 pub mod block;
 pub mod buffer;
 pub mod builder;
+pub mod constant;
+pub mod debuginfo;
+pub mod diagnostic;
+pub mod dot;
+pub mod generic_value;
+pub mod host;
+pub mod linker;
+pub mod listener;
+pub mod metadata;
 pub mod module;
+pub mod object_cache;
+pub mod pass;
+pub mod remarks;
+pub mod runtime;
+pub mod service;
+pub mod stats;
+pub mod target;
 pub mod util;
+pub mod tiered;
+pub mod typed;
 pub mod types;
 pub mod value;
 
 // public reimports from llvm_sys;
 pub use llvm_sys::prelude::{LLVMContextRef, LLVMModuleRef, LLVMValueRef};
 
+use std::cell::Cell;
+use std::ffi::CString;
 use std::mem;
 use std::ptr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use llvm_sys::core;
+use llvm_sys::prelude::LLVMBool;
 use llvm_sys::execution_engine::{LLVMAddGlobalMapping, LLVMAddModule,
                                  LLVMCreateMCJITCompilerForModule, LLVMExecutionEngineRef,
-                                 LLVMGetPointerToGlobal, LLVMLinkInMCJIT,
-                                 LLVMMCJITCompilerOptions, LLVMRemoveModule};
+                                 LLVMCreateInterpreterForModule, LLVMGenericValueRef,
+                                 LLVMGetPointerToGlobal, LLVMLinkInInterpreter, LLVMLinkInMCJIT,
+                                 LLVMMCJITCompilerOptions, LLVMRemoveModule, LLVMRunFunction,
+                                 LLVMRunStaticConstructors, LLVMRunStaticDestructors};
 use llvm_sys::target::{LLVM_InitializeNativeAsmPrinter, LLVM_InitializeNativeTarget};
-use llvm_sys::target_machine::LLVMCodeModel;
+use llvm_sys::target_machine::{LLVMCodeModel, LLVMGetDefaultTargetTriple};
 
 use libc::{c_char, c_uint};
 
-pub use analysis::Verifier;
+use object_cache::ObjectCacheBinding;
+
+pub use analysis::{Cfg, Diagnostic, DominatorTree, Lint, Loop, LoopInfo, Verifier};
+pub use attribute::{Attr, AttrIndex, CallConv};
 pub use block::BasicBlock;
 pub use builder::{Builder, CastOp};
+pub use constant::Constant;
+pub use debuginfo::DebugInfoBuilder;
+pub use diagnostic::{catch_fatal_error, DiagnosticHandler, FatalError, install_fatal_error_handler,
+                     reset_fatal_error_handler, Severity};
+pub use generic_value::GenericValue;
+pub use linker::{LinkError, LinkFlags, Linker};
+pub use listener::{JitEvent, JitEventListener, JitFunctionInfo};
+pub use metadata::{LoopHints, MDNode, MDString, Tbaa};
 pub use module::Module;
+pub use object_cache::ObjectCache;
+pub use pass::{CustomPasses, ExtensionPoint, FunctionPass, ModulePass};
+pub use remarks::{Remark, RemarkCollector, RemarkFilter, RemarkKind, RemarkLocation};
+pub use runtime::RuntimeLibrary;
+pub use service::{CompiledFn, CompileService, PendingFn};
+pub use stats::{CompileReport, PassTiming, Statistic};
+pub use target::{FileType, RelocMode, Target, TargetMachine, TargetOptions};
+pub use tiered::{Tier, TieredCompiler, TieredFn};
+pub use typed::{Ptr, TypedBuilder, TypedValue};
 pub use types::{FunctionTy, Ty};
-pub use value::{Arg, delete_func, Function, GlobalValue, Predicate, ToValue, Value, ValueIter, ValueRef};
+pub use value::{Arg, AsmDialect, CallSite, delete_func, Function, GlobalValue, InlineAsm, Instruction, Predicate, ToValue, Value, ValueIter, ValueRef};
 
 use types::{LLVMTy};
 
 pub const JIT_OPT_LVEL: usize = 2;
 
+/// The kind of execution engine running the generated code.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum EngineKind {
+  /// Compile into native machine code.
+  MCJIT,
+  /// Interpret the IR, for machines where native code can't be generated. It is slow,
+  /// and functions can only be called through `JitCompiler::run_function`.
+  Interpreter,
+}
+
+/// Options to create a `JitCompiler`.
+#[derive(Clone, Debug)]
+pub struct JitOptions {
+  /// The execution engine to use.
+  pub engine: EngineKind,
+  /// Optimization level of the code generator, from 0 to 3.
+  pub opt_level: usize,
+  /// Register generated code with GDB.
+  pub gdb_listener: bool,
+  /// Write `perf` map and jitdump files for generated code.
+  pub perf_listener: bool,
+  /// Look up compiled objects in this cache before compiling modules, and store them in it.
+  pub object_cache: Option<Arc<ObjectCache>>,
+  /// Collect the time of each pass and the statistics of LLVM, for all compilers of the
+  /// process from now on, in the reports of `JitCompiler::take_report`. See `stats::enable`.
+  pub compile_stats: bool,
+  /// Generate code for the exact CPU of the host and its features, e.g. AVX2, rather than
+  /// a generic CPU of its architecture. The module gets the triple and data layout of the
+  /// host. The code may not run on other machines, so the CPU and its features are part
+  /// of the keys of `object_cache`.
+  pub host_cpu: bool,
+}
+
+impl Default for JitOptions {
+  fn default() -> JitOptions {
+    JitOptions {
+      engine: EngineKind::MCJIT,
+      opt_level: JIT_OPT_LVEL,
+      gdb_listener: false,
+      perf_listener: false,
+      object_cache: None,
+      compile_stats: false,
+      host_cpu: true,
+    }
+  }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub enum AddressSpace {
@@ -57,24 +150,43 @@ pub trait LLVMRef<T> {
 extern "C" {
   pub fn LLVMVersionMajor() -> u32;
   pub fn LLVMVersionMinor() -> u32;
+  pub fn LLVMCreateMCJITCompilerForModuleWithCPU(out_jit: *mut LLVMExecutionEngineRef,
+                                                 m: LLVMModuleRef,
+                                                 options: *const LLVMMCJITCompilerOptions,
+                                                 cpu: *const c_char,
+                                                 features: *const c_char,
+                                                 out_error: *mut *mut c_char)
+                                                 -> LLVMBool;
 }
 
-fn new_jit_ee(m: &Module, opt_lv: usize) -> Result<LLVMExecutionEngineRef, String> {
+fn new_jit_ee(m: &Module, opts: &JitOptions) -> Result<LLVMExecutionEngineRef, String> {
   // Transfer its ownership to ExecutionEngine.
   unsafe {
     let mut ee: LLVMExecutionEngineRef = mem::uninitialized();
     let mut err: *mut c_char = mem::uninitialized();
 
+    if opts.engine == EngineKind::Interpreter {
+      LLVMLinkInInterpreter();
+      let ret = LLVMCreateInterpreterForModule(&mut ee, m.0, &mut err);
+      return llvm_ret!(ret, ee, err);
+    }
+
     LLVMLinkInMCJIT();
     expect_noerr!(LLVM_InitializeNativeTarget(),
                   "failed to initialize native target");
     expect_noerr!(LLVM_InitializeNativeAsmPrinter(),
                   "failed to initialize native asm printer");
 
-    let mut opts = new_mcjit_compiler_options(opt_lv);
-    let opts_size = mem::size_of::<LLVMMCJITCompilerOptions>();
-
-    let ret = LLVMCreateMCJITCompilerForModule(&mut ee, m.0, &mut opts, opts_size as u64, &mut err);
+    let mut mcjit_opts = new_mcjit_compiler_options(opts.opt_level);
+    let ret = if opts.host_cpu {
+      let cpu = CString::new(host::cpu_name()).unwrap();
+      let features = CString::new(host::cpu_feature_string()).unwrap();
+      LLVMCreateMCJITCompilerForModuleWithCPU(&mut ee, m.0, &mcjit_opts, cpu.as_ptr(),
+                                              features.as_ptr(), &mut err)
+    } else {
+      let opts_size = mem::size_of::<LLVMMCJITCompilerOptions>();
+      LLVMCreateMCJITCompilerForModule(&mut ee, m.0, &mut mcjit_opts, opts_size, &mut err)
+    };
     llvm_ret!(ret, ee, err)
   }
 }
@@ -93,7 +205,19 @@ pub struct JitCompiler {
   ctx: LLVMContextRef,
   module: Module,
   ee: LLVMExecutionEngineRef,
+  engine_kind: EngineKind,
+  opt_level: usize,
+  host_cpu: bool,
   builder: Builder,
+  listeners: Vec<JitEventListener>,
+  object_cache: Option<ObjectCacheBinding>,
+  diagnostic_handler: Option<DiagnosticHandler>,
+
+  // The phases timed since the last report.
+  started: Cell<Instant>,
+  construction: Cell<Option<Duration>>,
+  optimization: Cell<Duration>,
+  codegen: Cell<Duration>,
 
   void_ty: Ty,
   bool_ty: Ty,
@@ -108,36 +232,58 @@ pub struct JitCompiler {
 
 impl JitCompiler {
   pub fn new(module_name: &str) -> Result<JitCompiler, String> {
+    JitCompiler::new_with_options(module_name, &JitOptions::default())
+  }
+
+  pub fn new_with_options(module_name: &str, opts: &JitOptions) -> Result<JitCompiler, String> {
     let ctx = JitCompiler::create_llvm_ctx();
     let module = Module::new(ctx, module_name);
-    JitCompiler::new_internal(ctx, module)
+    JitCompiler::new_internal(ctx, module, opts)
   }
 
   pub fn from_bc(bitcode_path: &str) -> Result<JitCompiler, String> {
     let ctx = JitCompiler::create_llvm_ctx();
     let module = try!(Module::from_bc(ctx, bitcode_path));
-    JitCompiler::new_internal(ctx, module)
+    JitCompiler::new_internal(ctx, module, &JitOptions::default())
   }
 
   pub fn from_module(module: Module) -> Result<JitCompiler, String> {
-    JitCompiler::new_internal(JitCompiler::create_llvm_ctx(), module)
+    JitCompiler::from_module_with_options(module, &JitOptions::default())
+  }
+
+  pub fn from_module_with_options(module: Module, opts: &JitOptions) -> Result<JitCompiler, String> {
+    JitCompiler::new_internal(JitCompiler::create_llvm_ctx(), module, opts)
   }
 
   fn create_llvm_ctx() -> LLVMContextRef {
     unsafe { core::LLVMContextCreate() }
   }
 
-  fn new_internal(ctx: LLVMContextRef, mut module: Module) -> Result<JitCompiler, String> {
+  fn new_internal(ctx: LLVMContextRef,
+                  mut module: Module,
+                  opts: &JitOptions)
+                  -> Result<JitCompiler, String> {
     module.forget();
 
-    let ee = try!(new_jit_ee(&module, JIT_OPT_LVEL));
+    let ee = try!(new_jit_ee(&module, opts));
     let builder = Builder(unsafe { core::LLVMCreateBuilderInContext(ctx) });
 
-    Ok(JitCompiler {
+    let mut jit = JitCompiler {
       ctx: ctx.clone(),
       module: module,
       ee: ee,
+      engine_kind: opts.engine,
+      opt_level: opts.opt_level,
+      host_cpu: opts.host_cpu,
       builder: builder,
+      listeners: Vec::new(),
+      object_cache: None,
+      diagnostic_handler: None,
+
+      started: Cell::new(Instant::now()),
+      construction: Cell::new(None),
+      optimization: Cell::new(Duration::new(0, 0)),
+      codegen: Cell::new(Duration::new(0, 0)),
 
       void_ty: Ty::void_ty(ctx),
       bool_ty: bool::llvm_ty(ctx),
@@ -148,7 +294,22 @@ impl JitCompiler {
       u64_ty: u64::llvm_ty(ctx),
       f32_ty: f32::llvm_ty(ctx),
       f64_ty: f64::llvm_ty(ctx),
-    })
+    };
+
+    if opts.gdb_listener {
+      jit.add_event_listener(try!(JitEventListener::gdb()));
+    }
+    if opts.perf_listener {
+      jit.add_event_listener(try!(JitEventListener::perf()));
+    }
+    if let Some(ref cache) = opts.object_cache {
+      try!(jit.set_object_cache(cache.clone()));
+    }
+    if opts.compile_stats {
+      stats::enable();
+    }
+
+    Ok(jit)
   }
 
   pub fn context(&self) -> LLVMContextRef {
@@ -160,6 +321,9 @@ impl JitCompiler {
   pub fn engine(&self) -> LLVMExecutionEngineRef {
     self.ee
   }
+  pub fn engine_kind(&self) -> EngineKind {
+    self.engine_kind
+  }
   pub fn builder(&self) -> &Builder {
     &self.builder
   }
@@ -179,6 +343,67 @@ impl JitCompiler {
     unsafe { LLVMAddModule(self.ee, m.0) }
   }
 
+  /// Register a listener to be notified when machine code is emitted or freed.
+  ///
+  /// Only code emitted after this call is notified.
+  pub fn add_event_listener(&mut self, listener: JitEventListener) {
+    unsafe { listener::LLVMExecutionEngineRegisterJITEventListener(self.ee, listener.as_ptr()) }
+    self.listeners.push(listener);
+  }
+
+  /// Look up compiled objects in `cache` before compiling modules, and store them in it.
+  ///
+  /// Only modules compiled after this call use the cache. This fails for an interpreter.
+  pub fn set_object_cache(&mut self, cache: Arc<ObjectCache>) -> Result<(), String> {
+    if self.engine_kind == EngineKind::Interpreter {
+      return Err("an interpreter has no compiled objects to cache".to_string());
+    }
+
+    let options = unsafe {
+      let triple = LLVMGetDefaultTargetTriple();
+      // Code generated for the host CPU may not run on other CPUs sharing the cache.
+      let cpu = if self.host_cpu {
+        format!("{} {}", host::cpu_name(), host::cpu_feature_string())
+      } else {
+        "generic".to_string()
+      };
+      let options = format!("{} {} -O{} LLVM {}.{}",
+                            util::chars::to_str(triple),
+                            cpu,
+                            self.opt_level,
+                            LLVMVersionMajor(),
+                            LLVMVersionMinor());
+      core::LLVMDisposeMessage(triple);
+      options
+    };
+
+    // Drop the current binding first, so it doesn't unset the new cache.
+    self.object_cache = None;
+    self.object_cache = Some(ObjectCacheBinding::new(self.ee, cache, options));
+    Ok(())
+  }
+
+  /// Returns the object cache in use, if any.
+  pub fn object_cache(&self) -> Option<&Arc<ObjectCache>> {
+    self.object_cache.as_ref().map(|b| b.cache())
+  }
+
+  /// Hand the severity and message of each diagnostic of the context, e.g. warnings and
+  /// optimization remarks, to `handler` instead of printing them to stderr.
+  ///
+  /// Errors don't exit the process then, unlike with LLVM's default handler.
+  pub fn set_diagnostic_handler<F>(&mut self, handler: F)
+    where F: FnMut(Severity, &str) + 'static
+  {
+    self.diagnostic_handler = None;
+    self.diagnostic_handler = Some(DiagnosticHandler::new(self.ctx, handler));
+  }
+
+  /// Restore the default diagnostic handler of the context.
+  pub fn remove_diagnostic_handler(&mut self) {
+    self.diagnostic_handler = None;
+  }
+
   /// Remove a module from the list of modules to interpret or compile.
   pub fn remove_module(&self, m: &Module) -> LLVMModuleRef {
     unsafe {
@@ -192,7 +417,43 @@ impl JitCompiler {
   ///
   /// This runs passes depending on the levels given.
   pub fn optimize(&self, opt_level: usize, size_level: usize) {
-    self.module.optimize(opt_level, size_level)
+    self.timed(&self.optimization, || self.module.optimize(opt_level, size_level))
+  }
+
+  // Run `f`, adding the time it took to `phase`. Building IR ends with the first phase.
+  fn timed<R, F: FnOnce() -> R>(&self, phase: &Cell<Duration>, f: F) -> R {
+    let start = Instant::now();
+    if self.construction.get().is_none() {
+      self.construction.set(Some(start - self.started.get()));
+    }
+    let res = f();
+    phase.set(phase.get() + start.elapsed());
+    res
+  }
+
+  /// Returns where the time went since the compiler was created or the last report: the
+  /// time spent building IR until the first optimization or code emission, in `optimize`,
+  /// and emitting machine code when pointers to the code are first taken. It has the time
+  /// of each pass and the statistics of LLVM if `JitOptions::compile_stats` is set.
+  ///
+  /// Pass timings and statistics are process-wide, so they include those of the other
+  /// compilers since any of them took a report.
+  pub fn take_report(&self) -> CompileReport {
+    let report = if stats::is_enabled() {
+      CompileReport::take()
+    } else {
+      CompileReport::default()
+    };
+    let construction = self.construction.get().unwrap_or_else(|| self.started.get().elapsed());
+
+    self.started.set(Instant::now());
+    self.construction.set(None);
+    CompileReport {
+      construction: construction,
+      optimization: self.optimization.replace(Duration::new(0, 0)),
+      codegen: self.codegen.replace(Duration::new(0, 0)),
+      ..report
+    }
   }
 
   /// Verify that the module is safe to run, returning a string detailing the error
@@ -283,7 +544,8 @@ impl JitCompiler {
   /// This is marked as unsafe because the type cannot be guranteed to be the same as the
   /// type of the global value at this point.
   pub unsafe fn get_ptr_to_global<T>(&self, global: &Value) -> *const T {
-    mem::transmute(LLVMGetPointerToGlobal(self.ee, global.0))
+    // MCJIT emits the machine code of its modules the first time.
+    self.timed(&self.codegen, || mem::transmute(LLVMGetPointerToGlobal(self.ee, global.0)))
   }
 
   /// Maps a global to a specific memory location.
@@ -313,11 +575,45 @@ impl JitCompiler {
     self.module.create_func_prototype(name, ret_ty, param_tys, builder)
   }
 
-  /// Returns a pointer to the machine code for the raw function poionter.
+  /// Runs the functions in `llvm.global_ctors` of all modules, e.g. the static
+  /// initializers of C++ code compiled by clang or those added by `Module::add_global_ctor`.
+  /// Machine code is emitted first if it isn't yet, and counted as code generation time.
+  ///
+  /// This is marked as unsafe because the constructors run arbitrary code, and nothing
+  /// prevents running them more than once.
+  pub unsafe fn run_static_constructors(&self) {
+    self.timed(&self.codegen, || LLVMRunStaticConstructors(self.ee))
+  }
+
+  /// Runs the functions in `llvm.global_dtors` of all modules.
+  ///
+  /// They are not run when the `JitCompiler` is dropped, so this should be called once the
+  /// generated code is no longer used.
+  pub unsafe fn run_static_destructors(&self) {
+    LLVMRunStaticDestructors(self.ee)
+  }
+
+  /// Runs `func` with the arguments given and returns its result.
+  ///
+  /// This works with either engine, but MCJIT only supports a few signatures: functions
+  /// taking no arguments, `main`-like functions, and functions taking a single `i32`.
+  ///
+  /// This is marked as unsafe because the arguments cannot be guaranteed to match the
+  /// signature of `func`.
+  pub unsafe fn run_function(&self, func: &Function, args: &[GenericValue]) -> GenericValue {
+    let mut arg_refs = args.iter().map(|a| a.0).collect::<Vec<LLVMGenericValueRef>>();
+    GenericValue(LLVMRunFunction(self.ee, func.0, arg_refs.len() as c_uint, arg_refs.as_mut_ptr()))
+  }
+
+  /// Returns a pointer to the machine code for the raw function poionter, or `None` if
+  /// it is not compiled or the engine is an interpreter.
   ///
   /// This is marked as unsafe because the defined function signature and
   /// return could be different from their internal representation.
   pub unsafe fn get_func_ptr(&self, func: &Function) -> Option<*const ()> {
+    if self.engine_kind == EngineKind::Interpreter {
+      return None;
+    }
 
     let ptr: *const u8 = self.get_ptr_to_global(&func.into());
 
@@ -332,6 +628,11 @@ impl JitCompiler {
 impl Drop for JitCompiler {
   fn drop(&mut self) {
     unsafe {
+      for l in self.listeners.drain(..) {
+        listener::LLVMExecutionEngineUnregisterJITEventListener(self.ee, l.as_ptr());
+      }
+      self.object_cache = None;
+      self.diagnostic_handler = None;
       core::LLVMContextDispose(self.ctx);
     }
   }
@@ -432,6 +733,55 @@ mod tests {
     }
   }
 
+  #[test]
+  fn test_func_specialize() {
+    let jit = JitCompiler::new("test_jit").ok().unwrap();
+    let ctx = jit.context();
+    let bld = &jit.new_builder();
+    let u64_ty = u64::llvm_ty(ctx);
+
+    let callee = jit.create_func_prototype("add_one", &u64_ty, &[&u64_ty], Some(bld));
+    bld.create_ret(&bld.create_add(&callee.arg(0).into(), &1u64.to_value(ctx)));
+    let caller = jit.create_func_prototype("twice", &u64_ty, &[&u64_ty], Some(bld));
+    let once = bld.create_call(&callee, &[&caller.arg(0).into()]);
+    bld.create_ret(&bld.create_call(&callee, &[&once]));
+    jit.verify().unwrap();
+
+    let twice: fn(u64) -> u64 = unsafe { ::std::mem::transmute(jit.get_func_ptr(&caller).unwrap()) };
+    assert_eq!(3, twice(1));
+
+    // A variant is built in its own module, so the code running already is left alone.
+    let mut variant_module = jit.module().extract_functions(&["twice"]).unwrap();
+    let variant = variant_module.get_func("twice").unwrap();
+    let variant_callee = variant_module.get_func("add_one").unwrap();
+    assert!(unsafe { core::LLVMIsDeclaration(variant_callee.0) } != 0);
+    variant.set_name("twice_v2");
+    variant_module.verify().unwrap();
+
+    jit.add_module(&variant_module);
+    variant_module.forget();
+    let twice_v2: fn(u64) -> u64 = unsafe { ::std::mem::transmute(jit.get_func_ptr(&variant).unwrap()) };
+    assert_eq!(12, twice_v2(10));
+    assert_eq!(3, twice(1));
+
+    assert!(jit.module().extract_functions(&["missing"]).is_err());
+    let other = Module::new(ctx, "other");
+    assert!(variant.clone_into(&other).is_ok());
+    assert!(variant.clone_into(&other).is_err());
+  }
+
+  #[test]
+  fn test_clone_module() {
+    let ctx = JitCompiler::create_llvm_ctx();
+    let module = Module::new(ctx, "original");
+    module.create_func_prototype("f", &Ty::void_ty(ctx), &[], None);
+
+    let copy = module.clone_module();
+    copy.create_func_prototype("g", &Ty::void_ty(ctx), &[], None);
+    assert!(copy.get_func("f").is_some());
+    assert!(module.get_func("g").is_none());
+  }
+
   #[test]
   fn test_global_mapping() {
     let jit = JitCompiler::new("test_jit").ok().unwrap();
@@ -455,6 +805,246 @@ mod tests {
     println!("after verify");
   }
 
+  #[test]
+  fn test_event_listener() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut jit = JitCompiler::new("test_jit").ok().unwrap();
+    let ctx = jit.context();
+
+    let emitted = Rc::new(RefCell::new(Vec::new()));
+    let emitted_clone = emitted.clone();
+    jit.add_event_listener(JitEventListener::callback(move |event, info| {
+      assert_eq!(JitEvent::Emitted, event);
+      assert!(info.addr != 0 && info.size > 0);
+      emitted_clone.borrow_mut().push(info.name.to_string());
+    }));
+
+    let bld = &jit.new_builder();
+    let func = jit.create_func_prototype("listened", &u64::llvm_ty(ctx), &[&u64::llvm_ty(ctx)],
+                                         Some(bld));
+    bld.create_ret(&func.arg(0).into());
+    unsafe { jit.get_func_ptr(&func).unwrap() };
+
+    assert!(emitted.borrow().iter().any(|name| name.ends_with("listened")));
+  }
+
+  #[test]
+  fn test_abi_bool() {
+    let jit = JitCompiler::new("test_jit").ok().unwrap();
+    let ctx = jit.context();
+    let bld = &jit.new_builder();
+
+    let abi_bool = Ty::abi_bool_ty(ctx);
+    let func = jit.create_func_prototype("negate", &abi_bool, &[&abi_bool], Some(bld));
+    let b = bld.create_abi_to_bool(&func.arg(0).into());
+    assert!(b.ty() == *jit.get_bool_ty());
+    let negated = bld.create_xor(&b, &true.to_value(ctx));
+    bld.create_ret(&bld.create_bool_to_abi(&negated));
+    jit.verify().unwrap();
+
+    let negate: fn(bool) -> bool = unsafe { ::std::mem::transmute(jit.get_func_ptr(&func).unwrap()) };
+    assert_eq!(false, negate(true));
+    assert_eq!(true, negate(false));
+  }
+
+  fn build_double(jit: &JitCompiler) -> Function {
+    let ctx = jit.context();
+    let bld = &jit.new_builder();
+    let func = jit.create_func_prototype("double", &i32::llvm_ty(ctx), &[&i32::llvm_ty(ctx)],
+                                         Some(bld));
+    bld.create_ret(&bld.create_mul(&func.arg(0).into(), &2i32.to_value(ctx)));
+    func
+  }
+
+  #[test]
+  fn test_run_function() {
+    let interp_opts = JitOptions { engine: EngineKind::Interpreter, ..JitOptions::default() };
+
+    for opts in &[JitOptions::default(), interp_opts] {
+      let jit = JitCompiler::new_with_options("test_jit", opts).ok().unwrap();
+      let func = build_double(&jit);
+      jit.verify().unwrap();
+
+      let i32_ty = i32::llvm_ty(jit.context());
+      let ret = unsafe { jit.run_function(&func, &[GenericValue::from_int(&i32_ty, -21i64 as u64, true)]) };
+      assert_eq!(-42, ret.to_int(true) as i64);
+      assert_eq!(32, ret.int_width());
+    }
+  }
+
+  #[test]
+  fn test_interpreter_has_no_func_ptr() {
+    let opts = JitOptions { engine: EngineKind::Interpreter, ..JitOptions::default() };
+    let jit = JitCompiler::new_with_options("test_jit", &opts).ok().unwrap();
+    let func = build_double(&jit);
+
+    assert_eq!(EngineKind::Interpreter, jit.engine_kind());
+    assert!(unsafe { jit.get_func_ptr(&func) }.is_none());
+  }
+
+  #[test]
+  fn test_host_cpu() {
+    let jit = JitCompiler::new("test_jit").ok().unwrap();
+    assert!(!jit.target().is_empty());
+    assert!(!jit.data_layout().is_empty());
+    let func = build_double(&jit);
+    assert!(unsafe { jit.get_func_ptr(&func) }.is_some());
+
+    let opts = JitOptions { host_cpu: false, ..JitOptions::default() };
+    let generic = JitCompiler::new_with_options("test_jit", &opts).ok().unwrap();
+    assert!(generic.target().is_empty());
+  }
+
+  #[test]
+  fn test_static_constructors() {
+    let jit = JitCompiler::new("test_jit").ok().unwrap();
+    let ctx = jit.context();
+    let bld = jit.new_builder();
+
+    let counter = jit.add_global("counter", &i64::llvm_ty(ctx));
+    counter.set_initializer(&0i64.to_value(ctx));
+    let counter_val = Value::from(&counter);
+
+    // Each structor computes counter * 10 + digit, so the final value records their order.
+    let structors = [("ctor_b", 2i64, 200), ("ctor_a", 1i64, 100), ("dtor_low", 3i64, 0),
+                     ("dtor_high", 4i64, 10)];
+    for &(name, digit, priority) in &structors {
+      let func = jit.create_func_prototype(name, &Ty::void_ty(ctx), &[], Some(&bld));
+      let old = bld.create_load(&counter_val);
+      let new = bld.create_add(&bld.create_mul(&old, &10i64.to_value(ctx)), &digit.to_value(ctx));
+      bld.create_store(&new, &counter_val);
+      bld.create_ret_void();
+
+      if name.starts_with("dtor") {
+        jit.module().add_global_dtor(&func, priority);
+      } else {
+        jit.module().add_global_ctor(&func, priority);
+      }
+    }
+    jit.verify().unwrap();
+
+    unsafe {
+      let counter_ptr: *const i64 = jit.get_ptr_to_global(&counter_val);
+      jit.run_static_constructors();
+      assert_eq!(12, *counter_ptr);
+      jit.run_static_destructors();
+      assert_eq!(1243, *counter_ptr);
+    }
+  }
+
+  #[test]
+  fn test_verifier_diagnostics() {
+    let jit = JitCompiler::new("test_jit").ok().unwrap();
+    let ctx = jit.context();
+    let bld = jit.new_builder();
+    jit.create_func_prototype("valid", &Ty::void_ty(ctx), &[], Some(&bld));
+    bld.create_ret_void();
+    let func = jit.create_func_prototype("broken", &Ty::void_ty(ctx), &[], Some(&bld));
+    let ret = bld.create_ret(&1i32.to_value(ctx));
+
+    assert!(Verifier::diagnose_func(&jit.get_func("valid").unwrap()).is_empty());
+    let diags = Verifier::diagnose_module(jit.module().0);
+    assert_eq!(1, diags.len());
+    assert!(diags[0].message.starts_with("Function return type does not match"));
+    assert_eq!(Some("broken".to_string()), diags[0].function);
+    assert_eq!(Some("entry".to_string()), diags[0].block);
+    assert_eq!(Some(ret.0), diags[0].instruction.map(|i| i.0));
+    assert!(diags[0].to_string().contains("in block entry of function broken"));
+    assert_eq!(diags.len(), Verifier::diagnose_func(&func).len());
+  }
+
+  #[test]
+  fn test_lint() {
+    let jit = JitCompiler::new("test_jit").ok().unwrap();
+    let ctx = jit.context();
+    let bld = jit.new_builder();
+    let i32_ty = i32::llvm_ty(ctx);
+    let func = jit.create_func_prototype("div_zero", &i32_ty, &[&i32_ty], Some(&bld));
+    let div = bld.create_div(&func.arg(0).into(), &0i32.to_value(ctx));
+    bld.create_ret(&div);
+    jit.verify().unwrap();
+
+    let diags = jit.module().lint();
+    let diag = diags.iter().find(|d| d.message.contains("Division by zero")).unwrap();
+    assert_eq!(Some("div_zero".to_string()), diag.function);
+    assert_eq!(Some(div.0), diag.instruction.map(|i| i.0));
+    assert_eq!(diags.len(), func.lint().len());
+  }
+
+  #[test]
+  fn test_lint_ignores_stderr() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let jit = JitCompiler::new("test_jit").ok().unwrap();
+    let ctx = jit.context();
+    let bld = jit.new_builder();
+    let i32_ty = i32::llvm_ty(ctx);
+    let func = jit.create_func_prototype("div_one", &i32_ty, &[&i32_ty], Some(&bld));
+    bld.create_ret(&bld.create_div(&func.arg(0).into(), &1i32.to_value(ctx)));
+
+    let done = Arc::new(AtomicBool::new(false));
+    let writer = {
+      let done = done.clone();
+      ::std::thread::spawn(move || while !done.load(Ordering::Relaxed) {
+        eprintln!("Not a lint finding");
+      })
+    };
+    for _ in 0..100 {
+      assert!(func.lint().is_empty());
+    }
+    done.store(true, Ordering::Relaxed);
+    writer.join().unwrap();
+  }
+
+  #[test]
+  fn test_cfg_analysis() {
+    let jit = JitCompiler::new("test_jit").ok().unwrap();
+    let ctx = jit.context();
+    let bld = jit.new_builder();
+    let i32_ty = i32::llvm_ty(ctx);
+    let func = jit.create_func_prototype("count", &Ty::void_ty(ctx), &[&i32_ty], Some(&bld));
+    let entry = func.get_entry().unwrap();
+    let (header, body, exit) = (func.append("header"), func.append("body"), func.append("exit"));
+    bld.create_br(&header);
+    bld.position_at_end(&header);
+    let cond = bld.create_cmp(&func.arg(0).into(), &0i32.to_value(ctx), Predicate::Lt);
+    bld.create_cond_br(&cond, &body, &exit);
+    bld.position_at_end(&body);
+    bld.create_br(&header);
+    bld.position_at_end(&exit);
+    bld.create_ret_void();
+    jit.verify().unwrap();
+
+    let cfg = Cfg::new(&func);
+    assert_eq!(vec![entry, body], cfg.predecessors(header));
+    assert_eq!(vec![body, exit], cfg.successors(header));
+    assert_eq!(vec![entry, header, exit, body], cfg.reverse_post_order());
+
+    let dom = DominatorTree::new(&cfg);
+    assert!(dom.dominates(header, body) && dom.dominates(header, exit));
+    assert!(!dom.dominates(body, exit));
+    assert_eq!(Some(header), dom.idom(exit));
+    assert_eq!(None, dom.idom(entry));
+
+    let post_dom = DominatorTree::new_post(&cfg);
+    assert!(post_dom.dominates(exit, entry) && post_dom.dominates(header, body));
+    assert!(!post_dom.dominates(body, header));
+    assert_eq!(Some(header), post_dom.idom(entry));
+
+    let loops = LoopInfo::new(&cfg, &dom);
+    assert_eq!(1, loops.loops().len());
+    let l = &loops.loops()[0];
+    assert_eq!(header, l.header());
+    assert_eq!(&[body], l.latches());
+    assert_eq!(&[header, body], l.blocks());
+    assert_eq!(&[exit], l.exits());
+    assert_eq!(1, loops.depth(body));
+    assert!(loops.loop_for(entry).is_none());
+  }
+
   #[test]
   fn test_version() {
     assert!(unsafe { LLVMVersionMajor() } >= 3);
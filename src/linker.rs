@@ -0,0 +1,331 @@
+//! Module Linking
+//!
+//! `Module::link_in` links a single module, while `Linker` links several modules into
+//! one and collects the errors of all of them, so every conflicting symbol can be
+//! reported at once.
+
+use std::fmt;
+use std::mem;
+
+use libc::{c_char, c_int, c_uint, c_void};
+use llvm_sys::core;
+use llvm_sys::prelude::{LLVMBool, LLVMModuleRef, LLVMValueRef};
+use llvm_sys::LLVMLinkage;
+
+use module::Module;
+use util::chars;
+
+// Extended APIs, offering more APIs than LLVM C API does.
+extern "C" {
+  pub fn LLVMLinkModulesWithFlags(dest: LLVMModuleRef,
+                                  src: LLVMModuleRef,
+                                  flags: c_uint,
+                                  callback: Option<extern "C" fn(*mut c_void, *const c_char)
+                                                                 -> c_int>,
+                                  opaque: *mut c_void,
+                                  out_message: *mut *mut c_char)
+                                  -> LLVMBool;
+  pub fn LLVMDropModuleContents(m: LLVMModuleRef);
+}
+
+/// Flags controlling how a module is linked.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct LinkFlags {
+  /// Definitions in the source module replace those in the destination module, instead
+  /// of conflicting with them.
+  pub override_from_src: bool,
+  /// Only link the definitions the destination module declares, and what they use.
+  pub link_only_needed: bool,
+}
+
+impl LinkFlags {
+  // The values of `llvm::Linker::Flags`.
+  fn bits(&self) -> c_uint {
+    (self.override_from_src as c_uint) | ((self.link_only_needed as c_uint) << 1)
+  }
+}
+
+/// An error linking a module.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LinkError {
+  /// The source module defines `symbols` which the destination module defines too.
+  Conflict { module: String, symbols: Vec<String> },
+  /// LLVM failed to link the source module, for the reason given in `message`.
+  Failed { module: String, message: String },
+}
+
+impl fmt::Display for LinkError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      LinkError::Conflict { ref module, ref symbols } => {
+        write!(f, "{}: symbols defined more than once: {}", module, symbols.join(", "))
+      }
+      LinkError::Failed { ref module, ref message } => write!(f, "{}: {}", module, message),
+    }
+  }
+}
+
+extern "C" fn should_internalize(opaque: *mut c_void, name: *const c_char) -> c_int {
+  let callback = unsafe { &mut *(opaque as *mut &mut FnMut(&str) -> bool) };
+  callback(chars::to_str(name)) as c_int
+}
+
+// Returns true if `global` is a definition which can't be merged with another one.
+unsafe fn is_strong_definition(global: LLVMValueRef) -> bool {
+  core::LLVMIsDeclaration(global) == 0 &&
+  core::LLVMGetLinkage(global) == LLVMLinkage::LLVMExternalLinkage
+}
+
+// Returns the names of the definitions in `src` which conflict with those in `dest`.
+unsafe fn conflicting_symbols(dest: LLVMModuleRef, src: LLVMModuleRef) -> Vec<String> {
+  let mut conflicts = Vec::new();
+  let mut check = |global: LLVMValueRef| {
+    if !is_strong_definition(global) {
+      return;
+    }
+    let name = core::LLVMGetValueName(global);
+    let other = match core::LLVMGetNamedFunction(dest, name) {
+      func if !func.is_null() => func,
+      _ => core::LLVMGetNamedGlobal(dest, name),
+    };
+    if !other.is_null() && is_strong_definition(other) {
+      conflicts.push(chars::to_str(name).to_string());
+    }
+  };
+
+  let mut func = core::LLVMGetFirstFunction(src);
+  while !func.is_null() {
+    check(func);
+    func = core::LLVMGetNextFunction(func);
+  }
+  let mut global = core::LLVMGetFirstGlobal(src);
+  while !global.is_null() {
+    check(global);
+    global = core::LLVMGetNextGlobal(global);
+  }
+
+  conflicts
+}
+
+/// Link `src` into `dest`, making the linked symbols for which `internalize` returns true
+/// internal to `dest`.
+pub fn link_modules(dest: &Module,
+                    mut src: Module,
+                    flags: LinkFlags,
+                    internalize: Option<&mut FnMut(&str) -> bool>)
+                    -> Result<(), LinkError> {
+  let module = src.name().to_string();
+
+  unsafe {
+    if core::LLVMGetModuleContext(dest.0) != core::LLVMGetModuleContext(src.0) {
+      return Err(LinkError::Failed {
+        module: module,
+        message: "the modules belong to different contexts".to_string(),
+      });
+    }
+
+    if !flags.override_from_src && !flags.link_only_needed {
+      let symbols = conflicting_symbols(dest.0, src.0);
+      if !symbols.is_empty() {
+        return Err(LinkError::Conflict {
+          module: module,
+          symbols: symbols,
+        });
+      }
+    }
+
+    // Linking consumes the source module, so link a copy if `src` doesn't own its module.
+    let src_ref = if src.1 {
+      src.forget();
+      src.0
+    } else {
+      core::LLVMCloneModule(src.0)
+    };
+
+    let mut message: *mut c_char = mem::uninitialized();
+    let failed = match internalize {
+      Some(mut callback) => {
+        let opaque = &mut callback as *mut &mut FnMut(&str) -> bool as *mut c_void;
+        LLVMLinkModulesWithFlags(dest.0, src_ref, flags.bits(), Some(should_internalize), opaque,
+                                 &mut message)
+      }
+      None => LLVMLinkModulesWithFlags(dest.0, src_ref, flags.bits(), None,
+                                       ::std::ptr::null_mut(), &mut message),
+    };
+
+    if failed != 0 {
+      let message_str = chars::to_str(message).to_string();
+      ::libc::free(message as *mut c_void);
+      Err(LinkError::Failed {
+        module: module,
+        message: message_str,
+      })
+    } else {
+      Ok(())
+    }
+  }
+}
+
+/// Links several modules into a destination module.
+///
+/// A module which fails to link doesn't stop the others from being linked; the errors
+/// of all of them are returned by `finish`.
+pub struct Linker<'a> {
+  dest: &'a Module,
+  flags: LinkFlags,
+  internalize: Option<Box<FnMut(&str) -> bool + 'a>>,
+  errors: Vec<LinkError>,
+}
+
+impl<'a> Linker<'a> {
+  pub fn new(dest: &'a Module) -> Linker<'a> {
+    Linker {
+      dest: dest,
+      flags: LinkFlags::default(),
+      internalize: None,
+      errors: Vec::new(),
+    }
+  }
+
+  /// Set the flags used for the modules added from now on.
+  pub fn set_flags(&mut self, flags: LinkFlags) {
+    self.flags = flags;
+  }
+
+  /// Make the symbols linked from now on internal to the destination module if
+  /// `internalize` returns true for their names.
+  pub fn set_internalize<F>(&mut self, internalize: F)
+    where F: FnMut(&str) -> bool + 'a
+  {
+    self.internalize = Some(Box::new(internalize));
+  }
+
+  /// Link `src` into the destination module, returning true if it succeeded.
+  pub fn add(&mut self, src: Module) -> bool {
+    let internalize = self.internalize.as_mut().map(|f| &mut **f as &mut FnMut(&str) -> bool);
+
+    match link_modules(self.dest, src, self.flags, internalize) {
+      Ok(()) => true,
+      Err(e) => {
+        self.errors.push(e);
+        false
+      }
+    }
+  }
+
+  /// Returns the errors so far.
+  pub fn errors(&self) -> &[LinkError] {
+    &self.errors
+  }
+
+  /// Returns the errors of all modules added, if any.
+  pub fn finish(self) -> Result<(), Vec<LinkError>> {
+    if self.errors.is_empty() {
+      Ok(())
+    } else {
+      Err(self.errors)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::JitCompiler;
+  use types::{LLVMTy, Ty};
+  use value::{ToValue, ValueRef};
+
+  fn define(m: &Module, name: &str) {
+    let ctx = unsafe { core::LLVMGetModuleContext(m.0) };
+    let bld = ::builder::Builder::new(ctx);
+    m.create_func_prototype(name, &Ty::void_ty(ctx), &[], Some(&bld));
+    bld.create_ret_void();
+  }
+
+  fn declare(m: &Module, name: &str) {
+    let ctx = unsafe { core::LLVMGetModuleContext(m.0) };
+    m.create_func_prototype(name, &Ty::void_ty(ctx), &[], None);
+  }
+
+  #[test]
+  fn test_linker_conflicts() {
+    let jit = JitCompiler::new("dest").ok().unwrap();
+    let ctx = jit.context();
+    define(jit.module(), "a");
+
+    let m1 = Module::new(ctx, "m1");
+    define(&m1, "a");
+    define(&m1, "b");
+    let m2 = Module::new(ctx, "m2");
+    define(&m2, "c");
+
+    let mut linker = Linker::new(jit.module());
+    assert!(!linker.add(m1));
+    assert!(linker.add(m2));
+    assert_eq!(Err(vec![LinkError::Conflict {
+                 module: "m1".to_string(),
+                 symbols: vec!["a".to_string()],
+               }]),
+               linker.finish());
+    assert!(jit.get_func("b").is_none());
+    assert!(jit.get_func("c").is_some());
+  }
+
+  #[test]
+  fn test_link_only_needed_internalize() {
+    let jit = JitCompiler::new("dest").ok().unwrap();
+    let ctx = jit.context();
+    declare(jit.module(), "needed");
+
+    let lib = Module::new(ctx, "lib");
+    define(&lib, "needed");
+    define(&lib, "unused");
+    lib.add_global("table", &i32::llvm_ty(ctx)).set_initializer(&1i32.to_value(ctx));
+
+    let mut linked = Vec::new();
+    {
+      let mut linker = Linker::new(jit.module());
+      linker.set_flags(LinkFlags { link_only_needed: true, ..LinkFlags::default() });
+      linker.set_internalize(|name| {
+        linked.push(name.to_string());
+        true
+      });
+      assert!(linker.add(lib));
+      linker.finish().unwrap();
+    }
+
+    assert_eq!(vec!["needed".to_string()], linked);
+    let needed = jit.get_func("needed").unwrap();
+    unsafe {
+      assert_eq!(0, core::LLVMIsDeclaration(needed.0));
+      assert_eq!(LLVMLinkage::LLVMInternalLinkage, core::LLVMGetLinkage(needed.0));
+    }
+    assert!(jit.get_func("unused").is_none());
+    assert!(jit.get_global("table").is_none());
+    assert_eq!(Some("needed"), needed.name());
+  }
+
+  #[test]
+  fn test_override_from_src() {
+    let jit = JitCompiler::new("dest").ok().unwrap();
+    define(jit.module(), "a");
+
+    let m = Module::new(jit.context(), "m");
+    define(&m, "a");
+    let flags = LinkFlags { override_from_src: true, ..LinkFlags::default() };
+    jit.module().link_in(m, flags).unwrap();
+    assert!(jit.get_func("a").is_some());
+  }
+
+  #[test]
+  fn test_link_destroy() {
+    let jit = JitCompiler::new("dest").ok().unwrap();
+    let m = Module::new(jit.context(), "m");
+    define(&m, "a");
+
+    jit.module().link_destroy(&m).unwrap();
+    assert!(jit.get_func("a").is_some());
+    assert!(m.get_func("a").is_none());
+    m.verify().unwrap();
+  }
+}
@@ -0,0 +1,128 @@
+//! JIT Event Listeners
+//!
+//! Listeners are notified when the execution engine emits or frees machine code.
+//! LLVM's own listeners register generated code with GDB or write `perf` map and
+//! jitdump files, while `JitEventListener::callback` hands the name, address and
+//! size of each function to a Rust closure.
+
+use std::ffi::CStr;
+
+use libc::{c_char, c_int, c_void};
+use llvm_sys::execution_engine::{self as ee, LLVMExecutionEngineRef};
+use llvm_sys::prelude::LLVMJITEventListenerRef;
+
+// Extended APIs, offering more APIs than LLVM C API does.
+extern "C" {
+  pub fn LLVMExecutionEngineRegisterJITEventListener(ee: LLVMExecutionEngineRef,
+                                                     listener: LLVMJITEventListenerRef);
+  pub fn LLVMExecutionEngineUnregisterJITEventListener(ee: LLVMExecutionEngineRef,
+                                                       listener: LLVMJITEventListenerRef);
+  pub fn LLVMCreateCallbackJITEventListener(callback: extern "C" fn(*mut c_void,
+                                                                    c_int,
+                                                                    *const c_char,
+                                                                    u64,
+                                                                    u64),
+                                            opaque: *mut c_void)
+                                            -> LLVMJITEventListenerRef;
+  pub fn LLVMDisposeCallbackJITEventListener(listener: LLVMJITEventListenerRef);
+}
+
+/// What happened to a generated function.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum JitEvent {
+  /// The machine code of the function was emitted.
+  Emitted,
+  /// The machine code of the function is about to be freed.
+  Freed,
+}
+
+/// A generated function the execution engine emitted or freed.
+#[derive(Clone, Debug)]
+pub struct JitFunctionInfo<'a> {
+  pub name: &'a str,
+  pub addr: usize,
+  pub size: usize,
+}
+
+type Callback = Box<FnMut(JitEvent, &JitFunctionInfo)>;
+
+/// A listener of code emission in a `JitCompiler`.
+pub struct JitEventListener {
+  listener: LLVMJITEventListenerRef,
+  // Only set for callback listeners, which are owned by this crate. The others are
+  // singletons owned by LLVM.
+  callback: Option<Box<Callback>>,
+}
+
+extern "C" fn on_event(opaque: *mut c_void,
+                       kind: c_int,
+                       name: *const c_char,
+                       addr: u64,
+                       size: u64) {
+  let callback = unsafe { &mut *(opaque as *mut Callback) };
+  let event = if kind == 0 {
+    JitEvent::Emitted
+  } else {
+    JitEvent::Freed
+  };
+  let name = unsafe { CStr::from_ptr(name) };
+
+  callback(event,
+           &JitFunctionInfo {
+             name: &name.to_string_lossy(),
+             addr: addr as usize,
+             size: size as usize,
+           });
+}
+
+impl JitEventListener {
+  /// Returns the listener registering generated code with GDB, so it can show symbols and
+  /// source locations of generated functions.
+  pub fn gdb() -> Result<JitEventListener, String> {
+    JitEventListener::from_llvm(unsafe { ee::LLVMCreateGDBRegistrationListener() }, "GDB")
+  }
+
+  /// Returns the listener writing `/tmp/perf-<pid>.map` and jitdump files, so `perf` can
+  /// attribute samples to generated functions.
+  ///
+  /// This fails if LLVM was built without perf support.
+  pub fn perf() -> Result<JitEventListener, String> {
+    JitEventListener::from_llvm(unsafe { ee::LLVMCreatePerfJITEventListener() }, "perf")
+  }
+
+  fn from_llvm(listener: LLVMJITEventListenerRef, name: &str) -> Result<JitEventListener, String> {
+    if listener.is_null() {
+      Err(format!("{} JIT event listener is not available in this LLVM build", name))
+    } else {
+      Ok(JitEventListener {
+        listener: listener,
+        callback: None,
+      })
+    }
+  }
+
+  /// Create a listener calling `f` whenever a function is emitted or freed.
+  pub fn callback<F>(f: F) -> JitEventListener
+    where F: FnMut(JitEvent, &JitFunctionInfo) + 'static
+  {
+    let mut callback: Box<Callback> = Box::new(Box::new(f));
+    let opaque = &mut *callback as *mut Callback as *mut c_void;
+
+    JitEventListener {
+      listener: unsafe { LLVMCreateCallbackJITEventListener(on_event, opaque) },
+      callback: Some(callback),
+    }
+  }
+
+  pub fn as_ptr(&self) -> LLVMJITEventListenerRef {
+    self.listener
+  }
+}
+
+impl Drop for JitEventListener {
+  fn drop(&mut self) {
+    if self.callback.is_some() {
+      unsafe { LLVMDisposeCallbackJITEventListener(self.listener) }
+    }
+  }
+}
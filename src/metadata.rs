@@ -0,0 +1,336 @@
+//! Metadata
+//!
+//! Metadata attaches extra information to instructions and modules, which
+//! doesn't change the semantics of a program but tells the optimizer more about
+//! it, e.g. which pointers never alias (TBAA), the range of a loaded value,
+//! or how a loop should be vectorized.
+//!
+//! Refer to http://llvm.org/docs/LangRef.html#metadata
+
+use std::mem;
+
+use libc::{c_char, c_uint};
+use llvm_sys::core;
+use llvm_sys::debuginfo::{LLVMMetadataReplaceAllUsesWith, LLVMTemporaryMDNode};
+use llvm_sys::prelude::{LLVMContextRef, LLVMValueRef};
+
+use super::LLVMRef;
+use value::{ToValue, Value};
+
+/// Returns the id of the metadata kind with the name given, e.g. `tbaa` or `range`.
+pub fn kind_id(ctx: LLVMContextRef, name: &str) -> c_uint {
+  unsafe {
+    core::LLVMGetMDKindIDInContext(ctx, name.as_ptr() as *const c_char, name.len() as c_uint)
+  }
+}
+
+/// A metadata string, like `!"int"`.
+#[derive(Copy, Clone)]
+pub struct MDString(pub LLVMValueRef);
+impl_from_ref!(LLVMValueRef, MDString);
+impl_from_into!(MDString, Value);
+
+impl MDString {
+  pub fn new(ctx: LLVMContextRef, text: &str) -> MDString {
+    MDString(unsafe {
+      core::LLVMMDStringInContext(ctx, text.as_ptr() as *const c_char, text.len() as c_uint)
+    })
+  }
+
+  /// Returns the text of this string.
+  pub fn as_str<'a>(&self) -> &'a str {
+    unsafe {
+      let mut len = 0;
+      let ptr = core::LLVMGetMDString(self.0, &mut len);
+      let bytes = ::std::slice::from_raw_parts(ptr as *const u8, len as usize);
+      ::std::str::from_utf8_unchecked(bytes)
+    }
+  }
+}
+
+/// A metadata tuple, like `!{!"int", !0, i64 0}`.
+#[derive(Copy, Clone)]
+pub struct MDNode(pub LLVMValueRef);
+impl_from_ref!(LLVMValueRef, MDNode);
+impl_from_into!(MDNode, Value);
+
+impl MDNode {
+  /// Create a new node with the operands given, which may be constants, strings or other
+  /// nodes.
+  pub fn new(ctx: LLVMContextRef, vals: &[&Value]) -> MDNode {
+    let ref_array = to_llvmref_array!(vals, LLVMValueRef);
+
+    MDNode(unsafe {
+      core::LLVMMDNodeInContext(ctx,
+                                ref_array.as_ptr() as *mut LLVMValueRef,
+                                vals.len() as c_uint)
+    })
+  }
+
+  /// Create a new node without operands, as used by `!nonnull`.
+  pub fn empty(ctx: LLVMContextRef) -> MDNode {
+    MDNode::new(ctx, &[])
+  }
+
+  /// Create a new distinct node whose first operand refers to the node itself, followed by
+  /// the operands given. This is the form `llvm.loop` expects.
+  pub fn new_self_ref(ctx: LLVMContextRef, vals: &[&Value]) -> MDNode {
+    unsafe {
+      let temp = LLVMTemporaryMDNode(ctx, ::std::ptr::null_mut(), 0);
+      let temp_val = Value(core::LLVMMetadataAsValue(ctx, temp));
+
+      let mut ops = vec![&temp_val];
+      ops.extend_from_slice(vals);
+      let node = MDNode::new(ctx, &ops);
+
+      LLVMMetadataReplaceAllUsesWith(temp, core::LLVMValueAsMetadata(node.0));
+      node
+    }
+  }
+
+  /// Returns the number of operands of this node.
+  pub fn num_operands(&self) -> usize {
+    unsafe { core::LLVMGetMDNodeNumOperands(self.0) as usize }
+  }
+
+  /// Returns the operands of this node.
+  pub fn operands(&self) -> Vec<Value> {
+    unsafe {
+      let count = core::LLVMGetMDNodeNumOperands(self.0);
+      let mut vals: Vec<LLVMValueRef> = (0..count).map(|_| mem::uninitialized()).collect();
+      core::LLVMGetMDNodeOperands(self.0, vals.as_mut_ptr());
+      vals.into_iter().map(|v| Value(v)).collect()
+    }
+  }
+
+  /// Create a `!range` node from the half-open intervals `[lo, hi)` given.
+  ///
+  /// All bounds must be constant integers of the type of the annotated value.
+  pub fn range(ctx: LLVMContextRef, ranges: &[(&Value, &Value)]) -> MDNode {
+    let mut ops = Vec::with_capacity(ranges.len() * 2);
+    for &(lo, hi) in ranges {
+      ops.push(lo);
+      ops.push(hi);
+    }
+
+    MDNode::new(ctx, &ops)
+  }
+}
+
+/// Builds TBAA type trees and access tags.
+///
+/// Two accesses whose tags lead to different scalar types under the same root are assumed
+/// not to alias, e.g. two generated column pointers given different scalar type nodes.
+///
+/// Refer to http://llvm.org/docs/LangRef.html#tbaa-metadata
+pub struct Tbaa {
+  ctx: LLVMContextRef,
+  root: MDNode,
+}
+
+impl Tbaa {
+  /// Create a new type tree with a root named `name`.
+  pub fn new(ctx: LLVMContextRef, name: &str) -> Tbaa {
+    let name: Value = MDString::new(ctx, name).into();
+    Tbaa {
+      ctx: ctx,
+      root: MDNode::new(ctx, &[&name]),
+    }
+  }
+
+  /// Returns the root node.
+  pub fn root(&self) -> MDNode {
+    self.root
+  }
+
+  /// Create a scalar type node named `name` under `parent`, or under the root if `parent`
+  /// is `None`.
+  pub fn scalar(&self, name: &str, parent: Option<&MDNode>) -> MDNode {
+    let name: Value = MDString::new(self.ctx, name).into();
+    let parent: Value = parent.unwrap_or(&self.root).into();
+    let offset = 0i64.to_value(self.ctx);
+
+    MDNode::new(self.ctx, &[&name, &parent, &offset])
+  }
+
+  /// Create an access tag for an access of type `access` at `offset` within `base`.
+  ///
+  /// For a scalar access, `base` and `access` are the same node and `offset` is 0.
+  pub fn tag(&self, base: &MDNode, access: &MDNode, offset: u64) -> MDNode {
+    let base: Value = base.into();
+    let access: Value = access.into();
+    let offset = offset.to_value(self.ctx);
+
+    MDNode::new(self.ctx, &[&base, &access, &offset])
+  }
+
+  /// Create an access tag for a scalar access of `ty`.
+  pub fn scalar_tag(&self, ty: &MDNode) -> MDNode {
+    self.tag(ty, ty, 0)
+  }
+}
+
+/// Builds `llvm.loop` hints, which should be attached to the branch of a loop latch.
+///
+/// Refer to http://llvm.org/docs/LangRef.html#llvm-loop
+pub struct LoopHints {
+  ctx: LLVMContextRef,
+  hints: Vec<MDNode>,
+}
+
+impl LoopHints {
+  pub fn new(ctx: LLVMContextRef) -> LoopHints {
+    LoopHints {
+      ctx: ctx,
+      hints: Vec::new(),
+    }
+  }
+
+  fn flag(mut self, name: &str) -> LoopHints {
+    let name: Value = MDString::new(self.ctx, name).into();
+    self.hints.push(MDNode::new(self.ctx, &[&name]));
+    self
+  }
+
+  fn hint(mut self, name: &str, val: &Value) -> LoopHints {
+    let name: Value = MDString::new(self.ctx, name).into();
+    self.hints.push(MDNode::new(self.ctx, &[&name, val]));
+    self
+  }
+
+  /// Enable or disable vectorization of the loop.
+  pub fn vectorize_enable(self, enable: bool) -> LoopHints {
+    let val = enable.to_value(self.ctx);
+    self.hint("llvm.loop.vectorize.enable", &val)
+  }
+
+  /// Sets the vectorization width of the loop.
+  pub fn vectorize_width(self, width: u32) -> LoopHints {
+    let val = width.to_value(self.ctx);
+    self.hint("llvm.loop.vectorize.width", &val)
+  }
+
+  /// Sets the interleave count of the loop.
+  pub fn interleave_count(self, count: u32) -> LoopHints {
+    let val = count.to_value(self.ctx);
+    self.hint("llvm.loop.interleave.count", &val)
+  }
+
+  /// Unroll the loop `count` times.
+  pub fn unroll_count(self, count: u32) -> LoopHints {
+    let val = count.to_value(self.ctx);
+    self.hint("llvm.loop.unroll.count", &val)
+  }
+
+  /// Never unroll the loop.
+  pub fn unroll_disable(self) -> LoopHints {
+    self.flag("llvm.loop.unroll.disable")
+  }
+
+  /// Fully unroll the loop if the trip count is known at compile time.
+  pub fn unroll_full(self) -> LoopHints {
+    self.flag("llvm.loop.unroll.full")
+  }
+
+  /// Enable or disable loop distribution.
+  pub fn distribute_enable(self, enable: bool) -> LoopHints {
+    let val = enable.to_value(self.ctx);
+    self.hint("llvm.loop.distribute.enable", &val)
+  }
+
+  /// Create the loop id node holding all the hints given so far.
+  pub fn build(&self) -> MDNode {
+    let hints = self.hints.iter().map(|h| h.into()).collect::<Vec<Value>>();
+    let hint_refs = hints.iter().collect::<Vec<&Value>>();
+    MDNode::new_self_ref(self.ctx, &hint_refs)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::{Builder, JitCompiler};
+  use types::LLVMTy;
+  use value::{Instruction, Predicate, ToValue, Value};
+
+  #[test]
+  fn test_md_node() {
+    let jit = JitCompiler::new("test_md").ok().unwrap();
+    let ctx = jit.context();
+
+    let s = MDString::new(ctx, "column");
+    assert_eq!("column", s.as_str());
+
+    let node = MDNode::new(ctx, &[&s.into(), &1i32.to_value(ctx)]);
+    assert_eq!(2, node.num_operands());
+    assert_eq!("i32 1", format!("{}", node.operands()[1]));
+
+    jit.module().add_named_metadata("test.columns", &node);
+    jit.module().add_named_metadata("test.columns", &MDNode::empty(ctx));
+    assert_eq!(2, jit.module().get_named_metadata("test.columns").len());
+    assert!(jit.module().get_named_metadata("test.none").is_empty());
+  }
+
+  #[test]
+  fn test_instr_metadata() {
+    let jit = JitCompiler::new("test_md").ok().unwrap();
+    let ctx = jit.context();
+    let bld = Builder::new(ctx);
+
+    let ptr_ty = i64::llvm_ty(ctx).pointer_ty();
+    let func = jit.create_func_prototype("sum", &i64::llvm_ty(ctx), &[&ptr_ty, &ptr_ty],
+                                         Some(&bld));
+
+    let tbaa = Tbaa::new(ctx, "test tbaa");
+    let col_a = tbaa.scalar("col_a", None);
+    let col_b = tbaa.scalar("col_b", None);
+
+    let a = Instruction::from(bld.create_load(&func.arg(0).into()));
+    let b = Instruction::from(bld.create_load(&func.arg(1).into()));
+    a.set_metadata("tbaa", &tbaa.scalar_tag(&col_a));
+    b.set_metadata("tbaa", &tbaa.scalar_tag(&col_b));
+    b.set_metadata("range",
+                   &MDNode::range(ctx, &[(&0i64.to_value(ctx), &100i64.to_value(ctx))]));
+
+    assert!(a.has_metadata());
+    assert!(a.get_metadata("tbaa").is_some());
+    assert!(a.get_metadata("range").is_none());
+
+    let sum = bld.create_add(&a.into(), &b.into());
+    bld.create_ret(&sum);
+    jit.verify().unwrap();
+  }
+
+  #[test]
+  fn test_loop_hints() {
+    let jit = JitCompiler::new("test_md").ok().unwrap();
+    let ctx = jit.context();
+    let bld = Builder::new(ctx);
+
+    let func = jit.create_func_prototype("count", &u64::llvm_ty(ctx), &[&u64::llvm_ty(ctx)],
+                                         Some(&bld));
+    let entry = bld.get_insert_block();
+    let body = func.append("body");
+    let exit = func.append("exit");
+    bld.create_br(&body);
+
+    bld.position_at_end(&body);
+    let i = bld.create_phi(&u64::llvm_ty(ctx), "i");
+    let next = bld.create_add(&Value::from(&i), &1u64.to_value(ctx));
+    i.add_incoming(&0u64.to_value(ctx), &entry);
+    i.add_incoming(&next, &body);
+    let cond = bld.create_ucmp(&next, &func.arg(0).into(), Predicate::Lt);
+    let latch = Instruction::from(bld.create_cond_br(&cond, &body, &exit));
+
+    let hints = LoopHints::new(ctx).vectorize_enable(true).unroll_count(4).distribute_enable(true);
+    latch.set_metadata("llvm.loop", &hints.build());
+
+    bld.position_at_end(&exit);
+    bld.create_ret(&next);
+    jit.verify().unwrap();
+
+    let loop_id = MDNode(latch.get_metadata("llvm.loop").unwrap().0);
+    assert_eq!(4, loop_id.num_operands());
+    assert!(loop_id.operands()[0].0 == loop_id.0);
+  }
+}
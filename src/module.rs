@@ -1,19 +1,37 @@
+use std::cmp;
+use std::ffi::CString;
 use std::mem;
-use libc::{c_char, c_uint};
+use std::ptr;
+use std::time::Instant;
+use libc::{c_char, c_uint, c_ulonglong};
 
 use llvm_sys::bit_reader::LLVMParseBitcodeInContext;
 use llvm_sys::core;
-use llvm_sys::linker;
-use llvm_sys::prelude::{LLVMContextRef, LLVMModuleRef};
-use llvm_sys::transforms::pass_manager_builder as pass;
+use llvm_sys::error::{LLVMDisposeErrorMessage, LLVMErrorRef, LLVMGetErrorMessage};
+use llvm_sys::LLVMLinkage;
+use llvm_sys::prelude::{LLVMContextRef, LLVMModuleRef, LLVMValueRef};
+use llvm_sys::transforms::pass_builder;
 
 use super::{AddressSpace, Builder, LLVMRef};
 use buffer::MemoryBuffer;
-use analysis::Verifier;
+use dot;
+use linker::{self, LinkError, LinkFlags};
+use analysis::{Diagnostic, Lint, Verifier};
+use metadata::MDNode;
+use pass::{self, CustomPasses};
+use stats::{self, CompileReport};
 use value::{Function, GlobalValue, Value, ValueIter, ValueRef};
 use types::{FunctionTy, Ty};
 use util::chars;
 
+// Returns the message of `err` as a string, and dispose it.
+unsafe fn take_error_message(err: LLVMErrorRef) -> String {
+  let msg = LLVMGetErrorMessage(err);
+  let s = chars::to_str(msg).to_string();
+  LLVMDisposeErrorMessage(msg);
+  s
+}
+
 /// LLVM Module
 ///
 /// ExecutionEngine can own Module. In this case, ExecutionEngine will dispose Module.
@@ -59,10 +77,20 @@ impl Module {
   }
 
   pub fn from_bc(ctx: LLVMContextRef, path: &str) -> Result<Module, String> {
+    let buf = try!(MemoryBuffer::from_file(path));
+    Module::parse_bc(ctx, &buf)
+  }
+
+  /// Parse a module from bitcode in memory, e.g. embedded with `include_bytes!`.
+  pub fn from_bc_bytes(ctx: LLVMContextRef, name: &str, bitcode: &[u8]) -> Result<Module, String> {
+    let buf = MemoryBuffer::from_bytes(name, bitcode);
+    Module::parse_bc(ctx, &buf)
+  }
+
+  fn parse_bc(ctx: LLVMContextRef, buf: &MemoryBuffer) -> Result<Module, String> {
     unsafe {
       let mut m: LLVMModuleRef = mem::uninitialized();
       let mut err: *mut c_char = mem::uninitialized();
-      let buf = try!(MemoryBuffer::from_file(path));
 
       let ret = LLVMParseBitcodeInContext(ctx, buf.as_ptr(), &mut m, &mut err);
       llvm_ret!(ret, Module(m, true), err)
@@ -85,55 +113,152 @@ impl Module {
     }
   }
 
+  /// Returns a deep copy of this module, which owns the copy.
+  ///
+  /// Unlike `clone`, which copies only the reference, the copy can be changed, compiled
+  /// or linked without affecting this module.
+  pub fn clone_module(&self) -> Module {
+    Module(unsafe { core::LLVMCloneModule(self.0) }, true)
+  }
+
+  /// Returns a new module in the same context with copies of the functions given, along
+  /// with declarations of the functions and globals they refer to.
+  pub fn extract_functions(&self, names: &[&str]) -> Result<Module, String> {
+    let ctx = unsafe { core::LLVMGetModuleContext(self.0) };
+    let m = Module::new(ctx, &format!("{}.extracted", self.name()));
+    unsafe {
+      core::LLVMSetTarget(m.0, core::LLVMGetTarget(self.0));
+      core::LLVMSetDataLayout(m.0, core::LLVMGetDataLayoutStr(self.0));
+    }
+
+    for name in names {
+      let func = try!(self.get_func(name)
+        .ok_or_else(|| format!("module {} has no function named {}", self.name(), name)));
+      try!(func.clone_into(&m));
+    }
+    Ok(m)
+  }
+
+  /// Returns the name of this module
+  pub fn name(&self) -> &str {
+    unsafe {
+      let mut len = 0;
+      let name = core::LLVMGetModuleIdentifier(self.0, &mut len);
+      let bytes = ::std::slice::from_raw_parts(name as *const u8, len);
+      ::std::str::from_utf8_unchecked(bytes)
+    }
+  }
+
   /// Link a module into this module, returning an error string if an error occurs.
   ///
   /// This *does not* destroy the source module.
   pub fn link(&self, m: &Module) -> Result<(), String> {
-    unsafe {
-      let mut error = mem::uninitialized();
-      let ret = linker::LLVMLinkModules(self.0,
-                                        m.0,
-                                        linker::LLVMLinkerMode::LLVMLinkerPreserveSource,
-                                        &mut error);
-      llvm_ret!(ret, (), error)
-    }
+    let copy = Module(unsafe { core::LLVMCloneModule(m.0) }, true);
+    self.link_in(copy, LinkFlags::default()).map_err(|e| e.to_string())
   }
 
   /// Link a module into this module, returning an error string if an error occurs.
   ///
-  /// This *does* destroy the source module.
+  /// This *does* destroy the source module: it is left empty once it is linked.
   pub fn link_destroy(&self, m: &Module) -> Result<(), String> {
-    unsafe {
-      let mut error = mem::uninitialized();
-      let ret = linker::LLVMLinkModules(self.0,
-                                        m.0,
-                                        linker::LLVMLinkerMode::LLVMLinkerDestroySource,
-                                        &mut error);
-      llvm_ret!(ret, (), error)
-    }
+    // `m` still owns the module, so a copy is given up to the linker.
+    let copy = Module(unsafe { core::LLVMCloneModule(m.0) }, true);
+    try!(self.link_in(copy, LinkFlags::default()).map_err(|e| e.to_string()));
+    unsafe { linker::LLVMDropModuleContents(m.0) };
+    Ok(())
+  }
+
+  /// Link `src` into this module with the flags given, consuming `src`.
+  ///
+  /// Unless `flags.override_from_src` is set, definitions in `src` of the symbols this
+  /// module defines are reported as `LinkError::Conflict`, and nothing is linked.
+  pub fn link_in(&self, src: Module, flags: LinkFlags) -> Result<(), LinkError> {
+    linker::link_modules(self, src, flags, None)
+  }
+
+  /// Link `src` into this module like `link_in`, then make the symbols linked from `src`
+  /// for which `internalize` returns true internal to this module.
+  pub fn link_in_internalize<F>(&self,
+                                src: Module,
+                                flags: LinkFlags,
+                                mut internalize: F)
+                                -> Result<(), LinkError>
+    where F: FnMut(&str) -> bool
+  {
+    linker::link_modules(self, src, flags, Some(&mut internalize))
   }
 
   /// Optimize this module with the given optimization level and size level.
   ///
-  /// This runs passes depending on the levels given.
+  /// This runs LLVM's default pipeline of the levels given, e.g. `default<O2>`, or
+  /// `default<Os>` and `default<Oz>` with the size levels 1 and 2.
   pub fn optimize(&self, opt_level: usize, size_level: usize) {
-    unsafe {
-      let builder = pass::LLVMPassManagerBuilderCreate();
-      pass::LLVMPassManagerBuilderSetOptLevel(builder, opt_level as c_uint);
-      pass::LLVMPassManagerBuilderSetSizeLevel(builder, size_level as c_uint);
-      let pass_manager = core::LLVMCreatePassManager();
-      pass::LLVMPassManagerBuilderPopulateModulePassManager(builder, pass_manager);
-      pass::LLVMPassManagerBuilderDispose(builder);
-      core::LLVMRunPassManager(pass_manager, self.0);
+    self.optimize_with(opt_level, size_level, &mut CustomPasses::new())
+  }
+
+  /// Optimize this module like `optimize`, running the custom passes given along with
+  /// LLVM's, at their extension points.
+  pub fn optimize_with(&self, opt_level: usize, size_level: usize, passes: &mut CustomPasses) {
+    // Size levels are only known by the pipelines of level 2.
+    let pipeline = match (opt_level, size_level) {
+      (0, _) => "default<O0>".to_string(),
+      (_, 1) => "default<Os>".to_string(),
+      (_, s) if s >= 2 => "default<Oz>".to_string(),
+      (o, _) => format!("default<O{}>", cmp::min(o, 3)),
+    };
+    let c_pipeline = CString::new(pipeline).unwrap();
+
+    let err = unsafe {
+      if passes.is_empty() {
+        let opts = pass_builder::LLVMCreatePassBuilderOptions();
+        let err = pass_builder::LLVMRunPasses(self.0, c_pipeline.as_ptr(), ptr::null_mut(),
+                                              opts);
+        pass_builder::LLVMDisposePassBuilderOptions(opts);
+        err
+      } else {
+        let raw = passes.to_raw();
+        pass::LLVMRunPassesWithCustomPasses(self.0, c_pipeline.as_ptr(), ptr::null_mut(),
+                                            raw.as_ptr(), raw.len() as c_uint)
+      }
+    };
+
+    // The pipeline is one of the default ones, so it always parses.
+    if !err.is_null() {
+      panic!("{}", unsafe { take_error_message(err) });
     }
   }
 
+  /// Optimize this module like `optimize_with`, returning how long it took, with the time
+  /// of each pass and the statistics counted if `stats::enable` was called.
+  pub fn optimize_with_report(&self,
+                              opt_level: usize,
+                              size_level: usize,
+                              passes: &mut CustomPasses)
+                              -> CompileReport {
+    let start = Instant::now();
+    self.optimize_with(opt_level, size_level, passes);
+    let optimization = start.elapsed();
+
+    let report = if stats::is_enabled() {
+      CompileReport::take()
+    } else {
+      CompileReport::default()
+    };
+    CompileReport { optimization: optimization, ..report }
+  }
+
   /// Verify that the module is safe to run, returning a string detailing the error
   /// when an error occurs.
   pub fn verify(&self) -> Result<(), String> {
     Verifier::verify_module(self.0)
   }
 
+  /// Check the functions of this module for code which is most likely wrong, such as
+  /// undefined behavior. See `Lint`.
+  pub fn lint(&self) -> Vec<Diagnostic> {
+    Lint::check_module(self.0)
+  }
+
   /// Dump the module to stderr (for debugging).
   pub fn dump(&self) {
     unsafe {
@@ -141,6 +266,11 @@ impl Module {
     }
   }
 
+  /// Returns the call graph of this module in the Graphviz DOT language.
+  pub fn call_graph_dot(&self) -> String {
+    dot::call_graph(self)
+  }
+
   /// Returns the type with the name given, or `None`` if no type with that name exists.
   pub fn get_ty(&self, name: &str) -> Option<Ty> {
     let c_name = chars::from_str(name);
@@ -187,6 +317,83 @@ impl Module {
                    core::LLVMGetNextGlobal)
   }
 
+  /// Add the metadata node given as an operand of the named metadata `name`, creating it
+  /// if it doesn't exist yet.
+  pub fn add_named_metadata(&self, name: &str, node: &MDNode) {
+    let c_name = chars::from_str(name);
+    unsafe { core::LLVMAddNamedMetadataOperand(self.0, c_name, node.0) }
+  }
+
+  /// Returns the operands of the named metadata `name`, which is empty if it doesn't exist.
+  pub fn get_named_metadata(&self, name: &str) -> Vec<MDNode> {
+    let c_name = chars::from_str(name);
+    unsafe {
+      let count = core::LLVMGetNamedMetadataNumOperands(self.0, c_name);
+      let mut nodes: Vec<LLVMValueRef> = (0..count).map(|_| mem::uninitialized()).collect();
+      core::LLVMGetNamedMetadataOperands(self.0, c_name, nodes.as_mut_ptr());
+      nodes.into_iter().map(|n| MDNode(n)).collect()
+    }
+  }
+
+  /// Register `func`, a `void()` function, to be run by `JitCompiler::run_static_constructors`.
+  ///
+  /// Constructors with a lower `priority` are run first, and those of the same priority
+  /// in the order they are added. This adds to `llvm.global_ctors`, so it works with
+  /// constructors already in the module.
+  pub fn add_global_ctor(&self, func: &Function, priority: u32) {
+    self.insert_structor("llvm.global_ctors", func, priority, true)
+  }
+
+  /// Register `func`, a `void()` function, to be run by `JitCompiler::run_static_destructors`.
+  ///
+  /// Destructors with a higher `priority` are run first, and those of the same priority
+  /// in the order they are added.
+  pub fn add_global_dtor(&self, func: &Function, priority: u32) {
+    self.insert_structor("llvm.global_dtors", func, priority, false)
+  }
+
+  // Rebuilds the `{ i32, ptr, ptr }` array of the appending global `name` with a new entry,
+  // since the initializer of a global cannot be resized in place. The execution engine
+  // runs the entries in array order regardless of their priorities, so the entry is
+  // inserted after those running before it, by `ascending` or descending priority.
+  fn insert_structor(&self, name: &str, func: &Function, priority: u32, ascending: bool) {
+    let c_name = chars::from_str(name);
+    unsafe {
+      let ctx = core::LLVMGetModuleContext(self.0);
+      let i32_ty = core::LLVMInt32TypeInContext(ctx);
+      let ptr_ty = core::LLVMPointerTypeInContext(ctx, 0);
+      let mut field_tys = [i32_ty, ptr_ty, ptr_ty];
+      let entry_ty = core::LLVMStructTypeInContext(ctx, field_tys.as_mut_ptr(), 3, 0);
+
+      let mut entries: Vec<LLVMValueRef> = Vec::new();
+      let old = core::LLVMGetNamedGlobal(self.0, c_name);
+      if !old.is_null() {
+        let init = core::LLVMGetInitializer(old);
+        if !init.is_null() {
+          let count = core::LLVMGetArrayLength2(core::LLVMTypeOf(init));
+          entries.extend((0..count).map(|i| core::LLVMGetAggregateElement(init, i as c_uint)));
+        }
+        core::LLVMDeleteGlobal(old);
+      }
+
+      let mut fields = [core::LLVMConstInt(i32_ty, priority as c_ulonglong, 0),
+                        func.0,
+                        core::LLVMConstPointerNull(ptr_ty)];
+      let pos = entries.iter()
+        .rposition(|&e| {
+          let p = core::LLVMConstIntGetZExtValue(core::LLVMGetAggregateElement(e, 0)) as u32;
+          if ascending { p <= priority } else { p >= priority }
+        })
+        .map_or(0, |i| i + 1);
+      entries.insert(pos, core::LLVMConstStructInContext(ctx, fields.as_mut_ptr(), 3, 0));
+
+      let init = core::LLVMConstArray(entry_ty, entries.as_mut_ptr(), entries.len() as c_uint);
+      let global = core::LLVMAddGlobal(self.0, core::LLVMTypeOf(init), c_name);
+      core::LLVMSetInitializer(global, init);
+      core::LLVMSetLinkage(global, LLVMLinkage::LLVMAppendingLinkage);
+    }
+  }
+
   /// Add a function to the module with the name given.
   pub fn add_func(&self, name: &str, sig: &FunctionTy) -> Function {
     let c_name = chars::from_str(name);
@@ -202,6 +409,12 @@ impl Module {
     }
   }
 
+  /// Get an iterator of functions, including declarations
+  pub fn functions(&self) -> ValueIter<Function> {
+    ValueIter::new(unsafe { core::LLVMGetFirstFunction(self.0) },
+                   core::LLVMGetNextFunction)
+  }
+
   /// Returns the function after creating prototype and initializing the entry block
   pub fn create_func_prototype(&self,
                                name: &str,
@@ -0,0 +1,349 @@
+//! Persistent Object Cache
+//!
+//! An `ObjectCache` keeps the machine code MCJIT emits for each module in a directory,
+//! and hands it back when a module with the same bitcode is compiled again with the same
+//! target options, so a restarted process skips code generation. Once the directory
+//! grows over its size limit, the least recently used objects are evicted.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::slice;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use libc::{c_char, c_void, size_t};
+use llvm_sys::bit_writer::LLVMWriteBitcodeToMemoryBuffer;
+use llvm_sys::blake3::{self, LLVM_BLAKE3_OUT_LEN};
+use llvm_sys::core;
+use llvm_sys::execution_engine::LLVMExecutionEngineRef;
+use llvm_sys::prelude::{LLVMMemoryBufferRef, LLVMModuleRef};
+
+use buffer::MemoryBuffer;
+
+pub enum LLVMOpaqueObjectCache {}
+pub type LLVMObjectCacheRef = *mut LLVMOpaqueObjectCache;
+
+// Extended APIs, offering more APIs than LLVM C API does.
+extern "C" {
+  pub fn LLVMCreateCallbackObjectCache(notify: extern "C" fn(*mut c_void,
+                                                             LLVMModuleRef,
+                                                             *const c_char,
+                                                             size_t),
+                                       get: extern "C" fn(*mut c_void, LLVMModuleRef)
+                                                          -> LLVMMemoryBufferRef,
+                                       opaque: *mut c_void)
+                                       -> LLVMObjectCacheRef;
+  pub fn LLVMDisposeCallbackObjectCache(cache: LLVMObjectCacheRef);
+  pub fn LLVMExecutionEngineSetObjectCache(ee: LLVMExecutionEngineRef, cache: LLVMObjectCacheRef);
+}
+
+const OBJECT_EXT: &'static str = "o";
+
+/// A directory of compiled objects, which can be shared by several `JitCompiler`s.
+#[derive(Debug)]
+pub struct ObjectCache {
+  dir: PathBuf,
+  max_size: u64,
+  hits: AtomicUsize,
+  misses: AtomicUsize,
+}
+
+impl ObjectCache {
+  /// Create a cache storing objects in `dir`, which is created if it doesn't exist, and
+  /// evicting objects once they take more than `max_size` bytes.
+  pub fn new<P: AsRef<Path>>(dir: P, max_size: u64) -> Result<ObjectCache, String> {
+    let dir = dir.as_ref().to_path_buf();
+    try!(fs::create_dir_all(&dir)
+      .map_err(|e| format!("failed to create object cache {}: {}", dir.display(), e)));
+
+    Ok(ObjectCache {
+      dir: dir,
+      max_size: max_size,
+      hits: AtomicUsize::new(0),
+      misses: AtomicUsize::new(0),
+    })
+  }
+
+  pub fn dir(&self) -> &Path {
+    &self.dir
+  }
+
+  pub fn max_size(&self) -> u64 {
+    self.max_size
+  }
+
+  /// Returns how many times a module was found in this cache.
+  pub fn hits(&self) -> usize {
+    self.hits.load(Ordering::Relaxed)
+  }
+
+  /// Returns how many times a module wasn't found in this cache, and had to be compiled.
+  pub fn misses(&self) -> usize {
+    self.misses.load(Ordering::Relaxed)
+  }
+
+  /// Returns the object stored under `key`, or `None` if there is none.
+  pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+    let path = self.path(key);
+    let mut obj = Vec::new();
+    match File::open(&path).and_then(|mut f| f.read_to_end(&mut obj)) {
+      Ok(_) => {
+        // Mark it recently used, so it is evicted last.
+        let _ = File::options().write(true).open(&path).and_then(|f| f.set_modified(SystemTime::now()));
+        Some(obj)
+      }
+      Err(_) => None,
+    }
+  }
+
+  /// Store `obj` under `key`, then evict the least recently used objects if the cache is
+  /// over its size limit.
+  pub fn put(&self, key: &str, obj: &[u8]) -> Result<(), String> {
+    // Written aside then renamed, so other processes never read a partial object.
+    let tmp_path = self.dir.join(format!("{}.{}.tmp", key, process::id()));
+    try!(File::create(&tmp_path)
+      .and_then(|mut f| f.write_all(obj))
+      .and_then(|_| fs::rename(&tmp_path, self.path(key)))
+      .map_err(|e| {
+        let _ = fs::remove_file(&tmp_path);
+        format!("failed to store object {}: {}", key, e)
+      }));
+
+    self.evict().map_err(|e| format!("failed to evict objects: {}", e))
+  }
+
+  /// Remove all objects in this cache.
+  pub fn clear(&self) -> Result<(), String> {
+    let remove = || -> io::Result<()> {
+      for (path, _, _) in try!(self.entries()) {
+        try!(fs::remove_file(path));
+      }
+      Ok(())
+    };
+    remove().map_err(|e| format!("failed to clear object cache: {}", e))
+  }
+
+  fn path(&self, key: &str) -> PathBuf {
+    self.dir.join(key).with_extension(OBJECT_EXT)
+  }
+
+  // Returns the path, size and modification time of each object.
+  fn entries(&self) -> io::Result<Vec<(PathBuf, u64, SystemTime)>> {
+    let mut entries = Vec::new();
+    for entry in try!(fs::read_dir(&self.dir)) {
+      let path = try!(entry).path();
+      if path.extension().map_or(false, |ext| ext == OBJECT_EXT) {
+        let meta = try!(fs::metadata(&path));
+        entries.push((path, meta.len(), try!(meta.modified())));
+      }
+    }
+    Ok(entries)
+  }
+
+  fn evict(&self) -> io::Result<()> {
+    let mut entries = try!(self.entries());
+    let mut total = entries.iter().map(|e| e.1).sum::<u64>();
+    entries.sort_by_key(|e| e.2);
+
+    for (path, size, _) in entries {
+      if total <= self.max_size {
+        break;
+      }
+      // Another process may have evicted it already.
+      let _ = fs::remove_file(path);
+      total -= size;
+    }
+    Ok(())
+  }
+}
+
+/// Returns the cache key of `m` compiled with the target options described by `options`,
+/// which is the BLAKE3 digest of both, so it is the same across Rust releases and distinct
+/// modules practically never collide.
+pub fn module_key(m: LLVMModuleRef, options: &str) -> String {
+  let bitcode = MemoryBuffer::from_ptr(unsafe { LLVMWriteBitcodeToMemoryBuffer(m) });
+  let bytes = unsafe {
+    slice::from_raw_parts(core::LLVMGetBufferStart(bitcode.as_ptr()) as *const u8,
+                          core::LLVMGetBufferSize(bitcode.as_ptr()))
+  };
+
+  // The length of the options first, so they can't run into the bitcode.
+  let len = (options.len() as u64).to_le_bytes();
+  digest(&[&len, options.as_bytes(), bytes])
+}
+
+// Returns the BLAKE3 digest of the concatenation of `parts`, in hex.
+fn digest(parts: &[&[u8]]) -> String {
+  let mut out = [0u8; LLVM_BLAKE3_OUT_LEN];
+  unsafe {
+    let mut hasher = mem::MaybeUninit::<blake3::llvm_blake3_hasher>::uninit();
+    blake3::llvm_blake3_hasher_init(hasher.as_mut_ptr());
+    for part in parts {
+      blake3::llvm_blake3_hasher_update(hasher.as_mut_ptr(), part.as_ptr() as *const c_void,
+                                        part.len());
+    }
+    blake3::llvm_blake3_hasher_finalize(hasher.as_mut_ptr(), out.as_mut_ptr(), out.len());
+  }
+  out.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+struct CacheState {
+  cache: Arc<ObjectCache>,
+  options: String,
+  // Keys of the modules being compiled. Code generation changes a module before its
+  // object is stored, so its key must be computed when it is looked up.
+  pending: RefCell<HashMap<LLVMModuleRef, String>>,
+}
+
+extern "C" fn on_compiled(opaque: *mut c_void, m: LLVMModuleRef, obj: *const c_char, size: size_t) {
+  let state = unsafe { &*(opaque as *const CacheState) };
+  let obj = unsafe { slice::from_raw_parts(obj as *const u8, size) };
+  let key = match state.pending.borrow_mut().remove(&m) {
+    Some(key) => key,
+    None => return,
+  };
+
+  // A failure to store only costs a recompilation later.
+  let _ = state.cache.put(&key, obj);
+}
+
+extern "C" fn on_get(opaque: *mut c_void, m: LLVMModuleRef) -> LLVMMemoryBufferRef {
+  let state = unsafe { &*(opaque as *const CacheState) };
+  let key = module_key(m, &state.options);
+
+  match state.cache.get(&key) {
+    Some(obj) => {
+      state.cache.hits.fetch_add(1, Ordering::Relaxed);
+      MemoryBuffer::from_bytes(&key, &obj).into_ptr()
+    }
+    None => {
+      state.cache.misses.fetch_add(1, Ordering::Relaxed);
+      state.pending.borrow_mut().insert(m, key);
+      ::std::ptr::null_mut()
+    }
+  }
+}
+
+/// An `ObjectCache` set on an execution engine, which is unset when this is dropped.
+pub struct ObjectCacheBinding {
+  ee: LLVMExecutionEngineRef,
+  cache: LLVMObjectCacheRef,
+  // Referred to by `cache`, so it must live as long.
+  state: Box<CacheState>,
+}
+
+impl ObjectCacheBinding {
+  /// Make `ee` look up and store objects in `cache`. `options` describes whatever else
+  /// than the module affects the code generated, such as the target and optimization
+  /// level.
+  pub fn new(ee: LLVMExecutionEngineRef, cache: Arc<ObjectCache>, options: String) -> ObjectCacheBinding {
+    let mut state = Box::new(CacheState {
+      cache: cache,
+      options: options,
+      pending: RefCell::new(HashMap::new()),
+    });
+    let opaque = &mut *state as *mut CacheState as *mut c_void;
+
+    unsafe {
+      let cache_ref = LLVMCreateCallbackObjectCache(on_compiled, on_get, opaque);
+      LLVMExecutionEngineSetObjectCache(ee, cache_ref);
+
+      ObjectCacheBinding {
+        ee: ee,
+        cache: cache_ref,
+        state: state,
+      }
+    }
+  }
+
+  pub fn cache(&self) -> &Arc<ObjectCache> {
+    &self.state.cache
+  }
+}
+
+impl Drop for ObjectCacheBinding {
+  fn drop(&mut self) {
+    unsafe {
+      LLVMExecutionEngineSetObjectCache(self.ee, ::std::ptr::null_mut());
+      LLVMDisposeCallbackObjectCache(self.cache);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use std::time::Duration;
+  use super::super::{JitCompiler, JitOptions};
+  use types::LLVMTy;
+  use value::ToValue;
+
+  fn temp_cache(name: &str, max_size: u64) -> ObjectCache {
+    let dir = env::temp_dir().join(format!("llvm-rs-{}-{}", name, process::id()));
+    let cache = ObjectCache::new(dir, max_size).unwrap();
+    cache.clear().unwrap();
+    cache
+  }
+
+  fn compile_add_one(opts: &JitOptions) -> u64 {
+    let jit = JitCompiler::new_with_options("cached", opts).ok().unwrap();
+    let ctx = jit.context();
+    let bld = jit.new_builder();
+    let func = jit.create_func_prototype("add_one", &u64::llvm_ty(ctx), &[&u64::llvm_ty(ctx)],
+                                         Some(&bld));
+    bld.create_ret(&bld.create_add(&func.arg(0).into(), &1u64.to_value(ctx)));
+
+    let add_one: fn(u64) -> u64 = unsafe { ::std::mem::transmute(jit.get_func_ptr(&func).unwrap()) };
+    add_one(41)
+  }
+
+  #[test]
+  fn test_jit_object_cache() {
+    let cache = Arc::new(temp_cache("jit-cache", 1 << 20));
+    let opts = JitOptions { object_cache: Some(cache.clone()), ..JitOptions::default() };
+
+    assert_eq!(42, compile_add_one(&opts));
+    assert_eq!((0, 1), (cache.hits(), cache.misses()));
+    assert_eq!(1, cache.entries().unwrap().len());
+
+    assert_eq!(42, compile_add_one(&opts));
+    assert_eq!((1, 1), (cache.hits(), cache.misses()));
+
+    // Another optimization level generates other code.
+    assert_eq!(42, compile_add_one(&JitOptions { opt_level: 0, ..opts.clone() }));
+    assert_eq!((1, 2), (cache.hits(), cache.misses()));
+
+    // Neither is code for a generic CPU shared with code for the host CPU.
+    assert_eq!(42, compile_add_one(&JitOptions { host_cpu: false, ..opts.clone() }));
+    assert_eq!((1, 3), (cache.hits(), cache.misses()));
+    cache.clear().unwrap();
+  }
+
+  #[test]
+  fn test_digest() {
+    assert_eq!("af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262", digest(&[]));
+    assert_eq!(digest(&[b"abc"]), digest(&[b"ab", b"c"]));
+    assert!(digest(&[b"abc"]) != digest(&[b"abd"]));
+  }
+
+  #[test]
+  fn test_eviction() {
+    let cache = temp_cache("eviction", 10);
+
+    cache.put("old", &[0; 6]).unwrap();
+    let past = SystemTime::now() - Duration::from_secs(60);
+    File::options().write(true).open(cache.path("old")).unwrap().set_modified(past).unwrap();
+
+    cache.put("new", &[1; 6]).unwrap();
+    assert_eq!(None, cache.get("old"));
+    assert_eq!(Some(vec![1; 6]), cache.get("new"));
+    cache.clear().unwrap();
+  }
+}
@@ -0,0 +1,255 @@
+//! Custom Passes
+//!
+//! Transformations written in Rust can run inside the pipeline of `Module::optimize`,
+//! between LLVM's own passes. A `FunctionPass` or `ModulePass` is added to `CustomPasses`
+//! at an `ExtensionPoint`, which tells where in the pipeline it runs, and the passes are
+//! given to `Module::optimize_with`.
+
+use libc::{c_char, c_uint, c_void};
+use llvm_sys::error::LLVMErrorRef;
+use llvm_sys::prelude::{LLVMBool, LLVMModuleRef, LLVMValueRef};
+use llvm_sys::target_machine::LLVMTargetMachineRef;
+
+use module::Module;
+use value::Function;
+
+// A custom pass given to `LLVMRunPassesWithCustomPasses`. Exactly one of `run_function`
+// and `run_module` is set.
+#[repr(C)]
+pub struct LLVMCustomPass {
+  pub ep: c_uint,
+  pub run_function: Option<extern "C" fn(*mut c_void, LLVMValueRef) -> LLVMBool>,
+  pub run_module: Option<extern "C" fn(*mut c_void, LLVMModuleRef) -> LLVMBool>,
+  pub opaque: *mut c_void,
+}
+
+// Extended APIs, offering more APIs than LLVM C API does.
+extern "C" {
+  pub fn LLVMRunPassesWithCustomPasses(m: LLVMModuleRef,
+                                       passes: *const c_char,
+                                       tm: LLVMTargetMachineRef,
+                                       custom: *const LLVMCustomPass,
+                                       num_custom: c_uint)
+                                       -> LLVMErrorRef;
+}
+
+/// A pass transforming or analysing each function with a body, one at a time.
+pub trait FunctionPass {
+  /// Run this pass on `func`, returning true if it changed the function.
+  fn run_on_function(&mut self, func: &Function) -> bool;
+}
+
+/// A pass transforming or analysing a whole module.
+pub trait ModulePass {
+  /// Run this pass on `m`, returning true if it changed the module.
+  fn run_on_module(&mut self, m: &Module) -> bool;
+}
+
+/// Where a custom pass runs in the pipeline of `Module::optimize_with`.
+///
+/// With the optimization level 0, only the passes at `EarlyAsPossible` and
+/// `EnabledOnOptLevel0` run, and those at `EnabledOnOptLevel0` only run with it. Module
+/// passes can only run at the extension points of the whole module, see
+/// `is_module_level`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ExtensionPoint {
+  /// At the start of the pipeline, before any optimization.
+  EarlyAsPossible = 0,
+  /// Before the module level optimizations, i.e. after inlining and the function
+  /// simplifications.
+  ModuleOptimizerEarly = 1,
+  /// After the scalar optimizations of each function.
+  ScalarOptimizerLate = 2,
+  /// At the end of the pipeline.
+  OptimizerLast = 3,
+  /// Before the vectorizers.
+  VectorizerStart = 4,
+  /// At the end of the pipeline, with the optimization level 0 only.
+  EnabledOnOptLevel0 = 5,
+  /// Along with each instruction combining pass, i.e. several times.
+  Peephole = 6,
+  /// Right after inlining, on each function whose callees are inlined into it, before the
+  /// function simplification passes.
+  CGSCCOptimizerLate = 7,
+}
+
+impl ExtensionPoint {
+  /// Returns true if the pipeline runs over the whole module at this point, so module
+  /// passes can be added to it. Function passes can be added to any point.
+  pub fn is_module_level(&self) -> bool {
+    match *self {
+      ExtensionPoint::EarlyAsPossible |
+      ExtensionPoint::ModuleOptimizerEarly |
+      ExtensionPoint::OptimizerLast |
+      ExtensionPoint::EnabledOnOptLevel0 => true,
+      _ => false,
+    }
+  }
+}
+
+enum CustomPass<'a> {
+  Function(Box<FunctionPass + 'a>),
+  Module(Box<ModulePass + 'a>),
+}
+
+extern "C" fn run_function_pass(opaque: *mut c_void, func: LLVMValueRef) -> LLVMBool {
+  let pass = unsafe { &mut *(opaque as *mut Box<FunctionPass>) };
+  pass.run_on_function(&Function(func)) as LLVMBool
+}
+
+extern "C" fn run_module_pass(opaque: *mut c_void, m: LLVMModuleRef) -> LLVMBool {
+  let pass = unsafe { &mut *(opaque as *mut Box<ModulePass>) };
+  // The pass manager owns the module, so it mustn't be disposed.
+  pass.run_on_module(&Module(m, false)) as LLVMBool
+}
+
+/// Passes written in Rust to be run along with LLVM's.
+pub struct CustomPasses<'a> {
+  passes: Vec<(ExtensionPoint, CustomPass<'a>)>,
+}
+
+impl<'a> CustomPasses<'a> {
+  pub fn new() -> CustomPasses<'a> {
+    CustomPasses { passes: Vec::new() }
+  }
+
+  /// Run `pass` on each function at `ep`. Passes added at the same extension point run
+  /// in the order they are added.
+  pub fn add_function_pass<P: FunctionPass + 'a>(&mut self, ep: ExtensionPoint, pass: P) {
+    self.passes.push((ep, CustomPass::Function(Box::new(pass))));
+  }
+
+  /// Run `pass` on the module at `ep`.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `ep` is not an extension point of the whole module.
+  pub fn add_module_pass<P: ModulePass + 'a>(&mut self, ep: ExtensionPoint, pass: P) {
+    assert!(ep.is_module_level(), "module passes can't run at {:?}", ep);
+    self.passes.push((ep, CustomPass::Module(Box::new(pass))));
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.passes.is_empty()
+  }
+
+  /// Returns the passes as given to `LLVMRunPassesWithCustomPasses`.
+  ///
+  /// This is marked as unsafe because the passes must not be moved or dropped until the
+  /// pipeline given them is done running.
+  pub unsafe fn to_raw(&mut self) -> Vec<LLVMCustomPass> {
+    self.passes
+      .iter_mut()
+      .map(|&mut (ep, ref mut pass)| {
+        match *pass {
+          CustomPass::Function(ref mut pass) => {
+            LLVMCustomPass {
+              ep: ep as c_uint,
+              run_function: Some(run_function_pass),
+              run_module: None,
+              opaque: pass as *mut Box<FunctionPass + 'a> as *mut c_void,
+            }
+          }
+          CustomPass::Module(ref mut pass) => {
+            LLVMCustomPass {
+              ep: ep as c_uint,
+              run_function: None,
+              run_module: Some(run_module_pass),
+              opaque: pass as *mut Box<ModulePass + 'a> as *mut c_void,
+            }
+          }
+        }
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::JitCompiler;
+  use llvm_sys::core;
+  use types::LLVMTy;
+  use value::{ToValue, ValueRef};
+
+  struct RecordNames<'a>(&'a mut Vec<String>);
+
+  impl<'a> FunctionPass for RecordNames<'a> {
+    fn run_on_function(&mut self, func: &Function) -> bool {
+      self.0.push(func.name().unwrap_or("").to_string());
+      false
+    }
+  }
+
+  struct AddMarker;
+
+  impl ModulePass for AddMarker {
+    fn run_on_module(&mut self, m: &Module) -> bool {
+      let ctx = unsafe { core::LLVMGetModuleContext(m.0) };
+      m.add_global("marker", &i32::llvm_ty(ctx)).set_initializer(&1i32.to_value(ctx));
+      true
+    }
+  }
+
+  #[test]
+  fn test_custom_passes() {
+    let jit = JitCompiler::new("test_passes").ok().unwrap();
+    let ctx = jit.context();
+    let bld = jit.new_builder();
+    jit.create_func_prototype("answer", &i32::llvm_ty(ctx), &[], Some(&bld));
+    bld.create_ret(&42i32.to_value(ctx));
+    jit.create_func_prototype("external", &i32::llvm_ty(ctx), &[], None);
+
+    let mut names = Vec::new();
+    {
+      let mut passes = CustomPasses::new();
+      passes.add_function_pass(ExtensionPoint::CGSCCOptimizerLate, RecordNames(&mut names));
+      passes.add_module_pass(ExtensionPoint::OptimizerLast, AddMarker);
+      jit.module().optimize_with(2, 0, &mut passes);
+    }
+
+    assert_eq!(vec!["answer".to_string()], names);
+    assert!(jit.get_global("marker").is_some());
+    jit.verify().unwrap();
+  }
+
+  #[test]
+  fn test_early_as_possible() {
+    for &opt_level in &[0, 2] {
+      let jit = JitCompiler::new("test_passes").ok().unwrap();
+      let ctx = jit.context();
+      let bld = jit.new_builder();
+      jit.create_func_prototype("answer", &i32::llvm_ty(ctx), &[], Some(&bld));
+      bld.create_ret(&42i32.to_value(ctx));
+      jit.create_func_prototype("external", &i32::llvm_ty(ctx), &[], None);
+
+      let mut names = Vec::new();
+      {
+        let mut passes = CustomPasses::new();
+        passes.add_function_pass(ExtensionPoint::EarlyAsPossible, RecordNames(&mut names));
+        jit.module().optimize_with(opt_level, 0, &mut passes);
+      }
+      assert_eq!(vec!["answer".to_string()], names);
+    }
+  }
+
+  #[test]
+  fn test_opt_level_0() {
+    let jit = JitCompiler::new("test_passes").ok().unwrap();
+    let mut passes = CustomPasses::new();
+    passes.add_module_pass(ExtensionPoint::OptimizerLast, AddMarker);
+    jit.module().optimize_with(0, 0, &mut passes);
+    assert!(jit.get_global("marker").is_none());
+
+    passes.add_module_pass(ExtensionPoint::EnabledOnOptLevel0, AddMarker);
+    jit.module().optimize_with(0, 0, &mut passes);
+    assert!(jit.get_global("marker").is_some());
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_module_pass_at_function_level() {
+    let mut passes = CustomPasses::new();
+    passes.add_module_pass(ExtensionPoint::Peephole, AddMarker);
+  }
+}
@@ -0,0 +1,307 @@
+//! Optimization Remarks
+//!
+//! Passes explain what they did or didn't do to the code, e.g. why a loop wasn't
+//! vectorized or a call wasn't inlined, in remarks. A `RemarkCollector` collects the
+//! remarks of a context while it lives, whether they come from `Module::optimize` or
+//! from the code generator of a `JitCompiler`, as `Remark` records which can also be
+//! written as YAML, in the format of `-pass-remarks-output`.
+//!
+//! A collector replaces the diagnostic handler of the context, and passes the other
+//! diagnostics on to it. It must be dropped before a handler set after it.
+
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::mem;
+
+use libc::{c_char, c_uint, c_void};
+use llvm_sys::prelude::LLVMContextRef;
+
+use util::chars;
+
+pub enum LLVMOpaqueRemarkCollector {}
+pub type LLVMRemarkCollectorRef = *mut LLVMOpaqueRemarkCollector;
+
+// Extended APIs, offering more APIs than LLVM C API does.
+extern "C" {
+  pub fn LLVMContextCollectRemarks(ctx: LLVMContextRef,
+                                   pass_filter: *const c_char,
+                                   kinds: c_uint,
+                                   callback: extern "C" fn(*mut c_void,
+                                                           c_uint,
+                                                           *const c_char,
+                                                           *const c_char,
+                                                           *const c_char,
+                                                           *const c_char,
+                                                           *const c_char,
+                                                           c_uint,
+                                                           c_uint),
+                                   opaque: *mut c_void,
+                                   out_message: *mut *mut c_char)
+                                   -> LLVMRemarkCollectorRef;
+  pub fn LLVMContextStopCollectingRemarks(ctx: LLVMContextRef, collector: LLVMRemarkCollectorRef);
+}
+
+/// What a remark tells.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RemarkKind {
+  /// An optimization was applied.
+  Passed = 1,
+  /// An optimization was missed.
+  Missed = 2,
+  /// Why an optimization was missed or applied.
+  Analysis = 4,
+}
+
+impl RemarkKind {
+  fn from_bits(bits: c_uint) -> RemarkKind {
+    match bits {
+      1 => RemarkKind::Passed,
+      2 => RemarkKind::Missed,
+      _ => RemarkKind::Analysis,
+    }
+  }
+
+  fn yaml_tag(&self) -> &'static str {
+    match *self {
+      RemarkKind::Passed => "Passed",
+      RemarkKind::Missed => "Missed",
+      RemarkKind::Analysis => "Analysis",
+    }
+  }
+}
+
+/// A source location, known from the debug info of the code.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RemarkLocation {
+  pub file: String,
+  pub line: u32,
+  pub column: u32,
+}
+
+/// A remark of a pass.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Remark {
+  pub kind: RemarkKind,
+  /// The name of the pass, e.g. `inline` or `loop-vectorize`.
+  pub pass: String,
+  /// The identifier of the remark within the pass, e.g. `NotInlined`.
+  pub name: String,
+  /// The function the remark is about.
+  pub function: String,
+  pub message: String,
+  pub location: Option<RemarkLocation>,
+}
+
+/// Which remarks to collect.
+#[derive(Clone, Debug)]
+pub struct RemarkFilter {
+  /// A regular expression matching the whole names of the passes, e.g.
+  /// `inline|loop-vectorize`.
+  pub passes: String,
+  pub passed: bool,
+  pub missed: bool,
+  pub analysis: bool,
+}
+
+impl Default for RemarkFilter {
+  /// Collect all remarks of all passes.
+  fn default() -> RemarkFilter {
+    RemarkFilter {
+      passes: ".*".to_string(),
+      passed: true,
+      missed: true,
+      analysis: true,
+    }
+  }
+}
+
+impl RemarkFilter {
+  fn kinds(&self) -> c_uint {
+    (self.passed as c_uint * RemarkKind::Passed as c_uint) |
+    (self.missed as c_uint * RemarkKind::Missed as c_uint) |
+    (self.analysis as c_uint * RemarkKind::Analysis as c_uint)
+  }
+}
+
+extern "C" fn on_remark(opaque: *mut c_void,
+                        kind: c_uint,
+                        pass: *const c_char,
+                        name: *const c_char,
+                        function: *const c_char,
+                        message: *const c_char,
+                        file: *const c_char,
+                        line: c_uint,
+                        column: c_uint) {
+  let remarks = unsafe { &*(opaque as *const RefCell<Vec<Remark>>) };
+  let location = chars::to_nullable_str(file).map(|file| {
+    RemarkLocation {
+      file: file.to_string(),
+      line: line as u32,
+      column: column as u32,
+    }
+  });
+
+  remarks.borrow_mut().push(Remark {
+    kind: RemarkKind::from_bits(kind),
+    pass: chars::to_str(pass).to_string(),
+    name: chars::to_str(name).to_string(),
+    function: chars::to_str(function).to_string(),
+    message: chars::to_str(message).to_string(),
+    location: location,
+  });
+}
+
+/// Collects the remarks of a context until it is dropped.
+pub struct RemarkCollector {
+  ctx: LLVMContextRef,
+  collector: LLVMRemarkCollectorRef,
+  // Referred to by `collector`, so it must live as long.
+  remarks: Box<RefCell<Vec<Remark>>>,
+}
+
+impl RemarkCollector {
+  /// Start collecting the remarks of `ctx` selected by `filter`, returning an error string
+  /// if its regular expression is invalid.
+  pub fn new(ctx: LLVMContextRef, filter: &RemarkFilter) -> Result<RemarkCollector, String> {
+    let passes = try!(CString::new(filter.passes.as_str()).map_err(|e| e.to_string()));
+    let remarks = Box::new(RefCell::new(Vec::new()));
+    let opaque = &*remarks as *const RefCell<Vec<Remark>> as *mut c_void;
+
+    unsafe {
+      let mut error: *mut c_char = mem::uninitialized();
+      let collector = LLVMContextCollectRemarks(ctx, passes.as_ptr(), filter.kinds(), on_remark,
+                                                opaque, &mut error);
+      if collector.is_null() {
+        let error_str = chars::to_str(error).to_string();
+        ::libc::free(error as *mut c_void);
+        return Err(format!("invalid pass filter {}: {}", filter.passes, error_str));
+      }
+
+      Ok(RemarkCollector {
+        ctx: ctx,
+        collector: collector,
+        remarks: remarks,
+      })
+    }
+  }
+
+  /// Returns the remarks collected so far.
+  pub fn remarks(&self) -> Vec<Remark> {
+    self.remarks.borrow().clone()
+  }
+
+  /// Returns the remarks collected so far, and forget them.
+  pub fn take(&self) -> Vec<Remark> {
+    mem::replace(&mut *self.remarks.borrow_mut(), Vec::new())
+  }
+}
+
+impl Drop for RemarkCollector {
+  fn drop(&mut self) {
+    unsafe { LLVMContextStopCollectingRemarks(self.ctx, self.collector) }
+  }
+}
+
+// Quote `s` as a single-quoted YAML scalar.
+fn yaml_quote(s: &str) -> String {
+  format!("'{}'", s.replace('\'', "''").replace('\n', " "))
+}
+
+/// Returns `remarks` as YAML documents, like those written by LLVM's
+/// `-pass-remarks-output` option, so they can be read by tools such as `opt-viewer`.
+pub fn to_yaml(remarks: &[Remark]) -> String {
+  let mut yaml = String::new();
+  for r in remarks {
+    yaml.push_str(&format!("--- !{}\n", r.kind.yaml_tag()));
+    yaml.push_str(&format!("Pass:            {}\n", yaml_quote(&r.pass)));
+    yaml.push_str(&format!("Name:            {}\n", yaml_quote(&r.name)));
+    if let Some(ref loc) = r.location {
+      yaml.push_str(&format!("DebugLoc:        {{ File: {}, Line: {}, Column: {} }}\n",
+                             yaml_quote(&loc.file),
+                             loc.line,
+                             loc.column));
+    }
+    yaml.push_str(&format!("Function:        {}\n", yaml_quote(&r.function)));
+    yaml.push_str("Args:\n");
+    yaml.push_str(&format!("  - String:          {}\n", yaml_quote(&r.message)));
+    yaml.push_str("...\n");
+  }
+  yaml
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::JitCompiler;
+  use types::{LLVMTy, Ty};
+  use value::{Predicate, ToValue, Value};
+
+  #[test]
+  fn test_collect_remarks() {
+    let jit = JitCompiler::new("test_remarks").ok().unwrap();
+    let ctx = jit.context();
+    let bld = jit.new_builder();
+    let i32_ty = i32::llvm_ty(ctx);
+    let sum = jit.add_global("sum", &i32_ty);
+    sum.set_initializer(&0i32.to_value(ctx));
+    let sum = Value::from(&sum);
+
+    // A loop adding 0 to 3 to `sum`, which gets fully unrolled.
+    let func = jit.create_func_prototype("add_four", &Ty::void_ty(ctx), &[], Some(&bld));
+    let entry = func.get_entry().unwrap();
+    let (body, exit) = (func.append("body"), func.append("exit"));
+    bld.create_br(&body);
+    bld.position_at_end(&body);
+    let i = bld.create_phi(&i32_ty, "i");
+    let i_val = Value::from(&i);
+    let old = bld.create_load(&sum);
+    bld.create_store(&bld.create_add(&old, &i_val), &sum);
+    let next = bld.create_add(&i_val, &1i32.to_value(ctx));
+    i.add_incoming(&0i32.to_value(ctx), &entry);
+    i.add_incoming(&next, &body);
+    bld.create_cond_br(&bld.create_cmp(&next, &4i32.to_value(ctx), Predicate::Lt), &body, &exit);
+    bld.position_at_end(&exit);
+    bld.create_ret_void();
+    jit.verify().unwrap();
+
+    let filter = RemarkFilter { passes: "loop-unroll".to_string(), ..RemarkFilter::default() };
+    let collector = RemarkCollector::new(ctx, &filter).unwrap();
+    jit.module().optimize(2, 0);
+
+    let remarks = collector.take();
+    assert!(!remarks.is_empty());
+    assert!(remarks.iter().all(|r| r.pass == "loop-unroll"));
+    let unrolled = remarks.iter().find(|r| r.kind == RemarkKind::Passed).unwrap();
+    assert_eq!("add_four", unrolled.function);
+    assert!(collector.remarks().is_empty());
+  }
+
+  #[test]
+  fn test_invalid_filter() {
+    let jit = JitCompiler::new("test_remarks").ok().unwrap();
+    let filter = RemarkFilter { passes: "(".to_string(), ..RemarkFilter::default() };
+    assert!(RemarkCollector::new(jit.context(), &filter).is_err());
+  }
+
+  #[test]
+  fn test_to_yaml() {
+    let remark = Remark {
+      kind: RemarkKind::Missed,
+      pass: "inline".to_string(),
+      name: "NoDefinition".to_string(),
+      function: "main".to_string(),
+      message: "foo will not be inlined into main because it's a declaration".to_string(),
+      location: Some(RemarkLocation { file: "query.sql".to_string(), line: 3, column: 7 }),
+    };
+
+    assert_eq!("--- !Missed\n\
+                Pass:            'inline'\n\
+                Name:            'NoDefinition'\n\
+                DebugLoc:        { File: 'query.sql', Line: 3, Column: 7 }\n\
+                Function:        'main'\n\
+                Args:\n  - String:          \
+                'foo will not be inlined into main because it''s a declaration'\n\
+                ...\n",
+               to_yaml(&[remark]));
+  }
+}
@@ -0,0 +1,112 @@
+//! Runtime Libraries
+//!
+//! A runtime library is a module of helper functions written in C or C++ and compiled
+//! into bitcode ahead of time. Generated code calls its helpers through declarations,
+//! and `RuntimeLibrary::link_into` resolves them by linking only the helpers a module
+//! uses, with `internal` linkage so the optimizer can inline them.
+//!
+//! The crate embeds the helpers of `runtime/runtime.cc` as `RuntimeLibrary::builtin`.
+//! Other libraries can be embedded the same way with `include_bytes!`.
+
+use std::borrow::Cow;
+
+use llvm_sys::core;
+
+use linker::LinkFlags;
+use module::Module;
+
+static BUILTIN_BITCODE: &'static [u8] = include_bytes!(concat!(env!("OUT_DIR"), "/runtime.bc"));
+
+/// Bitcode of helper functions to be linked into generated modules.
+pub struct RuntimeLibrary {
+  name: String,
+  bitcode: Cow<'static, [u8]>,
+}
+
+impl RuntimeLibrary {
+  /// Returns the runtime library built from `runtime/runtime.cc`, with string and hash
+  /// helpers such as `rt_hash_bytes` and `rt_str_eq`.
+  pub fn builtin() -> RuntimeLibrary {
+    RuntimeLibrary::from_bitcode("runtime", BUILTIN_BITCODE)
+  }
+
+  /// Create a runtime library from bitcode, usually embedded with `include_bytes!`.
+  ///
+  /// The bitcode is only parsed when it is linked.
+  pub fn from_bitcode<B: Into<Cow<'static, [u8]>>>(name: &str, bitcode: B) -> RuntimeLibrary {
+    RuntimeLibrary {
+      name: name.to_string(),
+      bitcode: bitcode.into(),
+    }
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// Link the functions of this library which `m` declares into `m`, along with whatever
+  /// they use themselves, returning an error string if an error occurs.
+  ///
+  /// The linked functions get `internal` linkage, so they can be inlined and won't clash
+  /// with the same library linked into other modules.
+  pub fn link_into(&self, m: &Module) -> Result<(), String> {
+    let ctx = unsafe { core::LLVMGetModuleContext(m.0) };
+    let lib = try!(Module::from_bc_bytes(ctx, &self.name, &self.bitcode));
+    let flags = LinkFlags {
+      link_only_needed: true,
+      ..LinkFlags::default()
+    };
+
+    m.link_in_internalize(lib, flags, |_| true).map_err(|e| e.to_string())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::JitCompiler;
+  use llvm_sys::LLVMLinkage;
+  use constant::Constant;
+  use types::{LLVMTy, Ty};
+  use value::{ToValue, Value};
+
+  #[test]
+  fn test_link_builtin() {
+    let jit = JitCompiler::new("test_runtime").ok().unwrap();
+    let ctx = jit.context();
+    let bld = jit.new_builder();
+
+    let u8_ptr_ty = u8::llvm_ty(ctx).pointer_ty();
+    let hash = jit.create_func_prototype("rt_hash_bytes",
+                                         &u64::llvm_ty(ctx),
+                                         &[&u8_ptr_ty, &usize::llvm_ty(ctx)],
+                                         None);
+    let func = jit.create_func_prototype("hash_empty", &u64::llvm_ty(ctx), &[], Some(&bld));
+    let null: Value = Constant::null_ptr(&u8_ptr_ty).into();
+    bld.create_ret(&bld.create_call(&hash, &[&null, &0usize.to_value(ctx)]));
+
+    RuntimeLibrary::builtin().link_into(jit.module()).unwrap();
+    jit.verify().unwrap();
+
+    // Linking replaced the declaration with the definition, so `hash` is gone.
+    let hash = jit.get_func("rt_hash_bytes").unwrap();
+    unsafe {
+      assert_eq!(0, core::LLVMIsDeclaration(hash.0));
+      assert_eq!(LLVMLinkage::LLVMInternalLinkage, core::LLVMGetLinkage(hash.0));
+    }
+    assert!(jit.get_func("rt_str_eq").is_none());
+    assert!(jit.get_func("rt_hash_combine").is_none());
+
+    let hash_empty: fn() -> u64 = unsafe { ::std::mem::transmute(jit.get_func_ptr(&func).unwrap()) };
+    assert_eq!(0xcbf29ce484222325, hash_empty());
+  }
+
+  #[test]
+  fn test_link_nothing_needed() {
+    let jit = JitCompiler::new("test_runtime").ok().unwrap();
+    jit.create_func_prototype("unrelated", &Ty::void_ty(jit.context()), &[], None);
+
+    RuntimeLibrary::builtin().link_into(jit.module()).unwrap();
+    assert!(jit.get_func("rt_hash_bytes").is_none());
+  }
+}
@@ -0,0 +1,312 @@
+//! Background Compilation
+//!
+//! `JitCompiler` is bound to the thread which created it. A `CompileService` runs a pool
+//! of worker threads, which compile modules built by closures sent to them, each with a
+//! `JitCompiler` of its own. The compiled functions come back as `CompiledFn` handles,
+//! which can be sent to and called from any thread.
+//!
+//! The machine code of a function lives as long as its handles, even after the service
+//! is dropped. Once the last handle is gone, its worker drops the compiler, freeing the
+//! code and the module. A worker only exits once it is shut down and all of its handles
+//! are gone.
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+
+use super::{JitCompiler, JitOptions};
+use module::Module;
+use value::Function;
+
+type Job = Box<FnOnce(&mut Worker) + Send>;
+
+enum Msg {
+  Compile(Job),
+  // The last handle to the function compiled by the compiler of this id was dropped.
+  Release(usize),
+  Shutdown,
+}
+
+struct Worker {
+  opts: JitOptions,
+  tx: Sender<Msg>,
+  compiled: usize,
+  // The compilers of the functions which still have handles, by id. Each owns the module
+  // and machine code of its function.
+  live: HashMap<usize, JitCompiler>,
+}
+
+impl Worker {
+  // Returns the id of the compiler and the address of the function compiled.
+  fn compile<B>(&mut self, build: B) -> Result<(usize, *const ()), String>
+    where B: FnOnce(&JitCompiler, &Module) -> Result<Function, String>
+  {
+    self.compiled += 1;
+    let name = format!("compile_service_{}", self.compiled);
+    let jit = try!(JitCompiler::new_with_options(&name, &self.opts));
+
+    let func = try!(build(&jit, jit.module()));
+    try!(jit.verify());
+
+    let ptr = try!(unsafe { jit.get_func_ptr(&func) }
+      .ok_or_else(|| "the compiled function has no machine code".to_string()));
+    self.live.insert(self.compiled, jit);
+    Ok((self.compiled, ptr))
+  }
+
+  fn run(mut self, rx: Receiver<Msg>) {
+    let mut shutdown = false;
+
+    while !shutdown || !self.live.is_empty() {
+      match rx.recv() {
+        Ok(Msg::Compile(job)) => job(&mut self),
+        Ok(Msg::Release(id)) => {
+          self.live.remove(&id);
+        }
+        Ok(Msg::Shutdown) | Err(_) => shutdown = true,
+      }
+    }
+  }
+}
+
+// Tells the worker owning the machine code once the last handle to it is dropped.
+struct KeepAlive {
+  tx: Mutex<Sender<Msg>>,
+  id: usize,
+}
+
+impl Drop for KeepAlive {
+  fn drop(&mut self) {
+    if let Ok(tx) = self.tx.lock() {
+      let _ = tx.send(Msg::Release(self.id));
+    }
+  }
+}
+
+/// A function compiled by a `CompileService`, whose type is the function pointer type `F`
+/// given when compiling it, e.g. `extern "C" fn(u64) -> u64`.
+pub struct CompiledFn<F> {
+  ptr: *const (),
+  _keep_alive: Arc<KeepAlive>,
+  marker: PhantomData<F>,
+}
+
+// The machine code is never changed once compiled.
+unsafe impl<F> Send for CompiledFn<F> {}
+unsafe impl<F> Sync for CompiledFn<F> {}
+
+impl<F> Clone for CompiledFn<F> {
+  fn clone(&self) -> CompiledFn<F> {
+    CompiledFn {
+      ptr: self.ptr,
+      _keep_alive: self._keep_alive.clone(),
+      marker: PhantomData,
+    }
+  }
+}
+
+impl<F: Copy> CompiledFn<F> {
+  pub fn as_ptr(&self) -> *const () {
+    self.ptr
+  }
+
+  /// Returns the function pointer, which must not be used after this handle is dropped.
+  ///
+  /// This is marked as unsafe because `F` cannot be guaranteed to match the signature of
+  /// the function compiled.
+  pub unsafe fn get(&self) -> F {
+    debug_assert_eq!(mem::size_of::<F>(), mem::size_of::<*const ()>());
+    mem::transmute_copy(&self.ptr)
+  }
+}
+
+/// A function being compiled by a `CompileService`.
+pub struct PendingFn<F> {
+  rx: Receiver<Result<CompiledFn<F>, String>>,
+}
+
+impl<F> PendingFn<F> {
+  /// Block until the function is compiled, returning it or an error string if building or
+  /// compiling the module failed.
+  pub fn wait(self) -> Result<CompiledFn<F>, String> {
+    self.rx.recv().unwrap_or_else(|_| Err("the compile service stopped".to_string()))
+  }
+
+  /// Returns the function if it is compiled already, without blocking.
+  pub fn try_wait(&self) -> Option<Result<CompiledFn<F>, String>> {
+    match self.rx.try_recv() {
+      Ok(res) => Some(res),
+      Err(TryRecvError::Empty) => None,
+      Err(TryRecvError::Disconnected) => Some(Err("the compile service stopped".to_string())),
+    }
+  }
+}
+
+/// A pool of threads compiling modules in the background.
+pub struct CompileService {
+  workers: Vec<Mutex<Sender<Msg>>>,
+  next: AtomicUsize,
+}
+
+impl CompileService {
+  /// Start `threads` workers, compiling each function with a `JitCompiler` created with
+  /// `opts`.
+  pub fn new(threads: usize, opts: &JitOptions) -> CompileService {
+    assert!(threads > 0, "a compile service needs at least one thread");
+
+    let workers = (0..threads)
+      .map(|i| {
+        let (tx, rx) = mpsc::channel();
+        let worker_tx = tx.clone();
+        let opts = opts.clone();
+
+        thread::Builder::new()
+          .name(format!("llvm-compile-{}", i))
+          .spawn(move || {
+            let worker = Worker {
+              opts: opts,
+              tx: worker_tx,
+              compiled: 0,
+              live: HashMap::new(),
+            };
+            worker.run(rx)
+          })
+          .expect("failed to spawn a compile thread");
+        Mutex::new(tx)
+      })
+      .collect();
+
+    CompileService {
+      workers: workers,
+      next: AtomicUsize::new(0),
+    }
+  }
+
+  pub fn num_threads(&self) -> usize {
+    self.workers.len()
+  }
+
+  /// Compile the function returned by `build` on a worker thread.
+  ///
+  /// `build` is given a new `JitCompiler` and its empty module, to which it should add the
+  /// function and whatever it needs. The compiler is dropped along with the last handle
+  /// to the function.
+  pub fn compile<F, B>(&self, build: B) -> PendingFn<F>
+    where B: FnOnce(&JitCompiler, &Module) -> Result<Function, String> + Send + 'static,
+          F: 'static
+  {
+    let (tx, rx) = mpsc::channel();
+    let job = move |worker: &mut Worker| {
+      let res = worker.compile(build).map(|(id, ptr)| {
+        CompiledFn {
+          ptr: ptr,
+          _keep_alive: Arc::new(KeepAlive {
+            tx: Mutex::new(worker.tx.clone()),
+            id: id,
+          }),
+          marker: PhantomData,
+        }
+      });
+      let _ = tx.send(res);
+    };
+
+    // If the worker is gone, the job is dropped along with `tx`, and `wait` fails.
+    let i = self.next.fetch_add(1, Ordering::Relaxed) % self.workers.len();
+    if let Ok(w) = self.workers[i].lock() {
+      let _ = w.send(Msg::Compile(Box::new(job)));
+    }
+    PendingFn { rx: rx }
+  }
+}
+
+impl Drop for CompileService {
+  fn drop(&mut self) {
+    for w in &self.workers {
+      if let Ok(w) = w.lock() {
+        let _ = w.send(Msg::Shutdown);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::thread;
+  use types::LLVMTy;
+  use value::ToValue;
+
+  fn build_add(n: u64) -> impl FnOnce(&JitCompiler, &Module) -> Result<Function, String> + Send {
+    move |jit, module| {
+      let ctx = jit.context();
+      let bld = jit.new_builder();
+      let func = module.create_func_prototype("add", &u64::llvm_ty(ctx), &[&u64::llvm_ty(ctx)],
+                                              Some(&bld));
+      bld.create_ret(&bld.create_add(&func.arg(0).into(), &n.to_value(ctx)));
+      Ok(func)
+    }
+  }
+
+  #[test]
+  fn test_compile_service() {
+    let service = CompileService::new(2, &JitOptions::default());
+
+    let pending = (0..4u64).map(|n| service.compile::<extern "C" fn(u64) -> u64, _>(build_add(n)))
+      .collect::<Vec<_>>();
+    let funcs = pending.into_iter().map(|p| p.wait().unwrap()).collect::<Vec<_>>();
+
+    // Handles can be called from other threads, even after the service is gone.
+    drop(service);
+    let handles = funcs.into_iter()
+      .enumerate()
+      .map(|(n, f)| thread::spawn(move || assert_eq!(10 + n as u64, unsafe { f.get() }(10))))
+      .collect::<Vec<_>>();
+    for h in handles {
+      h.join().unwrap();
+    }
+  }
+
+  #[test]
+  fn test_release_drops_compiler() {
+    let (tx, rx) = mpsc::channel();
+    let mut worker = Worker {
+      opts: JitOptions::default(),
+      tx: tx.clone(),
+      compiled: 0,
+      live: HashMap::new(),
+    };
+
+    let (id, ptr) = worker.compile(build_add(1)).unwrap();
+    let add_one: extern "C" fn(u64) -> u64 = unsafe { mem::transmute(ptr) };
+    assert_eq!(2, add_one(1));
+    assert!(worker.live.contains_key(&id));
+
+    drop(KeepAlive { tx: Mutex::new(tx.clone()), id: id });
+    match rx.try_recv() {
+      Ok(Msg::Release(released)) => assert_eq!(id, released),
+      _ => panic!("no release message"),
+    }
+
+    // The worker exits once shut down only if the compiler was dropped.
+    tx.send(Msg::Release(id)).unwrap();
+    tx.send(Msg::Shutdown).unwrap();
+    worker.run(rx);
+  }
+
+  #[test]
+  fn test_compile_error() {
+    let service = CompileService::new(1, &JitOptions::default());
+
+    let failed = service.compile::<extern "C" fn(), _>(|_, _| Err("no function".to_string()));
+    assert_eq!(Some("no function".to_string()), failed.wait().err());
+
+    let invalid = service.compile::<extern "C" fn(), _>(|jit, module| {
+      Ok(module.create_func_prototype("empty", jit.get_void_ty(), &[], Some(&jit.new_builder())))
+    });
+    assert!(invalid.wait().is_err());
+  }
+}
@@ -0,0 +1,220 @@
+//! Compile Statistics
+//!
+//! A `CompileReport` tells where the time of a compilation went: the total time of each
+//! phase, i.e. building IR, optimizing it and emitting machine code, the time of each
+//! pass, and the statistics counted by LLVM, e.g. how many instructions were combined.
+//!
+//! The phase totals are always measured by `JitCompiler`. Pass timings and statistics are
+//! only collected once `enable` is called, or with `JitOptions::compile_stats`, and are
+//! process-wide: a report takes those of all compilations since the last one, on any
+//! thread. Like the command line options of LLVM it sets, collecting them is a setup of
+//! the process, which stays on once enabled. Statistics are only counted by LLVM builds with assertions or
+//! `LLVM_ENABLE_STATS`, so they may be empty even then.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use libc::{c_char, c_void};
+
+use util::chars;
+
+// Extended APIs, offering more APIs than LLVM C API does.
+extern "C" {
+  pub fn LLVMEnableCompileStatistics();
+  pub fn LLVMTakeCompileStatistics() -> *mut c_char;
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Start collecting pass timings and statistics, for all threads until the process exits.
+pub fn enable() {
+  if !ENABLED.swap(true, Ordering::SeqCst) {
+    unsafe { LLVMEnableCompileStatistics() }
+  }
+}
+
+pub fn is_enabled() -> bool {
+  ENABLED.load(Ordering::SeqCst)
+}
+
+/// The time spent running a pass, summed over each time it ran.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PassTiming {
+  /// The name of the pass, e.g. `InstCombinePass` for an optimization, or the command
+  /// line name of a pass of the code generator, e.g. `x86-isel`.
+  pub pass: String,
+  pub wall: Duration,
+  pub user: Duration,
+  pub system: Duration,
+}
+
+/// A statistic counted by LLVM, e.g. `NumCombined` of `instcombine`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Statistic {
+  /// The component counting it, usually the name of a pass.
+  pub component: String,
+  pub name: String,
+  pub value: u64,
+}
+
+/// Where the time of compilations went.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompileReport {
+  /// The time from creating the compiler until it first optimized or emitted code, which
+  /// is the time spent building IR.
+  pub construction: Duration,
+  /// The time spent in the optimization pipeline.
+  pub optimization: Duration,
+  /// The time spent emitting machine code.
+  pub codegen: Duration,
+  /// The time of each pass, optimizations and code generation alike, by descending
+  /// wall time.
+  pub passes: Vec<PassTiming>,
+  pub statistics: Vec<Statistic>,
+}
+
+impl CompileReport {
+  /// Returns the pass timings and statistics collected since the last call, with zero
+  /// phase totals.
+  pub fn take() -> CompileReport {
+    let json = unsafe {
+      let json = LLVMTakeCompileStatistics();
+      let s = chars::to_str(json).to_string();
+      ::libc::free(json as *mut c_void);
+      s
+    };
+    parse_json(&json)
+  }
+
+  /// Returns the sum of the phase totals.
+  pub fn total(&self) -> Duration {
+    self.construction + self.optimization + self.codegen
+  }
+
+  /// Returns the timing of the pass named `pass`, if it ran.
+  pub fn pass(&self, pass: &str) -> Option<&PassTiming> {
+    self.passes.iter().find(|p| p.pass == pass)
+  }
+
+  /// Returns the value of the statistic `name` of `component`, if it was counted.
+  pub fn statistic(&self, component: &str, name: &str) -> Option<u64> {
+    self.statistics.iter().find(|s| s.component == component && s.name == name).map(|s| s.value)
+  }
+}
+
+fn seconds(s: f64) -> Duration {
+  Duration::new(s.trunc() as u64, (s.fract() * 1e9).round() as u32)
+}
+
+// Parse the output of `LLVMTakeCompileStatistics`, with entries such as
+// `"instcombine.NumCombined": 12` and `"time.pass.instcombine.wall": 1.5e-03`.
+fn parse_json(json: &str) -> CompileReport {
+  let mut report = CompileReport::default();
+  let mut index = HashMap::new();
+
+  for line in json.lines() {
+    let line = line.trim().trim_end_matches(',');
+    let (key, value) = match line.find("\": ") {
+      Some(pos) if line.starts_with('"') => (&line[1..pos], &line[pos + 3..]),
+      _ => continue,
+    };
+
+    if key.starts_with("time.") {
+      // The group, then the pass, then the kind of time.
+      let mut parts = key[5..].splitn(2, '.');
+      let rest = match (parts.next(), parts.next()) {
+        (Some(_), Some(rest)) => rest,
+        _ => continue,
+      };
+      let (pass, kind) = match rest.rfind('.') {
+        Some(pos) => (&rest[..pos], &rest[pos + 1..]),
+        None => continue,
+      };
+      let time = match value.parse::<f64>() {
+        Ok(t) => seconds(t),
+        Err(_) => continue,
+      };
+
+      let i = *index.entry(pass.to_string()).or_insert_with(|| {
+        report.passes.push(PassTiming {
+          pass: pass.to_string(),
+          wall: Duration::new(0, 0),
+          user: Duration::new(0, 0),
+          system: Duration::new(0, 0),
+        });
+        report.passes.len() - 1
+      });
+      let timing = &mut report.passes[i];
+      match kind {
+        "wall" => timing.wall += time,
+        "user" => timing.user += time,
+        "sys" => timing.system += time,
+        _ => {}
+      }
+    } else if let Some(pos) = key.find('.') {
+      if let Ok(value) = value.parse::<u64>() {
+        report.statistics.push(Statistic {
+          component: key[..pos].to_string(),
+          name: key[pos + 1..].to_string(),
+          value: value,
+        });
+      }
+    }
+  }
+
+  report.passes.sort_by(|a, b| b.wall.cmp(&a.wall));
+  report
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::{JitCompiler, JitOptions};
+  use types::LLVMTy;
+
+  #[test]
+  fn test_parse_json() {
+    let report = parse_json("{\n\
+                             \t\"instcombine.NumCombined\": 12,\n\
+                             \t\"time.pass.instcombine.wall\": 1.5000000000000000e-03,\n\
+                             \t\"time.pass.instcombine.user\": 1.0000000000000000e-03,\n\
+                             \t\"time.pass.instcombine.sys\": 0.0000000000000000e+00,\n\
+                             \t\"time.pass.x86-isel.wall\": 1.0000000000000000e-03,\n\
+                             \t\"time.pass.instcombine.wall\": 5.0000000000000000e-04\n\
+                             }\n");
+
+    assert_eq!(Some(12), report.statistic("instcombine", "NumCombined"));
+    assert_eq!(vec!["instcombine", "x86-isel"],
+               report.passes.iter().map(|p| p.pass.as_str()).collect::<Vec<_>>());
+    let instcombine = report.pass("instcombine").unwrap();
+    assert_eq!(Duration::from_millis(2), instcombine.wall);
+    assert_eq!(Duration::from_millis(1), instcombine.user);
+    assert!(report.pass("inline").is_none());
+  }
+
+  #[test]
+  fn test_compile_report() {
+    let opts = JitOptions { compile_stats: true, ..JitOptions::default() };
+    let jit = JitCompiler::new_with_options("test_stats", &opts).ok().unwrap();
+    let ctx = jit.context();
+    let bld = jit.new_builder();
+    let i64_ty = i64::llvm_ty(ctx);
+    let func = jit.create_func_prototype("double", &i64_ty, &[&i64_ty], Some(&bld));
+    let x = func.arg(0).into();
+    bld.create_ret(&bld.create_add(&x, &x));
+
+    jit.optimize(2, 0);
+    let double: fn(i64) -> i64 = unsafe { ::std::mem::transmute(jit.get_func_ptr(&func).unwrap()) };
+    assert_eq!(42, double(21));
+
+    let report = jit.take_report();
+    assert!(report.optimization > Duration::new(0, 0));
+    assert!(report.codegen > Duration::new(0, 0));
+    assert!(report.pass("InstCombinePass").is_some());
+
+    let next = jit.take_report();
+    assert_eq!(Duration::new(0, 0), next.optimization);
+    assert_eq!(Duration::new(0, 0), next.codegen);
+  }
+}
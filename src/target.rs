@@ -0,0 +1,340 @@
+//! Targets and Cross-Compilation
+//!
+//! The JIT only initializes the native target. To generate code for other machines, e.g.
+//! object files for `aarch64-unknown-linux-gnu` on an x86-64 host, their targets are
+//! initialized with `initialize_all` or `initialize`, and a `TargetMachine` for the triple
+//! emits object files or assembly from modules. Only the targets LLVM was built with,
+//! listed by `llvm-config --targets-built`, are available.
+
+use std::ffi::CString;
+use std::mem;
+use std::ptr;
+
+use libc::c_char;
+use llvm_sys::core;
+use llvm_sys::prelude::{LLVMBool, LLVMMemoryBufferRef};
+use llvm_sys::target;
+use llvm_sys::target_machine::{self as tm, LLVMCodeGenFileType, LLVMCodeGenOptLevel,
+                               LLVMCodeModel, LLVMRelocMode, LLVMTargetMachineRef,
+                               LLVMTargetRef};
+
+use buffer::MemoryBuffer;
+use module::Module;
+use util::chars;
+
+// Extended APIs, offering more APIs than LLVM C API does.
+extern "C" {
+  pub fn LLVMInitializeTargetByName(name: *const c_char) -> LLVMBool;
+}
+
+/// Initialize all targets LLVM was built with, with their code generators and assembly
+/// printers and parsers.
+pub fn initialize_all() {
+  unsafe {
+    target::LLVM_InitializeAllTargetInfos();
+    target::LLVM_InitializeAllTargets();
+    target::LLVM_InitializeAllTargetMCs();
+    target::LLVM_InitializeAllAsmPrinters();
+    target::LLVM_InitializeAllAsmParsers();
+  }
+}
+
+/// Initialize the target named `name` as in LLVM's sources, e.g. `AArch64`, `ARM` or
+/// `X86`, ignoring case, returning an error string if LLVM wasn't built with it.
+pub fn initialize(name: &str) -> Result<(), String> {
+  let c_name = try!(CString::new(name).map_err(|e| e.to_string()));
+  if unsafe { LLVMInitializeTargetByName(c_name.as_ptr()) } == 0 {
+    Ok(())
+  } else {
+    Err(format!("LLVM was built without the target {}", name))
+  }
+}
+
+// Returns the message `msg` as a string, and dispose it.
+unsafe fn take_message(msg: *mut c_char) -> String {
+  let s = chars::to_str(msg).to_string();
+  core::LLVMDisposeMessage(msg);
+  s
+}
+
+/// Returns `triple` in its normal form, e.g. `aarch64-unknown-linux-gnu` for
+/// `aarch64-linux-gnu`, or an error string if it contains a NUL byte.
+pub fn normalize_triple(triple: &str) -> Result<String, String> {
+  let c_triple = try!(CString::new(triple).map_err(|e| e.to_string()));
+  Ok(unsafe { take_message(tm::LLVMNormalizeTargetTriple(c_triple.as_ptr())) })
+}
+
+/// A target registered in LLVM, i.e. an architecture code can be generated for.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Target(pub LLVMTargetRef);
+
+impl Target {
+  /// Returns the targets initialized so far.
+  pub fn all() -> Vec<Target> {
+    let mut targets = Vec::new();
+    let mut t = unsafe { tm::LLVMGetFirstTarget() };
+    while !t.is_null() {
+      targets.push(Target(t));
+      t = unsafe { tm::LLVMGetNextTarget(t) };
+    }
+    targets
+  }
+
+  /// Returns the target for `triple`, or an error string if it isn't initialized.
+  pub fn from_triple(triple: &str) -> Result<Target, String> {
+    let c_triple = try!(CString::new(triple).map_err(|e| e.to_string()));
+
+    unsafe {
+      let mut t: LLVMTargetRef = mem::uninitialized();
+      let mut err: *mut c_char = mem::uninitialized();
+      let ret = tm::LLVMGetTargetFromTriple(c_triple.as_ptr(), &mut t, &mut err);
+      llvm_ret!(ret, Target(t), err)
+    }
+  }
+
+  /// Returns the short name of this target, e.g. `aarch64` or `x86-64`.
+  pub fn name(&self) -> &str {
+    unsafe { chars::to_str(tm::LLVMGetTargetName(self.0)) }
+  }
+
+  pub fn description(&self) -> &str {
+    unsafe { chars::to_str(tm::LLVMGetTargetDescription(self.0)) }
+  }
+
+  /// Returns true if the JIT can generate code for this target.
+  pub fn has_jit(&self) -> bool {
+    unsafe { tm::LLVMTargetHasJIT(self.0) != 0 }
+  }
+
+  /// Returns true if object files can be emitted for this target.
+  pub fn has_target_machine(&self) -> bool {
+    unsafe {
+      tm::LLVMTargetHasTargetMachine(self.0) != 0 && tm::LLVMTargetHasAsmBackend(self.0) != 0
+    }
+  }
+}
+
+/// How code refers to addresses, which matters to how object files can be linked.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RelocMode {
+  /// The default of the target.
+  Default,
+  /// Absolute addresses, for executables.
+  Static,
+  /// Position independent code, for shared libraries.
+  PIC,
+}
+
+/// Options of a `TargetMachine`.
+#[derive(Clone, Debug)]
+pub struct TargetOptions {
+  /// The CPU to generate code for, e.g. `neoverse-n1`, or `generic`.
+  pub cpu: String,
+  /// The features to enable or disable on top of those of the CPU, e.g. `+sve,-neon`.
+  pub features: String,
+  /// Optimization level of the code generator, from 0 to 3.
+  pub opt_level: usize,
+  pub reloc: RelocMode,
+}
+
+impl Default for TargetOptions {
+  fn default() -> TargetOptions {
+    TargetOptions {
+      cpu: "generic".to_string(),
+      features: String::new(),
+      opt_level: 2,
+      reloc: RelocMode::Default,
+    }
+  }
+}
+
+/// The kind of file a `TargetMachine` emits.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FileType {
+  Assembly,
+  Object,
+}
+
+/// Generates code for a target triple.
+pub struct TargetMachine(pub LLVMTargetMachineRef);
+impl_dispose!(TargetMachine, tm::LLVMDisposeTargetMachine);
+
+impl TargetMachine {
+  /// Create a machine generating code for `triple`, e.g. `aarch64-linux-gnu`, returning
+  /// an error string if its target isn't initialized.
+  pub fn new(triple: &str, opts: &TargetOptions) -> Result<TargetMachine, String> {
+    let triple = try!(normalize_triple(triple));
+    let target = try!(Target::from_triple(&triple));
+    if !target.has_target_machine() {
+      return Err(format!("the target {} can't emit code", target.name()));
+    }
+
+    let c_triple = CString::new(triple.as_str()).unwrap();
+    let cpu = try!(CString::new(opts.cpu.as_str()).map_err(|e| e.to_string()));
+    let features = try!(CString::new(opts.features.as_str()).map_err(|e| e.to_string()));
+    let level = match opts.opt_level {
+      0 => LLVMCodeGenOptLevel::LLVMCodeGenLevelNone,
+      1 => LLVMCodeGenOptLevel::LLVMCodeGenLevelLess,
+      2 => LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
+      _ => LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
+    };
+    let reloc = match opts.reloc {
+      RelocMode::Default => LLVMRelocMode::LLVMRelocDefault,
+      RelocMode::Static => LLVMRelocMode::LLVMRelocStatic,
+      RelocMode::PIC => LLVMRelocMode::LLVMRelocPIC,
+    };
+
+    let machine = unsafe {
+      tm::LLVMCreateTargetMachine(target.0, c_triple.as_ptr(), cpu.as_ptr(), features.as_ptr(),
+                                  level, reloc, LLVMCodeModel::LLVMCodeModelDefault)
+    };
+    if machine.is_null() {
+      Err(format!("failed to create a target machine for {}", triple))
+    } else {
+      Ok(TargetMachine(machine))
+    }
+  }
+
+  pub fn target(&self) -> Target {
+    Target(unsafe { tm::LLVMGetTargetMachineTarget(self.0) })
+  }
+
+  pub fn triple(&self) -> String {
+    unsafe { take_message(tm::LLVMGetTargetMachineTriple(self.0)) }
+  }
+
+  pub fn cpu(&self) -> String {
+    unsafe { take_message(tm::LLVMGetTargetMachineCPU(self.0)) }
+  }
+
+  pub fn features(&self) -> String {
+    unsafe { take_message(tm::LLVMGetTargetMachineFeatureString(self.0)) }
+  }
+
+  /// Returns the data layout of the code this machine generates.
+  pub fn data_layout(&self) -> String {
+    unsafe {
+      let td = tm::LLVMCreateTargetDataLayout(self.0);
+      let layout = take_message(target::LLVMCopyStringRepOfTargetData(td));
+      target::LLVMDisposeTargetData(td);
+      layout
+    }
+  }
+
+  /// Set the triple and data layout of `m` to those of this machine. This should be done
+  /// before optimizing it, since optimizations depend on them.
+  pub fn configure_module(&self, m: &Module) {
+    let triple = CString::new(self.triple()).unwrap();
+    unsafe {
+      core::LLVMSetTarget(m.0, triple.as_ptr());
+      let td = tm::LLVMCreateTargetDataLayout(self.0);
+      target::LLVMSetModuleDataLayout(m.0, td);
+      target::LLVMDisposeTargetData(td);
+    }
+  }
+
+  /// Generate code for `m`, returning the contents of the file or an error string. The
+  /// triple and data layout of `m` are set to those of this machine.
+  pub fn emit(&self, m: &Module, file_type: FileType) -> Result<Vec<u8>, String> {
+    self.configure_module(m);
+
+    unsafe {
+      let mut buf: LLVMMemoryBufferRef = ptr::null_mut();
+      let mut err: *mut c_char = ptr::null_mut();
+      let ret = tm::LLVMTargetMachineEmitToMemoryBuffer(self.0, m.0, codegen_file_type(file_type),
+                                                        &mut err, &mut buf);
+      let buf = try!(llvm_ret!(ret, MemoryBuffer::from_ptr(buf), err));
+      Ok(buf.as_bytes().to_vec())
+    }
+  }
+
+  /// Returns the object file of `m`, e.g. an ELF file for a Linux triple.
+  pub fn emit_object(&self, m: &Module) -> Result<Vec<u8>, String> {
+    self.emit(m, FileType::Object)
+  }
+
+  /// Returns the assembly of `m`.
+  pub fn emit_assembly(&self, m: &Module) -> Result<String, String> {
+    let asm = try!(self.emit(m, FileType::Assembly));
+    String::from_utf8(asm).map_err(|e| e.to_string())
+  }
+
+  /// Write the code of `m` to the file at `path`.
+  pub fn emit_to_file(&self, m: &Module, path: &str, file_type: FileType) -> Result<(), String> {
+    self.configure_module(m);
+    let c_path = try!(CString::new(path).map_err(|e| e.to_string()));
+
+    unsafe {
+      let mut err: *mut c_char = ptr::null_mut();
+      let ret = tm::LLVMTargetMachineEmitToFile(self.0, m.0, c_path.as_ptr(),
+                                                codegen_file_type(file_type), &mut err);
+      llvm_ret!(ret, (), err)
+    }
+  }
+}
+
+fn codegen_file_type(file_type: FileType) -> LLVMCodeGenFileType {
+  match file_type {
+    FileType::Assembly => LLVMCodeGenFileType::LLVMAssemblyFile,
+    FileType::Object => LLVMCodeGenFileType::LLVMObjectFile,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::{JitCompiler, JitOptions};
+  use types::LLVMTy;
+  use value::ToValue;
+
+  fn build_add_one(jit: &JitCompiler, m: &Module) {
+    let ctx = jit.context();
+    let bld = jit.new_builder();
+    let i64_ty = i64::llvm_ty(ctx);
+    let func = m.create_func_prototype("add_one", &i64_ty, &[&i64_ty], Some(&bld));
+    bld.create_ret(&bld.create_add(&func.arg(0).into(), &1i64.to_value(ctx)));
+  }
+
+  #[test]
+  fn test_cross_compile() {
+    initialize_all();
+    let triple = "aarch64-linux-gnu";
+    let machine = match TargetMachine::new(triple, &TargetOptions::default()) {
+      Ok(machine) => machine,
+      // LLVM was built without AArch64.
+      Err(_) => return,
+    };
+    assert_eq!("aarch64-unknown-linux-gnu", machine.triple());
+    assert_eq!("aarch64", machine.target().name());
+    assert!(Target::all().contains(&machine.target()));
+
+    let opts = JitOptions { host_cpu: false, ..JitOptions::default() };
+    let jit = JitCompiler::new_with_options("test_target", &opts).ok().unwrap();
+    let m = Module::new(jit.context(), "kernels");
+    build_add_one(&jit, &m);
+
+    let object = machine.emit_object(&m).unwrap();
+    assert_eq!(b"\x7fELF", &object[..4]);
+    assert_eq!("aarch64-unknown-linux-gnu", m.target());
+    assert_eq!(machine.data_layout(), m.data_layout());
+
+    let asm = machine.emit_assembly(&m).unwrap();
+    assert!(asm.contains("add_one:"));
+  }
+
+  #[test]
+  fn test_unknown_target() {
+    assert!(initialize("NoSuchTarget").is_err());
+    if cfg!(target_arch = "x86_64") {
+      assert_eq!(Ok(()), initialize("x86"));
+    }
+    assert!(TargetMachine::new("nosucharch-unknown-linux-gnu", &TargetOptions::default()).is_err());
+  }
+
+  #[test]
+  fn test_normalize_triple() {
+    assert_eq!(Ok("aarch64-unknown-linux-gnu".to_string()), normalize_triple("aarch64-linux-gnu"));
+    assert!(normalize_triple("x86_64\0-linux-gnu").is_err());
+    assert!(TargetMachine::new("x86_64\0-linux-gnu", &TargetOptions::default()).is_err());
+  }
+}
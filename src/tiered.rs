@@ -0,0 +1,294 @@
+//! Tiered Compilation
+//!
+//! A `TieredCompiler` first compiles a function quickly, without optimization, so it
+//! can be called right away. Every call made through its `TieredFn` handle is counted,
+//! and once a function gets hot it is recompiled with optimization in the background.
+//! The handle switches to the optimized code as soon as it is ready, while calls keep
+//! going to the baseline code meanwhile. One-shot functions never pay for optimization.
+
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use super::{JitCompiler, JitOptions};
+use module::Module;
+use service::{CompiledFn, CompileService, PendingFn};
+use value::Function;
+
+/// The code a `TieredFn` calls.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Tier {
+  /// Compiled without optimization.
+  Baseline,
+  /// The function got hot, and is being recompiled with optimization.
+  Optimizing,
+  /// Recompiled with optimization.
+  Optimized,
+  /// Recompiling it with optimization failed, so it stays at the baseline code.
+  Failed,
+}
+
+impl Tier {
+  fn from_usize(n: usize) -> Tier {
+    match n {
+      0 => Tier::Baseline,
+      1 => Tier::Optimizing,
+      2 => Tier::Optimized,
+      _ => Tier::Failed,
+    }
+  }
+}
+
+type Optimize<F> = Box<FnOnce() -> PendingFn<F> + Send>;
+
+struct TieredState<F> {
+  target: AtomicPtr<()>,
+  calls: AtomicUsize,
+  tier: AtomicUsize,
+  hot_threshold: usize,
+  optimize: Mutex<Option<Optimize<F>>>,
+  pending: Mutex<Option<PendingFn<F>>>,
+  // Both tiers are kept alive, since a caller may still run the baseline code after the
+  // switch.
+  code: Mutex<Vec<CompiledFn<F>>>,
+}
+
+/// A handle to a function compiled by a `TieredCompiler`, whose type is the function
+/// pointer type `F`, e.g. `extern "C" fn(u64) -> u64`.
+pub struct TieredFn<F> {
+  state: Arc<TieredState<F>>,
+}
+
+impl<F> Clone for TieredFn<F> {
+  fn clone(&self) -> TieredFn<F> {
+    TieredFn { state: self.state.clone() }
+  }
+}
+
+impl<F: Copy + 'static> TieredFn<F> {
+  /// Returns the function pointer of the best code available, counting a call.
+  ///
+  /// This should be called for every call rather than keeping the pointer, so calls are
+  /// counted and go to the optimized code once it is ready. The pointer must not be used
+  /// after all handles to the function are dropped.
+  ///
+  /// This is marked as unsafe because `F` cannot be guaranteed to match the signature of
+  /// the function compiled.
+  pub unsafe fn get(&self) -> F {
+    let calls = self.state.calls.fetch_add(1, Ordering::Relaxed) + 1;
+    match self.tier() {
+      Tier::Baseline if calls >= self.state.hot_threshold => self.start_optimizing(),
+      Tier::Optimizing => self.install_optimized(false),
+      _ => {}
+    }
+
+    let ptr = self.state.target.load(Ordering::Acquire);
+    debug_assert_eq!(mem::size_of::<F>(), mem::size_of::<*const ()>());
+    mem::transmute_copy(&ptr)
+  }
+
+  /// Returns how many times `get` was called.
+  pub fn calls(&self) -> usize {
+    self.state.calls.load(Ordering::Relaxed)
+  }
+
+  pub fn tier(&self) -> Tier {
+    Tier::from_usize(self.state.tier.load(Ordering::Acquire))
+  }
+
+  /// Recompile the function with optimization now if it isn't yet, and block until it is
+  /// done, returning the tier reached.
+  pub fn wait_optimized(&self) -> Tier {
+    self.start_optimizing();
+    self.install_optimized(true);
+    self.tier()
+  }
+
+  // Start recompiling the function, unless it was started already.
+  fn start_optimizing(&self) {
+    // `pending` is held until the recompilation is sent, so `install_optimized` waits for
+    // it rather than finding nothing pending once the tier is `Optimizing`.
+    let mut pending = match self.state.pending.lock() {
+      Ok(pending) => pending,
+      Err(_) => return,
+    };
+    let started = self.state.tier.compare_exchange(Tier::Baseline as usize,
+                                                   Tier::Optimizing as usize,
+                                                   Ordering::AcqRel,
+                                                   Ordering::Acquire);
+    if started.is_ok() {
+      if let Some(optimize) = self.state.optimize.lock().ok().and_then(|mut o| o.take()) {
+        *pending = Some(optimize());
+      }
+    }
+  }
+
+  fn install_optimized(&self, block: bool) {
+    let guard = if block {
+      self.state.pending.lock().ok()
+    } else {
+      self.state.pending.try_lock().ok()
+    };
+    let mut pending = match guard {
+      Some(pending) => pending,
+      None => return,
+    };
+
+    let res = match pending.take() {
+      Some(p) if block => p.wait(),
+      Some(p) => {
+        match p.try_wait() {
+          Some(res) => res,
+          None => {
+            *pending = Some(p);
+            return;
+          }
+        }
+      }
+      None => return,
+    };
+
+    match res {
+      Ok(code) => {
+        self.state.target.store(code.as_ptr() as *mut (), Ordering::Release);
+        if let Ok(mut c) = self.state.code.lock() {
+          c.push(code);
+        }
+        self.state.tier.store(Tier::Optimized as usize, Ordering::Release);
+      }
+      Err(_) => self.state.tier.store(Tier::Failed as usize, Ordering::Release),
+    }
+  }
+}
+
+/// Compiles functions at a baseline tier first, and again with optimization once hot.
+pub struct TieredCompiler {
+  baseline: CompileService,
+  optimizing: Arc<CompileService>,
+  opt_level: usize,
+  hot_threshold: usize,
+}
+
+impl TieredCompiler {
+  /// Create a compiler recompiling functions with `opts.opt_level` once they are called
+  /// `hot_threshold` times, each tier with `threads` threads.
+  pub fn new(threads: usize, hot_threshold: usize, opts: &JitOptions) -> TieredCompiler {
+    let baseline_opts = JitOptions { opt_level: 0, ..opts.clone() };
+
+    TieredCompiler {
+      baseline: CompileService::new(threads, &baseline_opts),
+      optimizing: Arc::new(CompileService::new(threads, opts)),
+      opt_level: opts.opt_level,
+      hot_threshold: hot_threshold,
+    }
+  }
+
+  pub fn hot_threshold(&self) -> usize {
+    self.hot_threshold
+  }
+
+  /// Compile the function returned by `build` at the baseline tier, and block until it is
+  /// done, returning it or an error string.
+  ///
+  /// `build` is called again on another thread to recompile the function, so it must build
+  /// the same function each time. See `CompileService::compile` for what it is given.
+  pub fn compile<F, B>(&self, build: B) -> Result<TieredFn<F>, String>
+    where B: Fn(&JitCompiler, &Module) -> Result<Function, String> + Send + Sync + 'static,
+          F: Copy + 'static
+  {
+    let build = Arc::new(build);
+
+    let baseline_build = build.clone();
+    let baseline = try!(self.baseline.compile::<F, _>(move |jit, m| baseline_build(jit, m)).wait());
+
+    let service = self.optimizing.clone();
+    let opt_level = self.opt_level;
+    let optimize: Optimize<F> = Box::new(move || {
+      service.compile(move |jit, m| {
+        let func = try!(build(jit, m));
+        m.optimize(opt_level, 0);
+        Ok(func)
+      })
+    });
+
+    Ok(TieredFn {
+      state: Arc::new(TieredState {
+        target: AtomicPtr::new(baseline.as_ptr() as *mut ()),
+        calls: AtomicUsize::new(0),
+        tier: AtomicUsize::new(Tier::Baseline as usize),
+        hot_threshold: self.hot_threshold,
+        optimize: Mutex::new(Some(optimize)),
+        pending: Mutex::new(None),
+        code: Mutex::new(vec![baseline]),
+      }),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use types::LLVMTy;
+
+  fn build_sum(jit: &JitCompiler, module: &Module) -> Result<Function, String> {
+    let ctx = jit.context();
+    let bld = jit.new_builder();
+    let u64_ty = u64::llvm_ty(ctx);
+    let func = module.create_func_prototype("sum3", &u64_ty, &[&u64_ty], Some(&bld));
+    let x = func.arg(0).into();
+    bld.create_ret(&bld.create_add(&bld.create_add(&x, &x), &x));
+    Ok(func)
+  }
+
+  #[test]
+  fn test_tiered() {
+    let compiler = TieredCompiler::new(1, 3, &JitOptions::default());
+    let sum3 = compiler.compile::<extern "C" fn(u64) -> u64, _>(build_sum).unwrap();
+    assert_eq!(Tier::Baseline, sum3.tier());
+
+    for i in 0..3 {
+      assert_eq!(3 * i, unsafe { sum3.get() }(i));
+    }
+    assert!(sum3.tier() != Tier::Baseline);
+
+    assert_eq!(Tier::Optimized, sum3.wait_optimized());
+    assert_eq!(30, unsafe { sum3.get() }(10));
+    assert_eq!(4, sum3.calls());
+  }
+
+  #[test]
+  fn test_one_shot_stays_baseline() {
+    let compiler = TieredCompiler::new(1, 100, &JitOptions::default());
+    let sum3 = compiler.compile::<extern "C" fn(u64) -> u64, _>(build_sum).unwrap();
+
+    assert_eq!(6, unsafe { sum3.get() }(2));
+    assert_eq!(Tier::Baseline, sum3.tier());
+  }
+
+  #[test]
+  fn test_hot_threshold_0() {
+    let compiler = TieredCompiler::new(1, 0, &JitOptions::default());
+    let sum3 = compiler.compile::<extern "C" fn(u64) -> u64, _>(build_sum).unwrap();
+
+    assert_eq!(6, unsafe { sum3.get() }(2));
+    assert!(sum3.tier() != Tier::Baseline);
+    assert_eq!(Tier::Optimized, sum3.wait_optimized());
+  }
+
+  #[test]
+  fn test_concurrent_wait_optimized() {
+    let compiler = TieredCompiler::new(2, 100, &JitOptions::default());
+    let sum3 = compiler.compile::<extern "C" fn(u64) -> u64, _>(build_sum).unwrap();
+
+    let waiters = (0..4)
+      .map(|_| {
+        let sum3 = sum3.clone();
+        ::std::thread::spawn(move || sum3.wait_optimized())
+      })
+      .collect::<Vec<_>>();
+    for w in waiters {
+      assert_eq!(Tier::Optimized, w.join().unwrap());
+    }
+    assert_eq!(9, unsafe { sum3.get() }(3));
+  }
+}
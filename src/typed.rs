@@ -0,0 +1,477 @@
+//! Statically typed builder layer
+//!
+//! `Builder` works on untyped `Value`s, so mismatched operands are only caught
+//! by debug assertions or by the verifier. `TypedBuilder` builds the same
+//! instructions on `TypedValue<T>`, which carries the Rust type `T` of the
+//! value, so mixing types becomes a Rust compile error:
+//!
+//! ```compile_fail
+//! # use llvm::{JitCompiler, TypedBuilder};
+//! let jit = JitCompiler::new("typed").unwrap();
+//! let bld = TypedBuilder::new(jit.context(), jit.builder());
+//! let x = bld.constant(1i32);
+//! let y = bld.constant(1f64);
+//! bld.add(&x, &y); // expected `TypedValue<i32>`, found `TypedValue<f64>`
+//! ```
+
+use std::marker::PhantomData;
+
+use libc::c_char;
+use llvm_sys::core;
+use llvm_sys::prelude::{LLVMContextRef, LLVMValueRef};
+
+use super::LLVMRef;
+use block::BasicBlock;
+use builder::{Builder, CastOp};
+use constant::Constant;
+use types::{LLVMTy, Ty};
+use value::{Function, Predicate, ToValue, Value, ValueRef};
+
+/// The kind of a primitive type, which decides the instructions used for it.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum PrimKind {
+  Bool,
+  SignedInt,
+  UnsignedInt,
+  Float,
+}
+
+/// A Rust primitive type with an LLVM counterpart.
+pub trait Primitive: LLVMTy {
+  fn kind() -> PrimKind;
+  fn bits() -> usize;
+}
+
+/// Primitive types supporting arithmetic.
+pub trait Numeric: Primitive {}
+
+/// Primitive types supporting bitwise operations.
+pub trait Bitwise: Primitive {}
+
+/// Integer types, supporting shifts.
+pub trait Integer: Numeric + Bitwise {}
+
+macro_rules! impl_primitive (
+  ($ty:ty, $kind:ident, $bits:expr) => (
+    impl Primitive for $ty {
+      #[inline(always)]
+      fn kind() -> PrimKind { PrimKind::$kind }
+      #[inline(always)]
+      fn bits() -> usize { $bits }
+    }
+  );
+);
+
+impl_primitive!(bool, Bool, 1);
+impl_primitive!(i8, SignedInt, 8);
+impl_primitive!(u8, UnsignedInt, 8);
+impl_primitive!(i16, SignedInt, 16);
+impl_primitive!(u16, UnsignedInt, 16);
+impl_primitive!(i32, SignedInt, 32);
+impl_primitive!(u32, UnsignedInt, 32);
+impl_primitive!(i64, SignedInt, 64);
+impl_primitive!(u64, UnsignedInt, 64);
+impl_primitive!(i128, SignedInt, 128);
+impl_primitive!(u128, UnsignedInt, 128);
+impl_primitive!(f32, Float, 32);
+impl_primitive!(f64, Float, 64);
+
+impl Bitwise for bool {}
+
+macro_rules! impl_integer (
+  ($($ty:ty),*) => (
+    $(
+      impl Numeric for $ty {}
+      impl Bitwise for $ty {}
+      impl Integer for $ty {}
+    )*
+  );
+);
+
+impl_integer!(i8, u8, i16, u16, i32, u32, i64, u64, i128, u128);
+impl Numeric for f32 {}
+impl Numeric for f64 {}
+
+/// A pointer to `T`, as a type parameter of `TypedValue`.
+pub struct Ptr<T>(PhantomData<T>);
+
+impl<T: LLVMTy> LLVMTy for Ptr<T> {
+  fn llvm_ty(ctx: LLVMContextRef) -> Ty {
+    T::llvm_ty(ctx).pointer_ty()
+  }
+}
+
+/// A value whose type is the LLVM counterpart of the Rust type `T`.
+///
+/// It lives as long as the context it was created in, denoted by `'ctx`.
+pub struct TypedValue<'ctx, T: LLVMTy> {
+  value: Value,
+  marker: PhantomData<(&'ctx (), *const T)>,
+}
+
+impl<'ctx, T: LLVMTy> Clone for TypedValue<'ctx, T> {
+  fn clone(&self) -> Self {
+    TypedValue::new_unchecked(self.value)
+  }
+}
+
+impl<'ctx, T: LLVMTy> Copy for TypedValue<'ctx, T> {}
+
+impl<'ctx, T: LLVMTy> LLVMRef<LLVMValueRef> for TypedValue<'ctx, T> {
+  #[inline]
+  fn as_ref(&self) -> LLVMValueRef {
+    self.value.0
+  }
+}
+
+impl<'ctx, T: LLVMTy> ValueRef for TypedValue<'ctx, T> {}
+
+impl<'ctx, T: LLVMTy> From<TypedValue<'ctx, T>> for Value {
+  #[inline]
+  fn from(v: TypedValue<'ctx, T>) -> Value {
+    v.value
+  }
+}
+
+impl<'a, 'ctx, T: LLVMTy> From<&'a TypedValue<'ctx, T>> for Value {
+  #[inline]
+  fn from(v: &'a TypedValue<'ctx, T>) -> Value {
+    v.value
+  }
+}
+
+impl<'ctx, T: LLVMTy> TypedValue<'ctx, T> {
+  /// Wraps `value` without checking its type.
+  pub fn new_unchecked(value: Value) -> TypedValue<'ctx, T> {
+    TypedValue {
+      value: value,
+      marker: PhantomData,
+    }
+  }
+
+  /// Wraps `value`, or returns `None` if its type isn't the LLVM counterpart of `T`.
+  ///
+  /// LLVM integers don't carry signedness, so e.g. an `i32` value may be wrapped as both
+  /// `TypedValue<i32>` and `TypedValue<u32>`.
+  pub fn from_value(ctx: LLVMContextRef, value: Value) -> Option<TypedValue<'ctx, T>> {
+    if value.ty() == T::llvm_ty(ctx) {
+      Some(TypedValue::new_unchecked(value))
+    } else {
+      None
+    }
+  }
+
+  /// Returns the untyped value.
+  pub fn value(&self) -> Value {
+    self.value
+  }
+}
+
+/// Builds instructions on `TypedValue`s with an underlying `Builder`.
+pub struct TypedBuilder<'ctx> {
+  ctx: LLVMContextRef,
+  builder: &'ctx Builder,
+}
+
+impl<'ctx> TypedBuilder<'ctx> {
+  pub fn new(ctx: LLVMContextRef, builder: &'ctx Builder) -> TypedBuilder<'ctx> {
+    TypedBuilder {
+      ctx: ctx,
+      builder: builder,
+    }
+  }
+
+  /// Returns the untyped builder.
+  pub fn builder(&self) -> &'ctx Builder {
+    self.builder
+  }
+
+  /// Position the builder at the end of `block`.
+  pub fn position_at_end(&self, block: &BasicBlock) {
+    self.builder.position_at_end(block)
+  }
+
+  /// Returns the constant `val`.
+  pub fn constant<T: LLVMTy + ToValue>(&self, val: T) -> TypedValue<'ctx, T> {
+    TypedValue::new_unchecked(val.to_value(self.ctx))
+  }
+
+  /// Returns the `index`-th argument of `func`, or `None` if its type isn't `T`.
+  pub fn arg<T: LLVMTy>(&self, func: &Function, index: usize) -> Option<TypedValue<'ctx, T>> {
+    TypedValue::from_value(self.ctx, func.arg(index).into())
+  }
+
+  /// Build an instruction that returns `value` from the function.
+  pub fn ret<T: LLVMTy>(&self, value: &TypedValue<'ctx, T>) -> Value {
+    self.builder.create_ret(&value.value)
+  }
+
+  /// Build an instruction that allocates a `T` on the stack and returns the pointer to it.
+  pub fn alloca<T: LLVMTy>(&self) -> TypedValue<'ctx, Ptr<T>> {
+    TypedValue::new_unchecked(self.builder.create_alloca(&T::llvm_ty(self.ctx)))
+  }
+
+  /// Build an instruction that loads a `T` from `ptr`.
+  pub fn load<T: LLVMTy>(&self, ptr: &TypedValue<'ctx, Ptr<T>>) -> TypedValue<'ctx, T> {
+    TypedValue::new_unchecked(self.builder.create_load(&ptr.value))
+  }
+
+  /// Build an instruction that stores `val` into `ptr`.
+  pub fn store<T: LLVMTy>(&self, val: &TypedValue<'ctx, T>, ptr: &TypedValue<'ctx, Ptr<T>>)
+                          -> Value {
+    self.builder.create_store(&val.value, &ptr.value)
+  }
+
+  /// Build an instruction that yields `true_val` if `cond` is true, and `false_val` otherwise.
+  pub fn select<T: LLVMTy>(&self,
+                           cond: &TypedValue<'ctx, bool>,
+                           true_val: &TypedValue<'ctx, T>,
+                           false_val: &TypedValue<'ctx, T>)
+                           -> TypedValue<'ctx, T> {
+    TypedValue::new_unchecked(self.builder
+      .create_select(&cond.value, &true_val.value, &false_val.value))
+  }
+
+  pub fn add<T: Numeric>(&self, l: &TypedValue<'ctx, T>, r: &TypedValue<'ctx, T>)
+                         -> TypedValue<'ctx, T> {
+    TypedValue::new_unchecked(self.builder.create_add(&l.value, &r.value))
+  }
+
+  pub fn sub<T: Numeric>(&self, l: &TypedValue<'ctx, T>, r: &TypedValue<'ctx, T>)
+                         -> TypedValue<'ctx, T> {
+    TypedValue::new_unchecked(self.builder.create_sub(&l.value, &r.value))
+  }
+
+  pub fn mul<T: Numeric>(&self, l: &TypedValue<'ctx, T>, r: &TypedValue<'ctx, T>)
+                         -> TypedValue<'ctx, T> {
+    TypedValue::new_unchecked(self.builder.create_mul(&l.value, &r.value))
+  }
+
+  /// Build a division, which is signed or unsigned depending on `T`.
+  pub fn div<T: Numeric>(&self, l: &TypedValue<'ctx, T>, r: &TypedValue<'ctx, T>)
+                         -> TypedValue<'ctx, T> {
+    TypedValue::new_unchecked(match T::kind() {
+      PrimKind::UnsignedInt => self.builder.create_udiv(&l.value, &r.value),
+      _ => self.builder.create_div(&l.value, &r.value),
+    })
+  }
+
+  /// Build a remainder, which is signed or unsigned depending on `T`.
+  pub fn rem<T: Numeric>(&self, l: &TypedValue<'ctx, T>, r: &TypedValue<'ctx, T>)
+                         -> TypedValue<'ctx, T> {
+    TypedValue::new_unchecked(match T::kind() {
+      PrimKind::UnsignedInt => self.builder.create_urem(&l.value, &r.value),
+      _ => self.builder.create_rem(&l.value, &r.value),
+    })
+  }
+
+  pub fn neg<T: Numeric>(&self, v: &TypedValue<'ctx, T>) -> TypedValue<'ctx, T> {
+    TypedValue::new_unchecked(match T::kind() {
+      PrimKind::Float => self.builder.create_fneg(&v.value),
+      _ => self.builder.create_neg(&v.value),
+    })
+  }
+
+  pub fn and<T: Bitwise>(&self, l: &TypedValue<'ctx, T>, r: &TypedValue<'ctx, T>)
+                         -> TypedValue<'ctx, T> {
+    TypedValue::new_unchecked(self.builder.create_and(&l.value, &r.value))
+  }
+
+  pub fn or<T: Bitwise>(&self, l: &TypedValue<'ctx, T>, r: &TypedValue<'ctx, T>)
+                        -> TypedValue<'ctx, T> {
+    TypedValue::new_unchecked(self.builder.create_or(&l.value, &r.value))
+  }
+
+  pub fn xor<T: Bitwise>(&self, l: &TypedValue<'ctx, T>, r: &TypedValue<'ctx, T>)
+                         -> TypedValue<'ctx, T> {
+    TypedValue::new_unchecked(self.builder.create_xor(&l.value, &r.value))
+  }
+
+  pub fn not<T: Bitwise>(&self, v: &TypedValue<'ctx, T>) -> TypedValue<'ctx, T> {
+    TypedValue::new_unchecked(self.builder.create_not(&v.value))
+  }
+
+  pub fn shl<T: Integer>(&self, l: &TypedValue<'ctx, T>, r: &TypedValue<'ctx, T>)
+                         -> TypedValue<'ctx, T> {
+    TypedValue::new_unchecked(self.builder.create_shl(&l.value, &r.value))
+  }
+
+  /// Build a right shift, which is arithmetic or logical depending on the signedness of `T`.
+  pub fn shr<T: Integer>(&self, l: &TypedValue<'ctx, T>, r: &TypedValue<'ctx, T>)
+                         -> TypedValue<'ctx, T> {
+    TypedValue::new_unchecked(match T::kind() {
+      PrimKind::UnsignedInt => self.builder.create_lshr(&l.value, &r.value),
+      _ => self.builder.create_ashr(&l.value, &r.value),
+    })
+  }
+
+  /// Build a comparison, which is signed, unsigned or ordered depending on `T`.
+  pub fn cmp<T: Numeric>(&self,
+                         l: &TypedValue<'ctx, T>,
+                         r: &TypedValue<'ctx, T>,
+                         pred: Predicate)
+                         -> TypedValue<'ctx, bool> {
+    TypedValue::new_unchecked(match T::kind() {
+      PrimKind::UnsignedInt => self.builder.create_ucmp(&l.value, &r.value, pred),
+      _ => self.builder.create_cmp(&l.value, &r.value, pred),
+    })
+  }
+
+  /// Build a conversion of `v` from `T` into `U`, following the semantics of Rust's `as`
+  /// except that a conversion into `bool` tests for non-zero. Like `as`, a float converted
+  /// into an integer saturates, and NaN becomes `0`.
+  pub fn cast<T: Primitive, U: Primitive>(&self, v: &TypedValue<'ctx, T>) -> TypedValue<'ctx, U> {
+    let dest = U::llvm_ty(self.ctx);
+    let (from, to) = (T::kind(), U::kind());
+    let (from_bits, to_bits) = (T::bits(), U::bits());
+
+    let op = match (from, to) {
+      (PrimKind::Float, PrimKind::Float) if from_bits > to_bits => Some(CastOp::FPTrunc),
+      (PrimKind::Float, PrimKind::Float) if from_bits < to_bits => Some(CastOp::FPExt),
+      (PrimKind::Float, PrimKind::Float) => None,
+      (PrimKind::Float, PrimKind::SignedInt) => {
+        return TypedValue::new_unchecked(self.fp_to_int_sat("llvm.fptosi.sat", v, &dest));
+      }
+      (PrimKind::Float, PrimKind::UnsignedInt) => {
+        return TypedValue::new_unchecked(self.fp_to_int_sat("llvm.fptoui.sat", v, &dest));
+      }
+      (PrimKind::SignedInt, PrimKind::Float) => Some(CastOp::SIToFP),
+      (PrimKind::UnsignedInt, PrimKind::Float) |
+      (PrimKind::Bool, PrimKind::Float) => Some(CastOp::UIToFP),
+      (_, PrimKind::Bool) => {
+        return TypedValue::new_unchecked(self.cmp_non_zero(v).value);
+      }
+      (_, _) if from_bits > to_bits => Some(CastOp::Trunc),
+      (PrimKind::SignedInt, _) if from_bits < to_bits => Some(CastOp::SExt),
+      (_, _) if from_bits < to_bits => Some(CastOp::ZExt),
+      (_, _) => None,
+    };
+
+    TypedValue::new_unchecked(match op {
+      Some(op) => self.builder.create_cast(op, &v.value, &dest),
+      None => v.value,
+    })
+  }
+
+  /// Call the saturating conversion intrinsic `name` overloaded on `dest` and the type of `v`.
+  fn fp_to_int_sat<T: Primitive>(&self, name: &str, v: &TypedValue<'ctx, T>, dest: &Ty) -> Value {
+    let func = self.builder
+                   .get_insert_block()
+                   .parent()
+                   .expect("The builder must be positioned in a function");
+    let src = T::llvm_ty(self.ctx);
+    let mut tys = [dest.0, src.0];
+
+    let intrinsic = unsafe {
+      let id = core::LLVMLookupIntrinsicID(name.as_ptr() as *const c_char, name.len());
+      let module = core::LLVMGetGlobalParent(func.0);
+      Function(core::LLVMGetIntrinsicDeclaration(module, id, tys.as_mut_ptr(), tys.len()))
+    };
+    self.builder.create_call(&intrinsic, &[&v.value])
+  }
+
+  fn cmp_non_zero<T: Primitive>(&self, v: &TypedValue<'ctx, T>) -> TypedValue<'ctx, bool> {
+    if T::kind() == PrimKind::Bool {
+      return TypedValue::new_unchecked(v.value);
+    }
+
+    let zero = Constant::null(&T::llvm_ty(self.ctx)).into();
+    TypedValue::new_unchecked(self.builder.create_cmp(&v.value, &zero, Predicate::Ne))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::JitCompiler;
+
+  #[test]
+  fn test_typed_arith() {
+    let jit = JitCompiler::new("test_typed").ok().unwrap();
+    let ctx = jit.context();
+    let bld = TypedBuilder::new(ctx, jit.builder());
+
+    let func = jit.create_func_prototype("avg",
+                                         &f64::llvm_ty(ctx),
+                                         &[&u32::llvm_ty(ctx), &u32::llvm_ty(ctx)],
+                                         Some(jit.builder()));
+    let a = bld.arg::<u32>(&func, 0).unwrap();
+    let b = bld.arg::<u32>(&func, 1).unwrap();
+    assert!(bld.arg::<f64>(&func, 0).is_none());
+
+    let sum = bld.add(&bld.cast::<u32, u64>(&a), &bld.cast::<u32, u64>(&b));
+    let avg = bld.div(&bld.cast::<u64, f64>(&sum), &bld.constant(2f64));
+
+    let local = bld.alloca::<f64>();
+    bld.store(&avg, &local);
+    bld.ret(&bld.load(&local));
+    jit.verify().unwrap();
+
+    let avg: fn(u32, u32) -> f64 = unsafe { ::std::mem::transmute(jit.get_func_ptr(&func).unwrap()) };
+    assert_eq!(3.5, avg(3, 4));
+    assert_eq!(4294967295.0, avg(::std::u32::MAX, ::std::u32::MAX));
+  }
+
+  #[test]
+  fn test_typed_cmp() {
+    let jit = JitCompiler::new("test_typed").ok().unwrap();
+    let ctx = jit.context();
+    let bld = TypedBuilder::new(ctx, jit.builder());
+
+    let func = jit.create_func_prototype("max_u8",
+                                         &u8::llvm_ty(ctx),
+                                         &[&u8::llvm_ty(ctx), &u8::llvm_ty(ctx)],
+                                         Some(jit.builder()));
+    let a = bld.arg::<u8>(&func, 0).unwrap();
+    let b = bld.arg::<u8>(&func, 1).unwrap();
+
+    let gt = bld.cmp(&a, &b, Predicate::Gt);
+    bld.ret(&bld.select(&gt, &a, &b));
+    jit.verify().unwrap();
+
+    let max: fn(u8, u8) -> u8 = unsafe { ::std::mem::transmute(jit.get_func_ptr(&func).unwrap()) };
+    assert_eq!(200, max(200, 100));
+    assert_eq!(200, max(100, 200));
+  }
+
+  #[test]
+  fn test_typed_cast_float_saturates() {
+    let jit = JitCompiler::new("test_typed").ok().unwrap();
+    let ctx = jit.context();
+    let bld = TypedBuilder::new(ctx, jit.builder());
+
+    let func = jit.create_func_prototype("to_u8",
+                                         &u8::llvm_ty(ctx),
+                                         &[&f64::llvm_ty(ctx)],
+                                         Some(jit.builder()));
+    let x = bld.arg::<f64>(&func, 0).unwrap();
+    bld.ret(&bld.cast::<f64, u8>(&x));
+    jit.verify().unwrap();
+
+    let to_u8: fn(f64) -> u8 = unsafe { ::std::mem::transmute(jit.get_func_ptr(&func).unwrap()) };
+    assert_eq!(42, to_u8(42.9));
+    assert_eq!(255, to_u8(1e10));
+    assert_eq!(0, to_u8(-1.0));
+    assert_eq!(0, to_u8(::std::f64::NAN));
+  }
+
+  #[test]
+  fn test_typed_cast_to_bool() {
+    let jit = JitCompiler::new("test_typed").ok().unwrap();
+    let ctx = jit.context();
+    let bld = TypedBuilder::new(ctx, jit.builder());
+
+    let func = jit.create_func_prototype("is_non_zero",
+                                         &Ty::abi_bool_ty(ctx),
+                                         &[&i32::llvm_ty(ctx)],
+                                         Some(jit.builder()));
+    let x = bld.arg::<i32>(&func, 0).unwrap();
+    let non_zero = bld.cast::<i32, bool>(&x);
+    jit.builder().create_ret(&jit.builder().create_bool_to_abi(&non_zero.value()));
+    jit.verify().unwrap();
+
+    let is_non_zero: fn(i32) -> bool = unsafe { ::std::mem::transmute(jit.get_func_ptr(&func).unwrap()) };
+    assert!(is_non_zero(-7));
+    assert!(!is_non_zero(0));
+  }
+}
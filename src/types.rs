@@ -31,6 +31,58 @@ impl Ty {
     Ty(unsafe { core::LLVMVoidTypeInContext(ctx) })
   }
 
+  /// Returns the integer type with the given bit width, e.g. `i1`, `i24` or `i128`.
+  #[inline(always)]
+  pub fn int_ty(ctx: LLVMContextRef, bits: usize) -> Ty {
+    Ty(unsafe { core::LLVMIntTypeInContext(ctx, bits as c_uint) })
+  }
+
+  /// Returns `i8`, which is how a Rust or C `bool` is passed in memory and across calls.
+  ///
+  /// Inside generated code, booleans are `i1` like the result of a comparison. Use
+  /// `Builder::create_bool_to_abi` and `Builder::create_abi_to_bool` at the boundary.
+  #[inline(always)]
+  pub fn abi_bool_ty(ctx: LLVMContextRef) -> Ty {
+    Ty(unsafe { core::LLVMInt8TypeInContext(ctx) })
+  }
+
+  /// Returns the 16-bit IEEE floating-point type.
+  #[inline(always)]
+  pub fn half_ty(ctx: LLVMContextRef) -> Ty {
+    Ty(unsafe { core::LLVMHalfTypeInContext(ctx) })
+  }
+
+  /// Returns the 16-bit brain floating-point type.
+  #[inline(always)]
+  pub fn bfloat_ty(ctx: LLVMContextRef) -> Ty {
+    Ty(unsafe { core::LLVMBFloatTypeInContext(ctx) })
+  }
+
+  /// Returns the 128-bit IEEE floating-point type.
+  #[inline(always)]
+  pub fn fp128_ty(ctx: LLVMContextRef) -> Ty {
+    Ty(unsafe { core::LLVMFP128TypeInContext(ctx) })
+  }
+
+  /// Returns the 80-bit x87 extended precision floating-point type.
+  #[inline(always)]
+  pub fn x86_fp80_ty(ctx: LLVMContextRef) -> Ty {
+    Ty(unsafe { core::LLVMX86FP80TypeInContext(ctx) })
+  }
+
+  /// Returns the bit width of this integer type.
+  #[inline(always)]
+  pub fn int_width(&self) -> usize {
+    debug_assert!(self.is_integer(), "The type must be an integer type");
+    unsafe { core::LLVMGetIntTypeWidth(self.0) as usize }
+  }
+
+  /// Returns true if this type is `i1`, the type of booleans in generated code.
+  #[inline(always)]
+  pub fn is_bool(&self) -> bool {
+    self.is_integer() && self.int_width() == 1
+  }
+
   /// Returns true if the size of the type is known at compile-time.
   ///
   /// This is equivalent to the type implementing `Sized` in Rust
@@ -72,8 +124,12 @@ impl Ty {
   pub fn is_float(&self) -> bool {
     let kind = unsafe { core::LLVMGetTypeKind(self.into()) } as c_uint;
     kind == LLVMTypeKind::LLVMHalfTypeKind as c_uint ||
+    kind == LLVMTypeKind::LLVMBFloatTypeKind as c_uint ||
     kind == LLVMTypeKind::LLVMFloatTypeKind as c_uint ||
-    kind == LLVMTypeKind::LLVMDoubleTypeKind as c_uint
+    kind == LLVMTypeKind::LLVMDoubleTypeKind as c_uint ||
+    kind == LLVMTypeKind::LLVMX86_FP80TypeKind as c_uint ||
+    kind == LLVMTypeKind::LLVMFP128TypeKind as c_uint ||
+    kind == LLVMTypeKind::LLVMPPC_FP128TypeKind as c_uint
   }
 }
 
@@ -130,7 +186,7 @@ macro_rules! impl_llvm_ty (
   );
 );
 
-impl_llvm_ty!(bool, core::LLVMInt8TypeInContext);
+impl_llvm_ty!(bool, core::LLVMInt1TypeInContext);
 impl_llvm_ty!(i8,  core::LLVMInt8TypeInContext);
 impl_llvm_ty!(u8,  core::LLVMInt8TypeInContext);
 impl_llvm_ty!(i16, core::LLVMInt16TypeInContext);
@@ -139,6 +195,8 @@ impl_llvm_ty!(i32, core::LLVMInt32TypeInContext);
 impl_llvm_ty!(u32, core::LLVMInt32TypeInContext);
 impl_llvm_ty!(i64, core::LLVMInt64TypeInContext);
 impl_llvm_ty!(u64, core::LLVMInt64TypeInContext);
+impl_llvm_ty!(i128, core::LLVMInt128TypeInContext);
+impl_llvm_ty!(u128, core::LLVMInt128TypeInContext);
 impl_llvm_ty!(f32, core::LLVMFloatTypeInContext);
 impl_llvm_ty!(f64, core::LLVMDoubleTypeInContext);
 
@@ -163,14 +221,29 @@ mod tests {
   pub fn test_types() {
     let jit = JitCompiler::new("test1").ok().unwrap();
     let ctx = jit.context();
-    assert_eq!("i8", format!("{}", bool::llvm_ty(ctx)));
+    assert_eq!("i1", format!("{}", bool::llvm_ty(ctx)));
     assert_eq!("i8", format!("{}", i8::llvm_ty(ctx)));
     assert_eq!("i16", format!("{}", i16::llvm_ty(ctx)));
     assert_eq!("i32", format!("{}", i32::llvm_ty(ctx)));
     assert_eq!("i64", format!("{}", i64::llvm_ty(ctx)));
+    assert_eq!("i128", format!("{}", i128::llvm_ty(ctx)));
+    assert_eq!("i128", format!("{}", u128::llvm_ty(ctx)));
     assert_eq!("float", format!("{}", f32::llvm_ty(ctx)));
     assert_eq!("double", format!("{}", f64::llvm_ty(ctx)));
 
+    assert_eq!("i24", format!("{}", Ty::int_ty(ctx, 24)));
+    assert_eq!(24, Ty::int_ty(ctx, 24).int_width());
+    assert_eq!("i8", format!("{}", Ty::abi_bool_ty(ctx)));
+    assert!(bool::llvm_ty(ctx).is_bool());
+    assert!(!Ty::abi_bool_ty(ctx).is_bool());
+
+    assert_eq!("half", format!("{}", Ty::half_ty(ctx)));
+    assert_eq!("bfloat", format!("{}", Ty::bfloat_ty(ctx)));
+    assert_eq!("fp128", format!("{}", Ty::fp128_ty(ctx)));
+    assert_eq!("x86_fp80", format!("{}", Ty::x86_fp80_ty(ctx)));
+    assert!(Ty::fp128_ty(ctx).is_float());
+    assert!(Ty::bfloat_ty(ctx).is_float());
+
     // assert_eq!("[10 x double]",  format!("{}", Type::array_ty(&Type::f64_ty(&ctx), 10)));
   }
 
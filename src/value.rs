@@ -1,18 +1,37 @@
 #![allow(dead_code)]
+use std::ffi::CString;
 use std::fmt;
 use std::mem;
 
 use libc::{c_char, c_int, c_uint, c_ulonglong};
 use llvm_sys::core;
+use llvm_sys::debuginfo::LLVMSetSubprogram;
 use llvm_sys::LLVMAttribute;
-use llvm_sys::prelude::{LLVMContextRef, LLVMValueRef};
+use llvm_sys::prelude::{LLVMBool, LLVMContextRef, LLVMModuleRef, LLVMTypeRef, LLVMValueRef};
 
 use super::LLVMRef;
-use analysis::Verifier;
+use analysis::{Diagnostic, Lint, Verifier};
+use attribute::{Attr, AttrIndex, CallConv};
 use types::{FunctionTy, LLVMTy, Ty};
 use block::BasicBlock;
+use debuginfo::DISubprogram;
+use metadata::{self, MDNode};
+use module::Module;
 use util::HasContext;
 
+// Extended APIs, offering more APIs than LLVM C API does.
+extern "C" {
+  pub fn LLVMCloneFunctionIntoModule(f: LLVMValueRef, dest: LLVMModuleRef) -> LLVMValueRef;
+  pub fn LLVMGetVerifiedInlineAsm(fn_ty: LLVMTypeRef,
+                                  asm: *const c_char,
+                                  constraints: *const c_char,
+                                  has_side_effects: LLVMBool,
+                                  is_align_stack: LLVMBool,
+                                  dialect: c_uint,
+                                  out_message: *mut *mut c_char)
+                                  -> LLVMValueRef;
+}
+
 /// Comparative operations on values.
 #[derive(Copy, Clone, Eq, PartialEq)]
 pub enum Predicate {
@@ -52,6 +71,24 @@ pub trait ValueRef: LLVMRef<LLVMValueRef> {
     Ty(unsafe { core::LLVMTypeOf(self.as_ref()) })
   }
 
+  /// Returns true if this value is a constant
+  #[inline]
+  fn is_constant(&self) -> bool {
+    unsafe { core::LLVMIsConstant(self.as_ref()) != 0 }
+  }
+
+  /// Returns true if this value is the null value of its type, e.g. `0` or `null`
+  #[inline]
+  fn is_null(&self) -> bool {
+    unsafe { core::LLVMIsNull(self.as_ref()) != 0 }
+  }
+
+  /// Returns true if this value is `undef`
+  #[inline]
+  fn is_undef(&self) -> bool {
+    unsafe { core::LLVMIsUndef(self.as_ref()) != 0 }
+  }
+
   #[inline]
   fn dump(&self) {
     unsafe {
@@ -133,6 +170,29 @@ int_to_value!{u64}
 int_to_value!{usize}
 int_to_value!{isize}
 
+impl ToValue for bool {
+  fn to_value(&self, ctx: LLVMContextRef) -> Value {
+    Value(unsafe { core::LLVMConstInt(Self::llvm_ty(ctx).as_ptr(), *self as c_ulonglong, 0) })
+  }
+}
+
+macro_rules! int128_to_value (
+  ($ty:ty) => (
+    impl ToValue for $ty {
+      fn to_value(&self, ctx: LLVMContextRef) -> Value
+      {
+        let words = [*self as u64, (*self >> 64) as u64];
+        Value(unsafe {
+          core::LLVMConstIntOfArbitraryPrecision(Self::llvm_ty(ctx).as_ptr(), 2, words.as_ptr())
+        })
+      }
+    }
+  );
+);
+
+int128_to_value!{i128}
+int128_to_value!{u128}
+
 impl ToValue for f32 {
   fn to_value(&self, ctx: LLVMContextRef) -> Value {
     Value(unsafe { core::LLVMConstReal(Self::llvm_ty(ctx).as_ptr(), *self as f64) })
@@ -211,6 +271,24 @@ impl Arg {
   pub fn remove_attribute(&self, attr: Attribute) {
     unsafe { core::LLVMRemoveAttribute(self.into(), attr.into()) }
   }
+
+  /// Returns the function this argument belongs to.
+  pub fn parent(&self) -> Function {
+    Function(unsafe { core::LLVMGetParamParent(self.0) })
+  }
+
+  /// Returns the position of this argument in its function's parameter list.
+  pub fn index(&self) -> usize {
+    let func = self.parent();
+    (0..func.args_count())
+      .position(|i| func.arg(i).0 == self.0)
+      .expect("argument is not a parameter of its parent")
+  }
+
+  /// Add the enum or string attribute given to this argument.
+  pub fn add_attr(&self, attr: &Attr) {
+    self.parent().add_attr(AttrIndex::Param(self.index()), attr)
+  }
 }
 
 /// A function that can be called and contains blocks.
@@ -238,6 +316,17 @@ impl Function {
     Some(BasicBlock(unsafe { mem::transmute(core::LLVMGetEntryBasicBlock(self.0)) }))
   }
 
+  /// Returns the basic blocks of this function, the entry block first.
+  pub fn blocks(&self) -> Vec<BasicBlock> {
+    let mut blocks = Vec::new();
+    let mut bb = unsafe { core::LLVMGetFirstBasicBlock(self.0) };
+    while !bb.is_null() {
+      blocks.push(BasicBlock(bb));
+      bb = unsafe { core::LLVMGetNextBasicBlock(bb) };
+    }
+    blocks
+  }
+
   /// Returns the type of this value
   pub fn signature(&self) -> FunctionTy {
     FunctionTy(unsafe { core::LLVMTypeOf(self.0) })
@@ -305,9 +394,102 @@ impl Function {
     unsafe { core::LLVMRemoveFunctionAttr(self.into(), attr.into()) }
   }
 
+  /// Add the enum or string attribute given at the position `idx`.
+  pub fn add_attr(&self, idx: AttrIndex, attr: &Attr) {
+    unsafe { core::LLVMAddAttributeAtIndex(self.0, idx.into(), attr.0) }
+  }
+
+  /// Returns the enum attribute with the name given at the position `idx`, or `None` if
+  /// it is not set.
+  pub fn get_enum_attr(&self, idx: AttrIndex, name: &str) -> Option<Attr> {
+    Attr::kind_for_name(name).and_then(|kind| unsafe {
+      ::util::ret_nullable_ptr(core::LLVMGetEnumAttributeAtIndex(self.0, idx.into(), kind))
+    })
+  }
+
+  /// Returns the string attribute with the key given at the position `idx`, or `None` if
+  /// it is not set.
+  pub fn get_string_attr(&self, idx: AttrIndex, key: &str) -> Option<Attr> {
+    unsafe {
+      let attr = core::LLVMGetStringAttributeAtIndex(self.0,
+                                                     idx.into(),
+                                                     key.as_ptr() as *const c_char,
+                                                     key.len() as c_uint);
+      ::util::ret_nullable_ptr(attr)
+    }
+  }
+
+  /// Remove the enum attribute with the name given from the position `idx`.
+  pub fn remove_enum_attr(&self, idx: AttrIndex, name: &str) {
+    if let Some(kind) = Attr::kind_for_name(name) {
+      unsafe { core::LLVMRemoveEnumAttributeAtIndex(self.0, idx.into(), kind) }
+    }
+  }
+
+  /// Remove the string attribute with the key given from the position `idx`.
+  pub fn remove_string_attr(&self, idx: AttrIndex, key: &str) {
+    unsafe {
+      core::LLVMRemoveStringAttributeAtIndex(self.0,
+                                             idx.into(),
+                                             key.as_ptr() as *const c_char,
+                                             key.len() as c_uint)
+    }
+  }
+
+  /// Sets the calling convention of this function.
+  ///
+  /// Calls to this function must use the same calling convention.
+  pub fn set_call_conv(&self, cc: CallConv) {
+    unsafe { core::LLVMSetFunctionCallConv(self.0, cc as c_uint) }
+  }
+
+  /// Returns the calling convention of this function, or `None` if it is not one of `CallConv`.
+  pub fn get_call_conv(&self) -> Option<CallConv> {
+    CallConv::from_raw(unsafe { core::LLVMGetFunctionCallConv(self.0) })
+  }
+
+  /// Attach the debug info given to this function.
+  pub fn set_subprogram(&self, sp: &DISubprogram) {
+    unsafe { LLVMSetSubprogram(self.0, sp.0) }
+  }
+
+  /// Copy this function into `m`, which must belong to the same context, returning the
+  /// copy or an error string if `m` already defines a function of the same name.
+  ///
+  /// The functions and globals this function refers to are declared in `m`, except those
+  /// with internal or private linkage, which are copied too. A declaration of this
+  /// function already in `m` is replaced by the copy.
+  pub fn clone_into(&self, m: &Module) -> Result<Function, String> {
+    let name = self.name().unwrap_or("");
+    unsafe {
+      if self.context() != core::LLVMGetModuleContext(m.0) {
+        return Err(format!("module {} belongs to another context than function {}",
+                           m.name(),
+                           name));
+      }
+      if core::LLVMIsDeclaration(self.0) != 0 {
+        return Err(format!("function {} has no body to copy", name));
+      }
+
+      ::util::ret_nullable_ptr(LLVMCloneFunctionIntoModule(self.0, m.0))
+        .ok_or_else(|| format!("function {} is already defined in module {}", name, m.name()))
+    }
+  }
+
   pub fn verify(&self) -> Result<(), String> {
     Verifier::verify_func(self)
   }
+
+  /// Check this function for code which is most likely wrong. See `Lint`.
+  pub fn lint(&self) -> Vec<Diagnostic> {
+    Lint::check_func(self)
+  }
+
+  /// Returns the control flow graph of this function in the Graphviz DOT language, with
+  /// the instructions of each block.
+  pub fn to_dot(&self) -> String {
+    ::dot::function_cfg(self)
+  }
 }
 
 pub fn delete_func(func: &Function) {
@@ -325,6 +507,114 @@ impl IntoIterator for Function {
   }
 }
 
+/// An instruction, as returned by the `create_*` methods of `Builder`.
+#[derive(Copy, Clone)]
+pub struct Instruction(pub LLVMValueRef);
+impl_from_ref!(LLVMValueRef, Instruction);
+impl_from_into!(Value, Instruction);
+impl_from_into!(Instruction, Value);
+impl_display!(Instruction, LLVMPrintValueToString);
+impl ValueRef for Instruction {}
+
+impl HasContext for Instruction {
+  fn context(&self) -> LLVMContextRef {
+    self.ty().context()
+  }
+}
+
+impl Instruction {
+  /// Attach the metadata node given as the metadata kind `kind`, e.g. `tbaa` or `range`.
+  pub fn set_metadata(&self, kind: &str, node: &MDNode) {
+    let kind_id = metadata::kind_id(self.context(), kind);
+    unsafe { core::LLVMSetMetadata(self.0, kind_id, node.0) }
+  }
+
+  /// Returns the metadata node attached as `kind`, or `None` if there is none.
+  pub fn get_metadata(&self, kind: &str) -> Option<MDNode> {
+    let kind_id = metadata::kind_id(self.context(), kind);
+    unsafe { ::util::ret_nullable_ptr(core::LLVMGetMetadata(self.0, kind_id)) }
+  }
+
+  /// Returns true if any metadata is attached to this instruction.
+  pub fn has_metadata(&self) -> bool {
+    unsafe { core::LLVMHasMetadata(self.0) != 0 }
+  }
+
+  /// Returns the basic block containing this instruction.
+  pub fn parent(&self) -> BasicBlock {
+    BasicBlock(unsafe { core::LLVMGetInstructionParent(self.0) })
+  }
+
+  /// Mark a load as never yielding a null pointer.
+  pub fn set_nonnull(&self) {
+    self.set_metadata("nonnull", &MDNode::empty(self.context()))
+  }
+}
+
+/// A call or invoke instruction.
+///
+/// It is usually made from the value returned by `Builder::create_call`.
+pub struct CallSite(pub LLVMValueRef);
+impl_from_ref!(LLVMValueRef, CallSite);
+impl_from_into!(Value, CallSite);
+impl_from_into!(CallSite, Value);
+impl_display!(CallSite, LLVMPrintValueToString);
+impl ValueRef for CallSite {}
+
+impl CallSite {
+  /// Add the enum or string attribute given at the position `idx` of this call.
+  pub fn add_attr(&self, idx: AttrIndex, attr: &Attr) {
+    unsafe { core::LLVMAddCallSiteAttribute(self.0, idx.into(), attr.0) }
+  }
+
+  /// Returns the enum attribute with the name given at the position `idx`, or `None` if
+  /// it is not set.
+  pub fn get_enum_attr(&self, idx: AttrIndex, name: &str) -> Option<Attr> {
+    Attr::kind_for_name(name).and_then(|kind| unsafe {
+      ::util::ret_nullable_ptr(core::LLVMGetCallSiteEnumAttribute(self.0, idx.into(), kind))
+    })
+  }
+
+  /// Returns the string attribute with the key given at the position `idx`, or `None` if
+  /// it is not set.
+  pub fn get_string_attr(&self, idx: AttrIndex, key: &str) -> Option<Attr> {
+    unsafe {
+      let attr = core::LLVMGetCallSiteStringAttribute(self.0,
+                                                      idx.into(),
+                                                      key.as_ptr() as *const c_char,
+                                                      key.len() as c_uint);
+      ::util::ret_nullable_ptr(attr)
+    }
+  }
+
+  /// Remove the enum attribute with the name given from the position `idx`.
+  pub fn remove_enum_attr(&self, idx: AttrIndex, name: &str) {
+    if let Some(kind) = Attr::kind_for_name(name) {
+      unsafe { core::LLVMRemoveCallSiteEnumAttribute(self.0, idx.into(), kind) }
+    }
+  }
+
+  /// Sets the calling convention of this call. It must match that of the callee.
+  pub fn set_call_conv(&self, cc: CallConv) {
+    unsafe { core::LLVMSetInstructionCallConv(self.0, cc as c_uint) }
+  }
+
+  /// Returns the calling convention of this call, or `None` if it is not one of `CallConv`.
+  pub fn get_call_conv(&self) -> Option<CallConv> {
+    CallConv::from_raw(unsafe { core::LLVMGetInstructionCallConv(self.0) })
+  }
+
+  /// Marks this call as a tail call or not.
+  pub fn set_tail_call(&self, tail_call: bool) {
+    unsafe { core::LLVMSetTailCall(self.0, tail_call as c_int) }
+  }
+
+  /// Returns true if this call is marked as a tail call.
+  pub fn is_tail_call(&self) -> bool {
+    unsafe { core::LLVMIsTailCall(self.0) != 0 }
+  }
+}
+
 /// A way of indicating to LLVM how you want arguments / functions to be handled.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 #[repr(C)]
@@ -429,6 +719,62 @@ impl PhiNode {
   }
 }
 
+/// The syntax of inline assembly.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AsmDialect {
+  /// AT&T syntax, e.g. `movl $$1, %eax`.
+  Att = 0,
+  /// Intel syntax, e.g. `mov eax, 1`.
+  Intel = 1,
+}
+
+/// A piece of inline assembly, which can be called like a function with
+/// `Builder::create_asm_call`.
+///
+/// See http://llvm.org/docs/LangRef.html#inline-assembler-expressions
+pub struct InlineAsm(pub LLVMValueRef);
+impl_from_ref!(LLVMValueRef, InlineAsm);
+impl_from_into!(InlineAsm, Value);
+impl_display!(InlineAsm, LLVMPrintValueToString);
+impl ValueRef for InlineAsm {}
+
+impl InlineAsm {
+  /// Returns the inline assembly `asm` of the function type `fn_ty`, whose operands are
+  /// described by `constraints`, e.g. `=r,r,~{memory}`, or an error string if they don't
+  /// match the type. Operands are referred to as `$0`, `$1`, ... in `asm`.
+  ///
+  /// `side_effects` keeps the assembly from being removed or moved even if its results
+  /// aren't used, and `align_stack` aligns the stack before it as for a call.
+  pub fn new(fn_ty: &FunctionTy,
+             asm: &str,
+             constraints: &str,
+             side_effects: bool,
+             align_stack: bool,
+             dialect: AsmDialect)
+             -> Result<InlineAsm, String> {
+    let asm_str = try!(CString::new(asm).map_err(|e| e.to_string()));
+    let constraints_str = try!(CString::new(constraints).map_err(|e| e.to_string()));
+
+    unsafe {
+      let mut error: *mut c_char = mem::uninitialized();
+      let asm = LLVMGetVerifiedInlineAsm(fn_ty.0,
+                                         asm_str.as_ptr(),
+                                         constraints_str.as_ptr(),
+                                         side_effects as LLVMBool,
+                                         align_stack as LLVMBool,
+                                         dialect as c_uint,
+                                         &mut error);
+      if asm.is_null() {
+        let error_str = ::util::chars::to_str(error).to_string();
+        ::libc::free(error as *mut ::libc::c_void);
+        Err(format!("invalid constraints {}: {}", constraints, error_str))
+      } else {
+        Ok(InlineAsm(asm))
+      }
+    }
+  }
+}
+
 /// Value Iterator implementation.
 ///
 /// T can be all descendent types of LLVMValueRef.
@@ -483,6 +829,12 @@ mod tests {
     assert_eq!("i64 1", format!("{}", 1u64.to_value(jit.context())));
     assert_eq!("i64 1", format!("{}", 1isize.to_value(jit.context())));
     assert_eq!("i64 1", format!("{}", 1usize.to_value(jit.context())));
+    assert_eq!("i128 1", format!("{}", 1i128.to_value(jit.context())));
+    assert_eq!("i128 -1", format!("{}", (-1i128).to_value(jit.context())));
+    assert_eq!("i128 18446744073709551616",
+               format!("{}", (1u128 << 64).to_value(jit.context())));
+    assert_eq!("i1 true", format!("{}", true.to_value(jit.context())));
+    assert_eq!("i1 false", format!("{}", false.to_value(jit.context())));
 
     assert_eq!("float 1.000000e+00",
                format!("{}", 1f32.to_value(jit.context())));
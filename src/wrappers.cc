@@ -1,41 +1,60 @@
+#include "llvm/IR/DiagnosticInfo.h"
+#include "llvm/IR/DiagnosticPrinter.h"
 #include "llvm/IR/IRBuilder.h"
+#include "llvm/IR/Dominators.h"
 #include "llvm/IR/InlineAsm.h"
+#include "llvm/IR/InstVisitor.h"
+#include "llvm/IR/IntrinsicInst.h"
 #include "llvm/IR/LLVMContext.h"
 #include "llvm/IR/Module.h"
-#include "llvm/IR/InlineAsm.h"
-#include "llvm/IR/LLVMContext.h"
 #include "llvm/IR/Verifier.h"
-#include "llvm/Analysis/Passes.h"
-#include "llvm/Analysis/Lint.h"
+#include "llvm/Analysis/AliasAnalysis.h"
+#include "llvm/Analysis/AssumptionCache.h"
+#include "llvm/Analysis/BasicAliasAnalysis.h"
+#include "llvm/Analysis/ConstantFolding.h"
+#include "llvm/Analysis/InstructionSimplify.h"
+#include "llvm/Analysis/Loads.h"
+#include "llvm/Analysis/MemoryLocation.h"
+#include "llvm/Analysis/ScopedNoAliasAA.h"
+#include "llvm/Analysis/TargetLibraryInfo.h"
+#include "llvm/Analysis/TypeBasedAliasAnalysis.h"
+#include "llvm/Analysis/ValueTracking.h"
 #include "llvm/ADT/ArrayRef.h"
-#include "llvm/ADT/Triple.h"
-#include "llvm/ADT/DenseSet.h"
-#include "llvm/Support/CommandLine.h"
-#include "llvm/Support/FormattedStream.h"
+#include "llvm/ADT/DenseMap.h"
+#include "llvm/ADT/StringSet.h"
+#include "llvm/ADT/Statistic.h"
+#include "llvm/Pass.h"
+#include "llvm/Support/Regex.h"
 #include "llvm/Support/Timer.h"
 #include "llvm/Support/raw_ostream.h"
-#include "llvm/Support/TargetSelect.h"
-#include "llvm/Support/TargetRegistry.h"
-#include "llvm/Support/SourceMgr.h"
-#include "llvm/Support/Host.h"
-#include "llvm/Support/Debug.h"
-#include "llvm/Support/DynamicLibrary.h"
-#include "llvm/Support/Memory.h"
+#include "llvm/Support/KnownBits.h"
+#include "llvm/Support/Error.h"
+#include "llvm/Support/ErrorHandling.h"
+#include "llvm/TargetParser/Triple.h"
 #include "llvm/ExecutionEngine/ExecutionEngine.h"
 #include "llvm/ExecutionEngine/MCJIT.h"
-#include "llvm/ExecutionEngine/Interpreter.h"
+#include "llvm/ExecutionEngine/JITEventListener.h"
+#include "llvm/ExecutionEngine/ObjectCache.h"
+#include "llvm/ExecutionEngine/RTDyldMemoryManager.h"
+#include "llvm/Object/SymbolSize.h"
+#include "llvm/Passes/PassBuilder.h"
+#include "llvm/Passes/StandardInstrumentations.h"
+#include "llvm/Target/CodeGenCWrappers.h"
 #include "llvm/Target/TargetMachine.h"
 #include "llvm/Target/TargetOptions.h"
-#include "llvm/Transforms/Scalar.h"
-#include "llvm/Transforms/IPO.h"
-#include "llvm/Transforms/Instrumentation.h"
-#include "llvm/Transforms/Vectorize.h"
-#include "llvm/Bitcode/ReaderWriter.h"
+#include "llvm/Transforms/Utils/Cloning.h"
+#include "llvm/Transforms/Utils/ValueMapper.h"
+#include "llvm/Linker/Linker.h"
 #include "llvm-c/Analysis.h"
+#include "llvm-c/Error.h"
 #include "llvm-c/Core.h"
-#include "llvm-c/BitReader.h"
 #include "llvm-c/ExecutionEngine.h"
 #include "llvm-c/Object.h"
+#include "llvm-c/Target.h"
+#include "llvm-c/TargetMachine.h"
+
+#include <map>
+#include <vector>
 
 using namespace llvm;
 
@@ -43,7 +62,7 @@ extern "C" LLVMValueRef LLVMGetOrInsertFunction(LLVMModuleRef M,
                                                 const char* Name,
                                                 LLVMTypeRef FunctionTy) {
   return wrap(unwrap(M)->getOrInsertFunction(Name,
-                                             unwrap<FunctionType>(FunctionTy)));
+                                             unwrap<FunctionType>(FunctionTy)).getCallee());
 }
 
 extern "C" LLVMValueRef LLVMGetOrInsertGlobal(LLVMModuleRef M,
@@ -74,6 +93,1205 @@ extern "C" bool LLVMVerifyFunction2(LLVMValueRef Fn,
   return Result;
 }
 
+// Inline assembly
+
+// Returns the inline assembly Asm of the function type FnTy, or null with OutMessage set
+// if the constraints don't match the type.
+extern "C" LLVMValueRef LLVMGetVerifiedInlineAsm(LLVMTypeRef FnTy,
+                                                 const char *Asm,
+                                                 const char *Constraints,
+                                                 LLVMBool HasSideEffects,
+                                                 LLVMBool IsAlignStack,
+                                                 unsigned Dialect,
+                                                 char **OutMessage) {
+  FunctionType *Ty = unwrap<FunctionType>(FnTy);
+  if (Error Err = InlineAsm::verify(Ty, Constraints)) {
+    *OutMessage = strdup(toString(std::move(Err)).c_str());
+    return nullptr;
+  }
+  return wrap(InlineAsm::get(Ty, Asm, Constraints, HasSideEffects, IsAlignStack,
+                             static_cast<InlineAsm::AsmDialect>(Dialect)));
+}
+
+// Lint
+
+namespace {
+
+// The checks of LLVM's Lint pass, which prints its findings to dbgs(), i.e. stderr. These
+// are written to Messages instead, in the same format: a message, then the values it is
+// about, one per line.
+class LintChecker : public InstVisitor<LintChecker> {
+public:
+  LintChecker(Module *Mod, AAResults *AA, AssumptionCache *AC, DominatorTree *DT,
+              TargetLibraryInfo *TLI, raw_ostream &Messages)
+    : Mod(Mod), DL(&Mod->getDataLayout()), AA(AA), AC(AC), DT(DT), TLI(TLI),
+      Messages(Messages) {}
+
+  void visitFunction(Function &F);
+  void visitCallBase(CallBase &I);
+  void visitReturnInst(ReturnInst &I);
+  void visitLoadInst(LoadInst &I);
+  void visitStoreInst(StoreInst &I);
+  void visitXor(BinaryOperator &I);
+  void visitSub(BinaryOperator &I);
+  void visitLShr(BinaryOperator &I) { checkShift(I); }
+  void visitAShr(BinaryOperator &I) { checkShift(I); }
+  void visitShl(BinaryOperator &I) { checkShift(I); }
+  void visitSDiv(BinaryOperator &I) { checkDivisor(I); }
+  void visitUDiv(BinaryOperator &I) { checkDivisor(I); }
+  void visitSRem(BinaryOperator &I) { checkDivisor(I); }
+  void visitURem(BinaryOperator &I) { checkDivisor(I); }
+  void visitAllocaInst(AllocaInst &I);
+  void visitVAArgInst(VAArgInst &I);
+  void visitIndirectBrInst(IndirectBrInst &I);
+  void visitExtractElementInst(ExtractElementInst &I);
+  void visitInsertElementInst(InsertElementInst &I);
+  void visitUnreachableInst(UnreachableInst &I);
+
+private:
+  enum { Read = 1, Write = 2, Callee = 4, Branchee = 8 };
+
+  Module *Mod;
+  const DataLayout *DL;
+  AAResults *AA;
+  AssumptionCache *AC;
+  DominatorTree *DT;
+  TargetLibraryInfo *TLI;
+  raw_ostream &Messages;
+
+  void checkShift(BinaryOperator &I);
+  void checkDivisor(BinaryOperator &I);
+  void visitMemoryReference(Instruction &I, const MemoryLocation &Loc, MaybeAlign Align,
+                            Type *Ty, unsigned Flags);
+  bool isZero(Value *V);
+  Value *findValue(Value *V, bool OffsetOk);
+  Value *findValue(Value *V, bool OffsetOk, SmallPtrSetImpl<Value *> &Visited);
+
+  void writeValues(ArrayRef<const Value *> Vs) {
+    for (const Value *V : Vs) {
+      if (!V)
+        continue;
+      if (isa<Instruction>(V)) {
+        Messages << *V << '\n';
+      } else {
+        V->printAsOperand(Messages, true, Mod);
+        Messages << '\n';
+      }
+    }
+  }
+
+  template <typename... Ts>
+  void checkFailed(const Twine &Message, const Ts &...Vs) {
+    Messages << Message << '\n';
+    writeValues({Vs...});
+  }
+};
+
+} // end anonymous namespace
+
+// Reports the values given with Message unless C holds, and returns from the check.
+#define LINT_CHECK(C, ...)                                                                \
+  do {                                                                                    \
+    if (!(C)) {                                                                           \
+      checkFailed(__VA_ARGS__);                                                           \
+      return;                                                                             \
+    }                                                                                     \
+  } while (false)
+
+void LintChecker::visitFunction(Function &F) {
+  LINT_CHECK(F.hasName() || F.hasLocalLinkage(),
+             "Unusual: Unnamed function with non-local linkage", &F);
+}
+
+void LintChecker::visitCallBase(CallBase &I) {
+  Value *CalleeV = I.getCalledOperand();
+  visitMemoryReference(I, MemoryLocation::getAfter(CalleeV), std::nullopt, nullptr, Callee);
+
+  if (Function *F = dyn_cast<Function>(findValue(CalleeV, false))) {
+    LINT_CHECK(I.getCallingConv() == F->getCallingConv(),
+               "Undefined behavior: Caller and callee calling convention differ", &I);
+
+    FunctionType *FT = F->getFunctionType();
+    unsigned NumActualArgs = I.arg_size();
+    LINT_CHECK(FT->isVarArg() ? FT->getNumParams() <= NumActualArgs
+                              : FT->getNumParams() == NumActualArgs,
+               "Undefined behavior: Call argument count mismatches callee argument count",
+               &I);
+    LINT_CHECK(FT->getReturnType() == I.getType(),
+               "Undefined behavior: Call return type mismatches callee return type", &I);
+
+    Function::arg_iterator PI = F->arg_begin(), PE = F->arg_end();
+    auto AE = I.arg_end();
+    for (auto AI = I.arg_begin(); AI != AE && PI != PE; ++AI) {
+      Value *Actual = *AI;
+      Argument *Formal = &*PI++;
+      LINT_CHECK(Formal->getType() == Actual->getType(),
+                 "Undefined behavior: Call argument type mismatches callee parameter type",
+                 &I);
+
+      // Check that noalias arguments don't alias other arguments.
+      if (Formal->hasNoAliasAttr() && Actual->getType()->isPointerTy()) {
+        AttributeList PAL = I.getAttributes();
+        unsigned ArgNo = 0;
+        for (auto BI = I.arg_begin(); BI != AE; ++BI, ++ArgNo) {
+          // ByVal arguments are copied to the stack of the callee.
+          if (PAL.hasParamAttr(ArgNo, Attribute::ByVal))
+            continue;
+          if (Formal->onlyReadsMemory() && I.onlyReadsMemory(ArgNo))
+            continue;
+          if (I.doesNotAccessMemory(ArgNo))
+            continue;
+          if (AI != BI && (*BI)->getType()->isPointerTy() && !isa<ConstantPointerNull>(*BI)) {
+            AliasResult Result = AA->alias(*AI, *BI);
+            LINT_CHECK(Result != AliasResult::MustAlias &&
+                           Result != AliasResult::PartialAlias,
+                       "Unusual: noalias argument aliases another argument", &I);
+          }
+        }
+      }
+
+      // Check that an sret argument points to valid memory.
+      if (Formal->hasStructRetAttr() && Actual->getType()->isPointerTy()) {
+        Type *Ty = Formal->getParamStructRetType();
+        MemoryLocation Loc(Actual, LocationSize::precise(DL->getTypeStoreSize(Ty)),
+                           I.getAAMetadata());
+        visitMemoryReference(I, Loc, DL->getABITypeAlign(Ty), Ty, Read | Write);
+      }
+    }
+  }
+
+  if (const auto *CI = dyn_cast<CallInst>(&I)) {
+    if (CI->isTailCall()) {
+      const AttributeList &PAL = CI->getAttributes();
+      unsigned ArgNo = 0;
+      for (Value *Arg : I.args()) {
+        if (PAL.hasParamAttr(ArgNo++, Attribute::ByVal))
+          continue;
+        LINT_CHECK(!isa<AllocaInst>(findValue(Arg, true)),
+                   "Undefined behavior: Call with \"tail\" keyword references alloca", &I);
+      }
+    }
+  }
+
+  if (auto *II = dyn_cast<IntrinsicInst>(&I)) {
+    switch (II->getIntrinsicID()) {
+    default:
+      break;
+    case Intrinsic::memcpy: {
+      auto *MCI = cast<MemCpyInst>(&I);
+      visitMemoryReference(I, MemoryLocation::getForDest(MCI), MCI->getDestAlign(), nullptr,
+                           Write);
+      visitMemoryReference(I, MemoryLocation::getForSource(MCI), MCI->getSourceAlign(),
+                           nullptr, Read);
+
+      // Known partial overlap can't be told from knowing nothing, so only a full overlap
+      // is reported.
+      LocationSize Size = LocationSize::afterPointer();
+      if (auto *Len = dyn_cast<ConstantInt>(findValue(MCI->getLength(), false)))
+        if (Len->getValue().isIntN(32))
+          Size = LocationSize::precise(Len->getValue().getZExtValue());
+      LINT_CHECK(AA->alias(MCI->getSource(), Size, MCI->getDest(), Size) !=
+                     AliasResult::MustAlias,
+                 "Undefined behavior: memcpy source and destination overlap", &I);
+      break;
+    }
+    case Intrinsic::memmove: {
+      auto *MMI = cast<MemMoveInst>(&I);
+      visitMemoryReference(I, MemoryLocation::getForDest(MMI), MMI->getDestAlign(), nullptr,
+                           Write);
+      visitMemoryReference(I, MemoryLocation::getForSource(MMI), MMI->getSourceAlign(),
+                           nullptr, Read);
+      break;
+    }
+    case Intrinsic::memset: {
+      auto *MSI = cast<MemSetInst>(&I);
+      visitMemoryReference(I, MemoryLocation::getForDest(MSI), MSI->getDestAlign(), nullptr,
+                           Write);
+      break;
+    }
+    case Intrinsic::vastart:
+    case Intrinsic::vaend:
+    // Stackrestore sets the stack pointer, which may be read or written at any time.
+    case Intrinsic::stackrestore:
+      visitMemoryReference(I, MemoryLocation::getForArgument(&I, 0, TLI), std::nullopt,
+                           nullptr, Read | Write);
+      break;
+    case Intrinsic::vacopy:
+      visitMemoryReference(I, MemoryLocation::getForArgument(&I, 0, TLI), std::nullopt,
+                           nullptr, Write);
+      visitMemoryReference(I, MemoryLocation::getForArgument(&I, 1, TLI), std::nullopt,
+                           nullptr, Read);
+      break;
+    }
+  }
+}
+
+void LintChecker::visitReturnInst(ReturnInst &I) {
+  Function *F = I.getParent()->getParent();
+  LINT_CHECK(!F->doesNotReturn(),
+             "Unusual: Return statement in function with noreturn attribute", &I);
+
+  if (Value *V = I.getReturnValue())
+    LINT_CHECK(!isa<AllocaInst>(findValue(V, true)), "Unusual: Returning alloca value", &I);
+}
+
+void LintChecker::visitMemoryReference(Instruction &I, const MemoryLocation &Loc,
+                                       MaybeAlign Align, Type *Ty, unsigned Flags) {
+  if (Loc.Size.hasValue() && Loc.Size.getValue().getKnownMinValue() == 0)
+    return;
+
+  Value *Ptr = const_cast<Value *>(Loc.Ptr);
+  Value *Object = findValue(Ptr, true);
+  LINT_CHECK(!isa<ConstantPointerNull>(Object),
+             "Undefined behavior: Null pointer dereference", &I);
+  LINT_CHECK(!isa<UndefValue>(Object), "Undefined behavior: Undef pointer dereference", &I);
+  LINT_CHECK(!isa<ConstantInt>(Object) || !cast<ConstantInt>(Object)->isMinusOne(),
+             "Unusual: All-ones pointer dereference", &I);
+  LINT_CHECK(!isa<ConstantInt>(Object) || !cast<ConstantInt>(Object)->isOne(),
+             "Unusual: Address one pointer dereference", &I);
+
+  if (Flags & Write) {
+    if (auto *GV = dyn_cast<GlobalVariable>(Object))
+      LINT_CHECK(!GV->isConstant(), "Undefined behavior: Write to read-only memory", &I);
+    LINT_CHECK(!isa<Function>(Object) && !isa<BlockAddress>(Object),
+               "Undefined behavior: Write to text section", &I);
+  }
+  if (Flags & Read) {
+    LINT_CHECK(!isa<Function>(Object), "Unusual: Load from function body", &I);
+    LINT_CHECK(!isa<BlockAddress>(Object), "Undefined behavior: Load from block address",
+               &I);
+  }
+  if (Flags & Callee)
+    LINT_CHECK(!isa<BlockAddress>(Object), "Undefined behavior: Call to block address", &I);
+  if (Flags & Branchee)
+    LINT_CHECK(!isa<Constant>(Object) || isa<BlockAddress>(Object),
+               "Undefined behavior: Branch to non-blockaddress", &I);
+
+  // Buffer overflows and misalignment are only found in allocas and globals.
+  int64_t Offset = 0;
+  Value *Base = GetPointerBaseWithConstantOffset(Ptr, Offset, *DL);
+  uint64_t BaseSize = MemoryLocation::UnknownSize;
+  MaybeAlign BaseAlign;
+  if (auto *AI = dyn_cast<AllocaInst>(Base)) {
+    Type *ATy = AI->getAllocatedType();
+    if (!AI->isArrayAllocation() && ATy->isSized() && !ATy->isScalableTy())
+      BaseSize = DL->getTypeAllocSize(ATy).getFixedValue();
+    BaseAlign = AI->getAlign();
+  } else if (auto *GV = dyn_cast<GlobalVariable>(Base)) {
+    // Another definition of the global may be linked in otherwise.
+    if (GV->hasDefinitiveInitializer()) {
+      Type *GTy = GV->getValueType();
+      if (GTy->isSized() && !GTy->isScalableTy())
+        BaseSize = DL->getTypeAllocSize(GTy).getFixedValue();
+      BaseAlign = GV->getAlign();
+      if (!BaseAlign && GTy->isSized())
+        BaseAlign = DL->getABITypeAlign(GTy);
+    }
+  }
+
+  LINT_CHECK(!Loc.Size.hasValue() || Loc.Size.isScalable() ||
+                 BaseSize == MemoryLocation::UnknownSize ||
+                 (Offset >= 0 &&
+                  Offset + Loc.Size.getValue().getFixedValue() <= BaseSize),
+             "Undefined behavior: Buffer overflow", &I);
+
+  if (!Align && Ty && Ty->isSized())
+    Align = DL->getABITypeAlign(Ty);
+  if (BaseAlign && Align)
+    LINT_CHECK(*Align <= commonAlignment(*BaseAlign, Offset),
+               "Undefined behavior: Memory reference address is misaligned", &I);
+}
+
+void LintChecker::visitLoadInst(LoadInst &I) {
+  visitMemoryReference(I, MemoryLocation::get(&I), I.getAlign(), I.getType(), Read);
+}
+
+void LintChecker::visitStoreInst(StoreInst &I) {
+  visitMemoryReference(I, MemoryLocation::get(&I), I.getAlign(),
+                       I.getOperand(0)->getType(), Write);
+}
+
+void LintChecker::visitXor(BinaryOperator &I) {
+  LINT_CHECK(!isa<UndefValue>(I.getOperand(0)) || !isa<UndefValue>(I.getOperand(1)),
+             "Undefined result: xor(undef, undef)", &I);
+}
+
+void LintChecker::visitSub(BinaryOperator &I) {
+  LINT_CHECK(!isa<UndefValue>(I.getOperand(0)) || !isa<UndefValue>(I.getOperand(1)),
+             "Undefined result: sub(undef, undef)", &I);
+}
+
+void LintChecker::checkShift(BinaryOperator &I) {
+  if (auto *CI = dyn_cast<ConstantInt>(findValue(I.getOperand(1), false)))
+    LINT_CHECK(CI->getValue().ult(I.getType()->getScalarSizeInBits()),
+               "Undefined result: Shift count out of range", &I);
+}
+
+void LintChecker::checkDivisor(BinaryOperator &I) {
+  LINT_CHECK(!isZero(I.getOperand(1)), "Undefined behavior: Division by zero", &I);
+}
+
+// Returns true if V is zero, or undef which may be. A vector is zero if any of its
+// elements is.
+bool LintChecker::isZero(Value *V) {
+  if (isa<UndefValue>(V))
+    return true;
+
+  auto *VecTy = dyn_cast<FixedVectorType>(V->getType());
+  if (!VecTy)
+    return computeKnownBits(V, *DL).isZero();
+
+  auto *C = dyn_cast<Constant>(V);
+  if (!C)
+    return false;
+  if (C->isZeroValue())
+    return true;
+  for (unsigned I = 0, N = VecTy->getNumElements(); I != N; ++I) {
+    Constant *Elem = C->getAggregateElement(I);
+    if (isa<UndefValue>(Elem) || computeKnownBits(Elem, *DL).isZero())
+      return true;
+  }
+  return false;
+}
+
+void LintChecker::visitAllocaInst(AllocaInst &I) {
+  if (isa<ConstantInt>(I.getArraySize()))
+    LINT_CHECK(&I.getParent()->getParent()->getEntryBlock() == I.getParent(),
+               "Pessimization: Static alloca outside of entry block", &I);
+}
+
+void LintChecker::visitVAArgInst(VAArgInst &I) {
+  visitMemoryReference(I, MemoryLocation::get(&I), std::nullopt, nullptr, Read | Write);
+}
+
+void LintChecker::visitIndirectBrInst(IndirectBrInst &I) {
+  visitMemoryReference(I, MemoryLocation::getAfter(I.getAddress()), std::nullopt, nullptr,
+                       Branchee);
+  LINT_CHECK(I.getNumDestinations() != 0,
+             "Undefined behavior: indirectbr with no destinations", &I);
+}
+
+void LintChecker::visitExtractElementInst(ExtractElementInst &I) {
+  auto *VecTy = dyn_cast<FixedVectorType>(I.getVectorOperandType());
+  if (auto *CI = dyn_cast<ConstantInt>(findValue(I.getIndexOperand(), false)))
+    if (VecTy)
+      LINT_CHECK(CI->getValue().ult(VecTy->getNumElements()),
+                 "Undefined result: extractelement index out of range", &I);
+}
+
+void LintChecker::visitInsertElementInst(InsertElementInst &I) {
+  auto *VecTy = dyn_cast<FixedVectorType>(I.getType());
+  if (auto *CI = dyn_cast<ConstantInt>(findValue(I.getOperand(2), false)))
+    if (VecTy)
+      LINT_CHECK(CI->getValue().ult(VecTy->getNumElements()),
+                 "Undefined result: insertelement index out of range", &I);
+}
+
+void LintChecker::visitUnreachableInst(UnreachableInst &I) {
+  // Suspicious rather than undefined.
+  if (&I == &I.getParent()->front() || std::prev(I.getIterator())->mayHaveSideEffects())
+    return;
+  checkFailed("Unusual: unreachable immediately preceded by instruction without side "
+              "effects",
+              &I);
+}
+
+#undef LINT_CHECK
+
+// Returns the value V is known to be, looking through casts, loads of stored values and
+// the like. With OffsetOk, it returns the object V points into instead.
+Value *LintChecker::findValue(Value *V, bool OffsetOk) {
+  SmallPtrSet<Value *, 4> Visited;
+  return findValue(V, OffsetOk, Visited);
+}
+
+Value *LintChecker::findValue(Value *V, bool OffsetOk, SmallPtrSetImpl<Value *> &Visited) {
+  // Detect self-referential values, which may be found in unreachable code.
+  if (!Visited.insert(V).second)
+    return PoisonValue::get(V->getType());
+
+  V = OffsetOk ? getUnderlyingObject(V) : V->stripPointerCasts();
+
+  if (auto *L = dyn_cast<LoadInst>(V)) {
+    BasicBlock::iterator BBI = L->getIterator();
+    BasicBlock *BB = L->getParent();
+    SmallPtrSet<BasicBlock *, 4> VisitedBlocks;
+    while (VisitedBlocks.insert(BB).second) {
+      if (Value *U = FindAvailableLoadedValue(L, BB, BBI))
+        return findValue(U, OffsetOk, Visited);
+      if (BBI != BB->begin())
+        break;
+      BB = BB->getUniquePredecessor();
+      if (!BB)
+        break;
+      BBI = BB->end();
+    }
+  } else if (auto *PN = dyn_cast<PHINode>(V)) {
+    if (Value *W = PN->hasConstantValue())
+      return findValue(W, OffsetOk, Visited);
+  } else if (auto *CI = dyn_cast<CastInst>(V)) {
+    if (CI->isNoopCast(*DL))
+      return findValue(CI->getOperand(0), OffsetOk, Visited);
+  } else if (auto *Ex = dyn_cast<ExtractValueInst>(V)) {
+    if (Value *W = FindInsertedValue(Ex->getAggregateOperand(), Ex->getIndices()))
+      if (W != V)
+        return findValue(W, OffsetOk, Visited);
+  } else if (auto *CE = dyn_cast<ConstantExpr>(V)) {
+    if (Instruction::isCast(CE->getOpcode()) &&
+        CastInst::isNoopCast(Instruction::CastOps(CE->getOpcode()),
+                             CE->getOperand(0)->getType(), CE->getType(), *DL))
+      return findValue(CE->getOperand(0), OffsetOk, Visited);
+  }
+
+  if (auto *Inst = dyn_cast<Instruction>(V)) {
+    if (Value *W = simplifyInstruction(Inst, {*DL, TLI, DT, AC}))
+      return findValue(W, OffsetOk, Visited);
+  } else if (auto *C = dyn_cast<Constant>(V)) {
+    Value *W = ConstantFoldConstant(C, *DL, TLI);
+    if (W != V)
+      return findValue(W, OffsetOk, Visited);
+  }
+  return V;
+}
+
+// Returns what lint finds in Fn, which must have a body.
+extern "C" char *LLVMLintFunction(LLVMValueRef Fn) {
+  Function &F = *unwrap<Function>(Fn);
+
+  // The alias analyses of Lint, which unlike the default ones need no module analyses.
+  FunctionAnalysisManager FAM;
+  FAM.registerPass([] {
+    AAManager AA;
+    AA.registerFunctionAnalysis<BasicAA>();
+    AA.registerFunctionAnalysis<ScopedNoAliasAA>();
+    AA.registerFunctionAnalysis<TypeBasedAA>();
+    return AA;
+  });
+  PassBuilder PB;
+  PB.registerFunctionAnalyses(FAM);
+
+  std::string Messages;
+  raw_string_ostream OS(Messages);
+  LintChecker Checker(F.getParent(), &FAM.getResult<AAManager>(F),
+                      &FAM.getResult<AssumptionAnalysis>(F),
+                      &FAM.getResult<DominatorTreeAnalysis>(F),
+                      &FAM.getResult<TargetLibraryAnalysis>(F), OS);
+  Checker.visit(F);
+  OS.flush();
+  return strdup(Messages.c_str());
+}
+
+// Host targeting
+
+// Creates an MCJIT compiler for M like LLVMCreateMCJITCompilerForModule, with all of its
+// Options, generating code for the CPU and features given, e.g. "skylake" and
+// "+avx2,-avx512f". The target triple and data layout of M are set to those of the target
+// machine. Returns true and sets OutError on failure.
+extern "C" LLVMBool LLVMCreateMCJITCompilerForModuleWithCPU(
+    LLVMExecutionEngineRef *OutJIT, LLVMModuleRef M, const LLVMMCJITCompilerOptions *Options,
+    const char *CPU, const char *Features, char **OutError) {
+  SmallVector<StringRef, 64> FeatureList;
+  StringRef(Features).split(FeatureList, ',', -1, false);
+  std::vector<std::string> Attrs(FeatureList.begin(), FeatureList.end());
+
+  TargetOptions TargetOpts;
+  TargetOpts.EnableFastISel = Options->EnableFastISel;
+
+  Module *Mod = unwrap(M);
+  for (Function &F : *Mod)
+    F.addFnAttr("frame-pointer", Options->NoFramePointerElim ? "all" : "none");
+
+  std::string Error;
+  EngineBuilder Builder{std::unique_ptr<Module>(Mod)};
+  Builder.setEngineKind(EngineKind::JIT)
+      .setErrorStr(&Error)
+      .setOptLevel(static_cast<CodeGenOptLevel>(Options->OptLevel))
+      .setTargetOptions(TargetOpts)
+      .setMCPU(CPU)
+      .setMAttrs(Attrs);
+  bool JIT;
+  if (std::optional<CodeModel::Model> CM = unwrap(Options->CodeModel, JIT))
+    Builder.setCodeModel(*CM);
+  if (Options->MCJMM)
+    Builder.setMCJITMemoryManager(std::unique_ptr<RTDyldMemoryManager>(
+        reinterpret_cast<RTDyldMemoryManager *>(Options->MCJMM)));
+
+  TargetMachine *TM = Builder.selectTarget();
+  if (!TM) {
+    *OutError = strdup(Error.c_str());
+    return 1;
+  }
+#if LLVM_VERSION_MAJOR >= 21
+  Mod->setTargetTriple(TM->getTargetTriple());
+#else
+  Mod->setTargetTriple(TM->getTargetTriple().str());
+#endif
+  Mod->setDataLayout(TM->createDataLayout());
+
+  if (ExecutionEngine *EE = Builder.create(TM)) {
+    *OutJIT = wrap(EE);
+    return 0;
+  }
+  *OutError = strdup(Error.c_str());
+  return 1;
+}
+
+// Targets
+
+// Initializes the target named Name, e.g. "AArch64", with its code generator and
+// assembly printer and parser if it has them. Returns 0 on success, or 1 if LLVM wasn't
+// built with it.
+extern "C" LLVMBool LLVMInitializeTargetByName(const char *Name) {
+  std::string N = StringRef(Name).lower();
+  bool Found = false;
+#define LLVM_TARGET(TargetName)                                                           \
+  if (N == StringRef(#TargetName).lower()) {                                              \
+    LLVMInitialize##TargetName##TargetInfo();                                             \
+    LLVMInitialize##TargetName##Target();                                                 \
+    LLVMInitialize##TargetName##TargetMC();                                               \
+    Found = true;                                                                         \
+  }
+#include "llvm/Config/Targets.def"
+#undef LLVM_TARGET
+#define LLVM_ASM_PRINTER(TargetName)                                                      \
+  if (N == StringRef(#TargetName).lower())                                                \
+    LLVMInitialize##TargetName##AsmPrinter();
+#include "llvm/Config/AsmPrinters.def"
+#undef LLVM_ASM_PRINTER
+#define LLVM_ASM_PARSER(TargetName)                                                       \
+  if (N == StringRef(#TargetName).lower())                                                \
+    LLVMInitialize##TargetName##AsmParser();
+#include "llvm/Config/AsmParsers.def"
+#undef LLVM_ASM_PARSER
+  return Found ? 0 : 1;
+}
+
+// JIT event listeners
+
+extern "C" void LLVMExecutionEngineRegisterJITEventListener(LLVMExecutionEngineRef EE,
+                                                            LLVMJITEventListenerRef L) {
+  unwrap(EE)->RegisterJITEventListener(unwrap(L));
+}
+
+extern "C" void LLVMExecutionEngineUnregisterJITEventListener(LLVMExecutionEngineRef EE,
+                                                              LLVMJITEventListenerRef L) {
+  unwrap(EE)->UnregisterJITEventListener(unwrap(L));
+}
+
+// Kind is 0 when a function is emitted, and 1 when it is freed.
+typedef void (*LLVMJITEventCallback)(void *Opaque, int Kind, const char *Name,
+                                     uint64_t Addr, uint64_t Size);
+
+namespace {
+
+struct EmittedFunction {
+  std::string Name;
+  uint64_t Addr;
+  uint64_t Size;
+};
+
+class CallbackJITEventListener : public JITEventListener {
+public:
+  CallbackJITEventListener(LLVMJITEventCallback Callback, void *Opaque)
+    : Callback(Callback), Opaque(Opaque) {}
+
+  void notifyObjectLoaded(ObjectKey K, const object::ObjectFile &Obj,
+                          const RuntimeDyld::LoadedObjectInfo &L) override {
+    object::OwningBinary<object::ObjectFile> DebugObj = L.getObjectForDebug(Obj);
+    const object::ObjectFile *O = DebugObj.getBinary() ? DebugObj.getBinary() : &Obj;
+    std::vector<EmittedFunction> &Funcs = Emitted[K];
+
+    for (const auto &P : object::computeSymbolSizes(*O)) {
+      object::SymbolRef Sym = P.first;
+      Expected<object::SymbolRef::Type> Ty = Sym.getType();
+      if (!Ty) {
+        consumeError(Ty.takeError());
+        continue;
+      }
+      if (*Ty != object::SymbolRef::ST_Function)
+        continue;
+
+      Expected<StringRef> Name = Sym.getName();
+      Expected<uint64_t> Addr = Sym.getAddress();
+      if (!Name || !Addr) {
+        consumeError(Name.takeError());
+        consumeError(Addr.takeError());
+        continue;
+      }
+
+      EmittedFunction F = { Name->str(), *Addr, P.second };
+      Callback(Opaque, 0, F.Name.c_str(), F.Addr, F.Size);
+      Funcs.push_back(F);
+    }
+  }
+
+  void notifyFreeingObject(ObjectKey K) override {
+    auto It = Emitted.find(K);
+    if (It == Emitted.end())
+      return;
+
+    for (const EmittedFunction &F : It->second)
+      Callback(Opaque, 1, F.Name.c_str(), F.Addr, F.Size);
+    Emitted.erase(It);
+  }
+
+private:
+  LLVMJITEventCallback Callback;
+  void *Opaque;
+  std::map<ObjectKey, std::vector<EmittedFunction> > Emitted;
+};
+
+} // end anonymous namespace
+
+extern "C" LLVMJITEventListenerRef LLVMCreateCallbackJITEventListener(LLVMJITEventCallback Callback,
+                                                                      void *Opaque) {
+  return wrap(new CallbackJITEventListener(Callback, Opaque));
+}
+
+extern "C" void LLVMDisposeCallbackJITEventListener(LLVMJITEventListenerRef L) {
+  delete unwrap(L);
+}
+
+// Object caches
+
+// Called with the object emitted for M, to be stored.
+typedef void (*LLVMObjectCacheNotifyCallback)(void *Opaque, LLVMModuleRef M,
+                                              const char *Obj, size_t Size);
+// Returns the object stored for M, or null if there is none.
+typedef LLVMMemoryBufferRef (*LLVMObjectCacheGetCallback)(void *Opaque, LLVMModuleRef M);
+
+typedef struct LLVMOpaqueObjectCache *LLVMObjectCacheRef;
+
+namespace {
+
+class CallbackObjectCache : public ObjectCache {
+public:
+  CallbackObjectCache(LLVMObjectCacheNotifyCallback Notify, LLVMObjectCacheGetCallback Get,
+                      void *Opaque)
+    : Notify(Notify), Get(Get), Opaque(Opaque) {}
+
+  void notifyObjectCompiled(const Module *M, MemoryBufferRef Obj) override {
+    Notify(Opaque, wrap(M), Obj.getBufferStart(), Obj.getBufferSize());
+  }
+
+  std::unique_ptr<MemoryBuffer> getObject(const Module *M) override {
+    return std::unique_ptr<MemoryBuffer>(unwrap(Get(Opaque, wrap(M))));
+  }
+
+private:
+  LLVMObjectCacheNotifyCallback Notify;
+  LLVMObjectCacheGetCallback Get;
+  void *Opaque;
+};
+
+} // end anonymous namespace
+
+extern "C" LLVMObjectCacheRef LLVMCreateCallbackObjectCache(LLVMObjectCacheNotifyCallback Notify,
+                                                            LLVMObjectCacheGetCallback Get,
+                                                            void *Opaque) {
+  return reinterpret_cast<LLVMObjectCacheRef>(new CallbackObjectCache(Notify, Get, Opaque));
+}
+
+extern "C" void LLVMDisposeCallbackObjectCache(LLVMObjectCacheRef Cache) {
+  delete reinterpret_cast<CallbackObjectCache *>(Cache);
+}
+
+// Set the cache MCJIT looks up objects in before compiling a module, or none if Cache is
+// null.
+extern "C" void LLVMExecutionEngineSetObjectCache(LLVMExecutionEngineRef EE,
+                                                  LLVMObjectCacheRef Cache) {
+  unwrap(EE)->setObjectCache(reinterpret_cast<CallbackObjectCache *>(Cache));
+}
+
+// Module linking
+
+// Returns non-zero if the symbol `Name` linked from the source module should become
+// internal to the destination module.
+typedef int (*LLVMShouldInternalizeCallback)(void *Opaque, const char *Name);
+
+namespace {
+
+// Collects the errors the linker reports through the context's diagnostic handler.
+struct LinkDiagnosticHandler : public DiagnosticHandler {
+  std::string &Message;
+
+  LinkDiagnosticHandler(std::string &Message) : Message(Message) {}
+
+  bool handleDiagnostics(const DiagnosticInfo &DI) override {
+    if (DI.getSeverity() == DS_Error) {
+      raw_string_ostream OS(Message);
+      DiagnosticPrinterRawOStream DP(OS);
+      if (!Message.empty())
+        OS << "\n";
+      DI.print(DP);
+    }
+    return true;
+  }
+};
+
+} // end anonymous namespace
+
+// Links Src into Dest with the given Linker::Flags, taking ownership of Src.
+extern "C" LLVMBool LLVMLinkModulesWithFlags(LLVMModuleRef Dest, LLVMModuleRef Src,
+                                             unsigned Flags,
+                                             LLVMShouldInternalizeCallback Callback,
+                                             void *Opaque, char **OutMessage) {
+  Module &D = *unwrap(Dest);
+  LLVMContext &Ctx = D.getContext();
+  std::string Message;
+
+  std::unique_ptr<DiagnosticHandler> OldHandler = Ctx.getDiagnosticHandler();
+  Ctx.setDiagnosticHandler(std::make_unique<LinkDiagnosticHandler>(Message));
+
+  std::function<void(Module &, const StringSet<> &)> Internalize;
+  if (Callback) {
+    Internalize = [Callback, Opaque](Module &M, const StringSet<> &Names) {
+      for (const auto &Entry : Names) {
+        GlobalValue *GV = M.getNamedValue(Entry.getKey());
+        if (GV && !GV->isDeclaration() && Callback(Opaque, Entry.getKey().str().c_str()))
+          GV->setLinkage(GlobalValue::InternalLinkage);
+      }
+    };
+  }
+
+  bool Failed = Linker::linkModules(D, std::unique_ptr<Module>(unwrap(Src)), Flags,
+                                    Internalize);
+  Ctx.setDiagnosticHandler(std::move(OldHandler));
+
+  if (Failed && OutMessage)
+    *OutMessage = strdup(Message.c_str());
+  return Failed;
+}
+
+// Deletes the functions, global variables, aliases and ifuncs of M, leaving it empty.
+extern "C" void LLVMDropModuleContents(LLVMModuleRef M) {
+  Module *Mod = unwrap(M);
+  Mod->dropAllReferences();
+  for (GlobalValue &GV : Mod->global_values())
+    GV.removeDeadConstantUsers();
+  while (!Mod->ifunc_empty())
+    Mod->ifunc_begin()->eraseFromParent();
+  while (!Mod->alias_empty())
+    Mod->alias_begin()->eraseFromParent();
+  while (!Mod->empty())
+    Mod->begin()->eraseFromParent();
+  while (!Mod->global_empty())
+    Mod->global_begin()->eraseFromParent();
+}
+
+// Function cloning
+
+namespace {
+
+// Clones functions into another module of the same context. The globals a cloned
+// function refers to are mapped into the destination module: those with local linkage
+// are copied, since they can't be resolved from outside the source module, and the
+// others are declared.
+class FunctionCloner : public ValueMaterializer {
+public:
+  FunctionCloner(Module &M) : M(M) {}
+
+  Function *clone(const Function &F) {
+    Function *NewF = Function::Create(F.getFunctionType(), F.getLinkage(),
+                                      F.getAddressSpace(), F.getName(), &M);
+    // Registered before cloning the body, so recursive calls refer to the clone.
+    Mapped[&F] = NewF;
+
+    ValueToValueMapTy VMap;
+    Function::arg_iterator NewArg = NewF->arg_begin();
+    for (const Argument &A : F.args()) {
+      NewArg->setName(A.getName());
+      VMap[&A] = &*NewArg++;
+    }
+
+    SmallVector<ReturnInst *, 8> Returns;
+    CloneFunctionInto(NewF, &F, VMap, CloneFunctionChangeType::DifferentModule, Returns, "",
+                      nullptr, nullptr, this);
+    return NewF;
+  }
+
+  Value *materialize(Value *V) override {
+    GlobalValue *GV = dyn_cast<GlobalValue>(V);
+    if (!GV || GV->getParent() == &M)
+      return nullptr;
+
+    auto It = Mapped.find(GV);
+    if (It != Mapped.end())
+      return It->second;
+
+    if (!GV->hasLocalLinkage())
+      if (GlobalValue *Existing = M.getNamedValue(GV->getName()))
+        return Mapped[GV] = Existing;
+
+    if (Function *F = dyn_cast<Function>(GV)) {
+      if (F->hasLocalLinkage() && !F->isDeclaration())
+        return clone(*F);
+
+      Function *Decl = Function::Create(F->getFunctionType(), GlobalValue::ExternalLinkage,
+                                        F->getAddressSpace(), F->getName(), &M);
+      Decl->copyAttributesFrom(F);
+      return Mapped[GV] = Decl;
+    }
+
+    if (GlobalVariable *G = dyn_cast<GlobalVariable>(GV)) {
+      bool Copy = G->hasLocalLinkage() && G->hasInitializer();
+      GlobalVariable *NewG = new GlobalVariable(
+          M, G->getValueType(), G->isConstant(),
+          Copy ? G->getLinkage() : GlobalValue::ExternalLinkage, nullptr, G->getName(),
+          nullptr, G->getThreadLocalMode(), G->getAddressSpace());
+      NewG->copyAttributesFrom(G);
+      Mapped[GV] = NewG;
+      if (Copy) {
+        ValueToValueMapTy VMap;
+        NewG->setInitializer(MapValue(G->getInitializer(), VMap, RF_None, nullptr, this));
+      }
+      return NewG;
+    }
+
+    return nullptr;
+  }
+
+private:
+  Module &M;
+  DenseMap<const GlobalValue *, GlobalValue *> Mapped;
+};
+
+} // end anonymous namespace
+
+// Copies the definition of Fn into Dest, which must belong to the same context, with
+// declarations of the functions and globals it refers to. Returns null if Dest already
+// defines a function of the same name.
+extern "C" LLVMValueRef LLVMCloneFunctionIntoModule(LLVMValueRef Fn, LLVMModuleRef Dest) {
+  Function *F = unwrap<Function>(Fn);
+  Module *M = unwrap(Dest);
+  FunctionCloner Cloner(*M);
+
+  Function *Existing = M->getFunction(F->getName());
+  if (!Existing)
+    return wrap(Cloner.clone(*F));
+  if (!Existing->isDeclaration())
+    return nullptr;
+
+  // Replace the declaration, which functions cloned earlier may refer to.
+  Existing->setName("");
+  Function *NewF = Cloner.clone(*F);
+  Existing->replaceAllUsesWith(NewF);
+  Existing->eraseFromParent();
+  return wrap(NewF);
+}
+
+// Optimization remarks
+
+// The kinds of remarks to collect, as a bit mask.
+enum {
+  LLVMRemarkPassed = 1,
+  LLVMRemarkMissed = 2,
+  LLVMRemarkAnalysis = 4,
+};
+
+// Called with each remark collected. Kind is one of the bits above. File is null if the
+// remark has no location.
+typedef void (*LLVMRemarkCallback)(void *Opaque, unsigned Kind, const char *Pass,
+                                   const char *Name, const char *Function,
+                                   const char *Message, const char *File, unsigned Line,
+                                   unsigned Column);
+
+typedef struct LLVMOpaqueRemarkCollector *LLVMRemarkCollectorRef;
+
+namespace {
+
+// Hands the remarks of the passes matching Filter to a callback, and the other
+// diagnostics to the handler it replaced.
+struct RemarkCollector : public DiagnosticHandler {
+  Regex Filter;
+  unsigned Kinds;
+  LLVMRemarkCallback Callback;
+  void *Opaque;
+  std::unique_ptr<DiagnosticHandler> Old;
+
+  RemarkCollector(StringRef Filter, unsigned Kinds, LLVMRemarkCallback Callback, void *Opaque)
+    : Filter(Filter), Kinds(Kinds), Callback(Callback), Opaque(Opaque) {}
+
+  bool isEnabled(unsigned Kind, StringRef PassName) const {
+    return (Kinds & Kind) && Filter.match(PassName);
+  }
+
+  bool isAnalysisRemarkEnabled(StringRef PassName) const override {
+    return isEnabled(LLVMRemarkAnalysis, PassName);
+  }
+  bool isMissedOptRemarkEnabled(StringRef PassName) const override {
+    return isEnabled(LLVMRemarkMissed, PassName);
+  }
+  bool isPassedOptRemarkEnabled(StringRef PassName) const override {
+    return isEnabled(LLVMRemarkPassed, PassName);
+  }
+  bool isAnyRemarkEnabled() const override {
+    return Kinds != 0;
+  }
+
+  bool handleDiagnostics(const DiagnosticInfo &DI) override {
+    const auto *Remark = dyn_cast<DiagnosticInfoOptimizationBase>(&DI);
+    if (!Remark) {
+      // A callback set through the C API after this was installed lands here.
+      if (DiagHandlerCallback) {
+        DiagHandlerCallback(DI, DiagnosticContext);
+        return true;
+      }
+      return Old && Old->handleDiagnostics(DI);
+    }
+
+    unsigned Kind = Remark->isPassed() ? LLVMRemarkPassed
+                  : Remark->isMissed() ? LLVMRemarkMissed : LLVMRemarkAnalysis;
+    if (!isEnabled(Kind, Remark->getPassName()))
+      return true;
+
+    std::string File;
+    unsigned Line = 0, Column = 0;
+    const auto *Located = dyn_cast<DiagnosticInfoWithLocationBase>(&DI);
+    if (Located && Located->isLocationAvailable()) {
+      DiagnosticLocation Loc = Located->getLocation();
+      File = Loc.getRelativePath().str();
+      Line = Loc.getLine();
+      Column = Loc.getColumn();
+    }
+    std::string Function = Located ? Located->getFunction().getName().str() : "";
+
+    Callback(Opaque, Kind, Remark->getPassName().str().c_str(),
+             Remark->getRemarkName().str().c_str(), Function.c_str(),
+             Remark->getMsg().c_str(), Located && Located->isLocationAvailable() ? File.c_str()
+                                                                             : nullptr,
+             Line, Column);
+    return true;
+  }
+};
+
+} // end anonymous namespace
+
+// Starts collecting the remarks of the passes whose names match the regular expression
+// PassFilter. Returns null and sets OutMessage if it is invalid.
+extern "C" LLVMRemarkCollectorRef LLVMContextCollectRemarks(LLVMContextRef C,
+                                                            const char *PassFilter,
+                                                            unsigned Kinds,
+                                                            LLVMRemarkCallback Callback,
+                                                            void *Opaque,
+                                                            char **OutMessage) {
+  std::string Error;
+  std::string Anchored = std::string("^(") + PassFilter + ")$";
+  if (!Regex(Anchored).isValid(Error)) {
+    *OutMessage = strdup(Error.c_str());
+    return nullptr;
+  }
+
+  LLVMContext &Ctx = *unwrap(C);
+  auto Collector = std::make_unique<RemarkCollector>(Anchored, Kinds, Callback, Opaque);
+  Collector->Old = Ctx.getDiagnosticHandler();
+  LLVMRemarkCollectorRef Ref =
+    reinterpret_cast<LLVMRemarkCollectorRef>(static_cast<DiagnosticHandler *>(Collector.get()));
+  Ctx.setDiagnosticHandler(std::move(Collector));
+  return Ref;
+}
+
+// Stops collecting remarks, restoring the handler replaced by R if it is still installed.
+extern "C" void LLVMContextStopCollectingRemarks(LLVMContextRef C, LLVMRemarkCollectorRef R) {
+  LLVMContext &Ctx = *unwrap(C);
+  std::unique_ptr<DiagnosticHandler> Current = Ctx.getDiagnosticHandler();
+  if (Current.get() == reinterpret_cast<DiagnosticHandler *>(R))
+    Ctx.setDiagnosticHandler(std::move(static_cast<RemarkCollector *>(Current.get())->Old));
+  else
+    Ctx.setDiagnosticHandler(std::move(Current));
+}
+
+// Fatal errors
+
+// Called with the reason of a fatal error. LLVM exits the process once it returns, so
+// it may unwind instead to recover from the error.
+typedef void (*LLVMUnwindingFatalErrorCallback)(const char *Reason);
+
+static void unwindingFatalErrorHandler(void *Data, const char *Reason, bool) {
+  reinterpret_cast<LLVMUnwindingFatalErrorCallback>(Data)(Reason);
+}
+
+extern "C" void LLVMInstallUnwindingFatalErrorHandler(LLVMUnwindingFatalErrorCallback Callback) {
+  remove_fatal_error_handler();
+  install_fatal_error_handler(unwindingFatalErrorHandler, reinterpret_cast<void *>(Callback));
+}
+
+// Custom passes
+
+// Each returns true if the pass changed the IR.
+typedef LLVMBool (*LLVMRunFunctionPassCallback)(void *Opaque, LLVMValueRef F);
+typedef LLVMBool (*LLVMRunModulePassCallback)(void *Opaque, LLVMModuleRef M);
+
+// The extension points of the default pipelines, as in pass::ExtensionPoint.
+enum {
+  LLVMEPEarlyAsPossible = 0,
+  LLVMEPModuleOptimizerEarly = 1,
+  LLVMEPScalarOptimizerLate = 2,
+  LLVMEPOptimizerLast = 3,
+  LLVMEPVectorizerStart = 4,
+  LLVMEPEnabledOnOptLevel0 = 5,
+  LLVMEPPeephole = 6,
+  LLVMEPCGSCCOptimizerLate = 7,
+};
+
+// A pass calling back into Rust. Exactly one of RunFunction and RunModule is set, and
+// RunModule only at the extension points of the whole module.
+typedef struct {
+  unsigned EP;
+  LLVMRunFunctionPassCallback RunFunction;
+  LLVMRunModulePassCallback RunModule;
+  void *Opaque;
+} LLVMCustomPass;
+
+namespace {
+
+struct CallbackFunctionPass : public PassInfoMixin<CallbackFunctionPass> {
+  LLVMRunFunctionPassCallback Run;
+  void *Opaque;
+
+  CallbackFunctionPass(LLVMRunFunctionPassCallback Run, void *Opaque)
+    : Run(Run), Opaque(Opaque) {}
+
+  PreservedAnalyses run(Function &F, FunctionAnalysisManager &) {
+    return Run(Opaque, wrap(&F)) ? PreservedAnalyses::none() : PreservedAnalyses::all();
+  }
+};
+
+struct CallbackModulePass : public PassInfoMixin<CallbackModulePass> {
+  LLVMRunModulePassCallback Run;
+  void *Opaque;
+
+  CallbackModulePass(LLVMRunModulePassCallback Run, void *Opaque)
+    : Run(Run), Opaque(Opaque) {}
+
+  PreservedAnalyses run(Module &M, ModuleAnalysisManager &) {
+    return Run(Opaque, wrap(&M)) ? PreservedAnalyses::none() : PreservedAnalyses::all();
+  }
+};
+
+void addToModule(ModulePassManager &MPM, const LLVMCustomPass &P) {
+  if (P.RunModule)
+    MPM.addPass(CallbackModulePass(P.RunModule, P.Opaque));
+  else
+    MPM.addPass(createModuleToFunctionPassAdaptor(CallbackFunctionPass(P.RunFunction,
+                                                                       P.Opaque)));
+}
+
+// Registers P at its extension point of PB. Only the passes at EarlyAsPossible and
+// EnabledOnOptLevel0 run at O0, though the O0 pipeline invokes all extension points.
+// The module extension points take a ThinOrFullLTOPhase since LLVM 20, hence the
+// variadic lambdas.
+void registerCustomPass(PassBuilder &PB, LLVMCustomPass P) {
+  switch (P.EP) {
+  case LLVMEPEarlyAsPossible:
+    PB.registerPipelineStartEPCallback([P](ModulePassManager &MPM, OptimizationLevel) {
+      addToModule(MPM, P);
+    });
+    break;
+  case LLVMEPModuleOptimizerEarly:
+    PB.registerOptimizerEarlyEPCallback([P](ModulePassManager &MPM, OptimizationLevel L,
+                                            auto...) {
+      if (L != OptimizationLevel::O0)
+        addToModule(MPM, P);
+    });
+    break;
+  case LLVMEPOptimizerLast:
+  case LLVMEPEnabledOnOptLevel0:
+    PB.registerOptimizerLastEPCallback([P](ModulePassManager &MPM, OptimizationLevel L,
+                                           auto...) {
+      if ((L == OptimizationLevel::O0) == (P.EP == LLVMEPEnabledOnOptLevel0))
+        addToModule(MPM, P);
+    });
+    break;
+  case LLVMEPScalarOptimizerLate:
+    PB.registerScalarOptimizerLateEPCallback([P](FunctionPassManager &FPM,
+                                                 OptimizationLevel L) {
+      if (L != OptimizationLevel::O0)
+        FPM.addPass(CallbackFunctionPass(P.RunFunction, P.Opaque));
+    });
+    break;
+  case LLVMEPVectorizerStart:
+    PB.registerVectorizerStartEPCallback([P](FunctionPassManager &FPM, OptimizationLevel L) {
+      if (L != OptimizationLevel::O0)
+        FPM.addPass(CallbackFunctionPass(P.RunFunction, P.Opaque));
+    });
+    break;
+  case LLVMEPPeephole:
+    PB.registerPeepholeEPCallback([P](FunctionPassManager &FPM, OptimizationLevel L) {
+      if (L != OptimizationLevel::O0)
+        FPM.addPass(CallbackFunctionPass(P.RunFunction, P.Opaque));
+    });
+    break;
+  case LLVMEPCGSCCOptimizerLate:
+    PB.registerCGSCCOptimizerLateEPCallback([P](CGSCCPassManager &CGPM, OptimizationLevel L) {
+      if (L != OptimizationLevel::O0)
+        CGPM.addPass(createCGSCCToFunctionPassAdaptor(CallbackFunctionPass(P.RunFunction,
+                                                                           P.Opaque)));
+    });
+    break;
+  }
+}
+
+} // end anonymous namespace
+
+// Runs the pipeline Passes over M like LLVMRunPasses, with the custom passes given added
+// at their extension points. TM may be null. The Opaque pointers of the passes must
+// outlive the call.
+extern "C" LLVMErrorRef LLVMRunPassesWithCustomPasses(LLVMModuleRef M,
+                                                      const char *Passes,
+                                                      LLVMTargetMachineRef TM,
+                                                      const LLVMCustomPass *Custom,
+                                                      unsigned NumCustom) {
+  Module *Mod = unwrap(M);
+  PassInstrumentationCallbacks PIC;
+  PassBuilder PB(reinterpret_cast<TargetMachine *>(TM), PipelineTuningOptions(),
+                 std::nullopt, &PIC);
+  for (unsigned I = 0; I < NumCustom; ++I)
+    registerCustomPass(PB, Custom[I]);
+
+  LoopAnalysisManager LAM;
+  FunctionAnalysisManager FAM;
+  CGSCCAnalysisManager CGAM;
+  ModuleAnalysisManager MAM;
+  PB.registerLoopAnalyses(LAM);
+  PB.registerFunctionAnalyses(FAM);
+  PB.registerCGSCCAnalyses(CGAM);
+  PB.registerModuleAnalyses(MAM);
+  PB.crossRegisterProxies(LAM, FAM, CGAM, MAM);
+
+  // Times the passes if LLVMEnableCompileStatistics asked for it, as LLVMRunPasses does.
+  StandardInstrumentations SI(Mod->getContext(), false);
+  SI.registerCallbacks(PIC, &MAM);
+
+  ModulePassManager MPM;
+  if (Error Err = PB.parsePassPipeline(MPM, Passes))
+    return wrap(std::move(Err));
+  MPM.run(*Mod, MAM);
+  return wrap(Error::success());
+}
+
+// Compile statistics
+
+// Starts timing each pass, whether run by LLVMRunPasses or by the legacy pass manager of
+// the code generator, and counting the statistics of LLVM. Both are process-wide, and
+// statistics can't be turned off again.
+extern "C" void LLVMEnableCompileStatistics() {
+  TimePassesIsEnabled = true;
+  EnableStatistics(false);
+}
+
+// Returns the pass timings and statistics collected so far as JSON, one
+// "name": value entry per line, and resets them.
+extern "C" char *LLVMTakeCompileStatistics() {
+  std::string Out;
+  raw_string_ostream OS(Out);
+  PrintStatisticsJSON(OS);
+  OS.flush();
+  ResetStatistics();
+  TimerGroup::clearAll();
+  return strdup(Out.c_str());
+}
+
 extern "C" uint32_t LLVMVersionMajor() {
   return LLVM_VERSION_MAJOR;
 }